@@ -0,0 +1,15 @@
+// Parses arbitrary text, and compiles what parses, which must end in errors at worst,
+// never in a panic. Run with `cargo fuzz run parse`; a crash found goes into
+// tests/programs once `cargo fuzz tmin` has cut it down.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vit::parser::Parser;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        if let Ok(program) = Parser::new().parse(source) {
+            let _ = vit::vit::build(program);
+        }
+    }
+});
@@ -1,45 +1,142 @@
+//! The syntax tree that `Parser::parse` returns: a program is a list of statements, each
+//! with the span of the source it was parsed from.
+
 use core::fmt;
 
+mod print;
+mod visit;
+
+pub use print::print;
+pub use visit::{
+    walk_expr, walk_expr_mut, walk_statement, walk_statement_mut, Visitor, VisitorMut,
+};
+
+/// A name as written in the source. Fields of records are paths, as in `line.start.x`,
+/// and the items of imported modules are qualified, as in `util::MAX`.
 pub type Identifier = String;
 
-#[derive(Debug)]
+/// The bytes of the source from `start` up to `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The number of the line where the span starts, counting from 1.
+    pub fn line(&self, source: &str) -> usize {
+        source[..self.start.min(source.len())].matches('\n').count() + 1
+    }
+}
+
+/// A node together with the span of the source it was parsed from.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+// Shows only the node, so that dumps of the AST stay readable.
+impl<T: fmt::Debug> fmt::Debug for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+/// A statement of a program. Blocks hold their statements with their spans.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Statement {
-    Declaration(Identifier, Option<Box<Expr>>),
+    /// `let name: type = value;`, where the type and the value are optional.
+    Declaration(Identifier, Option<Identifier>, Option<Box<Expr>>),
+    /// `const NAME = value;`
+    Constant(Identifier, Box<Expr>),
+    /// `struct Name { field, field: type }`
+    Struct(Identifier, Vec<(Identifier, Option<Identifier>)>),
+    /// `import "path";`, with the path without its quotes.
+    Import(String),
+    /// `name = value;`
     Assignment(Identifier, Box<Expr>),
+    /// `read name;`
     Read(Identifier),
-    If(Box<Expr>, Vec<Statement>, Option<Vec<Statement>>),
-    Until(Box<Expr>, Vec<Statement>),
+    /// `if predicate { ... } else { ... }`, where the else block is optional.
+    If(
+        Box<Expr>,
+        Vec<Spanned<Statement>>,
+        Option<Vec<Spanned<Statement>>>,
+    ),
+    /// `do { ... } until predicate;`
+    Until(Box<Expr>, Vec<Spanned<Statement>>),
+    /// `write 'text';`, with the literal as written, quotes and escapes included.
     WriteLiteral(String),
+    /// `write name;`
     WriteId(Identifier),
-    Loop(Vec<Statement>),
+    /// `loop { ... }`
+    Loop(Vec<Spanned<Statement>>),
+    /// `break;`
     Break,
 }
 
+/// An expression. Its `Debug` form writes it with every operation in parentheses.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Expr {
+    /// A number as written, negative if the flag is set. It holds an `Integer` or a `Float`.
     Number(bool, Box<Expr>),
+    /// An integer literal.
     Integer(i32),
-    Float(f32),
+    /// A float literal.
+    Float(f64),
+    /// A variable or constant.
     Id(Identifier),
+    /// A string literal, without its quotes.
+    Str(String),
+    /// A call to a builtin function, as in `max(a, b)`.
+    Call(Identifier, Vec<Expr>),
+    /// A binary operation. The parser uses it for comparisons and connectives too.
     Op(Box<Expr>, Opcode, Box<Expr>),
+    /// A comparison. The parser never produces it, but the compiler takes it like `Op`.
     Predicate(Box<Expr>, Opcode, Box<Expr>),
 }
 
-#[derive(PartialEq)]
+/// The operator of an `Op` or a `Predicate`, displayed as written in the source.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Opcode {
+    /// `+`, which also joins strings.
     Add,
+    /// `-`
     Sub,
+    /// `*`
     Mul,
+    /// `/`
     Div,
+    /// `^`
     Exp,
+    /// `%`
     Mod,
+    /// `and`
     And,
+    /// `or`
     Or,
+    /// `!`, which the parser never produces.
     Not,
+    /// `==`
     Eq,
+    /// `!=`
     Neq,
+    /// `>`
     Grt,
+    /// `<`
     Let,
+    /// `>=`
     Geq,
+    /// `<=`
     Leq,
 }
 
@@ -69,23 +166,6 @@ impl fmt::Display for Opcode {
     }
 }
 
-impl fmt::Display for Expr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Expr::Number(sign, num) => format!("{}{}", if *sign { "-" } else { "" }, num),
-                Expr::Integer(n) => format!("{n}"),
-                Expr::Float(n) => format!("{n}"),
-                Expr::Op(l, op, r) => format!("({} {} {})", *l, op, *r),
-                Expr::Predicate(l, op, r) => format!("({} {} {})", *l, op, *r),
-                Expr::Id(id) => id.clone(),
-            }
-        )
-    }
-}
-
 impl fmt::Debug for Opcode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -118,12 +198,20 @@ impl fmt::Debug for Expr {
             f,
             "{}",
             match self {
-                Expr::Number(sign, num) => format!("{}{}", if *sign { "-" } else { "" }, num),
+                Expr::Number(sign, num) => format!("{}{num:?}", if *sign { "-" } else { "" }),
                 Expr::Integer(n) => format!("{n}"),
                 Expr::Float(n) => format!("{n}"),
-                Expr::Op(l, op, r) => format!("({} {} {})", *l, op, *r),
-                Expr::Predicate(l, op, r) => format!("({} {} {})", *l, op, *r),
+                Expr::Op(l, op, r) => format!("({l:?} {op} {r:?})"),
+                Expr::Predicate(l, op, r) => format!("({l:?} {op} {r:?})"),
                 Expr::Id(id) => id.clone(),
+                Expr::Str(s) => format!("'{s}'"),
+                Expr::Call(id, args) => format!(
+                    "{id}({})",
+                    args.iter()
+                        .map(|arg| format!("{arg:?}"))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
             }
         )
     }
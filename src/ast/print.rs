@@ -0,0 +1,349 @@
+use core::fmt;
+
+use super::{Expr, Opcode, Spanned, Statement};
+
+const INDENT: &str = "    ";
+
+/// Writes a program back as source, a statement per line, in the layout of `vit fmt`.
+/// Parsing what it writes gives the same statements back, spans aside, for any program
+/// the parser can give. Others print as the closest source: a bare `Integer` or `Float`
+/// prints like the `Number` the parser would wrap it in, for example.
+pub fn print(program: &[Spanned<Statement>]) -> String {
+    program
+        .iter()
+        .map(|statement| format!("{}\n", statement.node))
+        .collect()
+}
+
+// Statements with blocks take several lines, indented by four spaces per level.
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_statement(f, self, 0)
+    }
+}
+
+fn write_statement(f: &mut fmt::Formatter, statement: &Statement, indent: usize) -> fmt::Result {
+    match statement {
+        Statement::Declaration(id, annotation, value) => {
+            write!(f, "let {id}")?;
+            if let Some(annotation) = annotation {
+                write!(f, ": {annotation}")?;
+            }
+            if let Some(value) = value {
+                write!(f, " = {value}")?;
+            }
+            write!(f, ";")
+        }
+        Statement::Constant(id, value) => write!(f, "const {id} = {value};"),
+        Statement::Struct(id, fields) if fields.is_empty() => write!(f, "struct {id} {{}}"),
+        Statement::Struct(id, fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(field, annotation)| match annotation {
+                    Some(annotation) => format!("{field}: {annotation}"),
+                    None => field.clone(),
+                })
+                .collect();
+            write!(f, "struct {id} {{ {} }}", fields.join(", "))
+        }
+        Statement::Import(path) => write!(f, "import \"{path}\";"),
+        Statement::Assignment(id, value) => write!(f, "{id} = {value};"),
+        Statement::Read(id) => write!(f, "read {id};"),
+        Statement::WriteLiteral(literal) => write!(f, "write {literal};"),
+        Statement::WriteId(id) => write!(f, "write {id};"),
+        Statement::Break => write!(f, "break;"),
+        Statement::If(predicate, then, otherwise) => {
+            write!(f, "if {predicate} ")?;
+            write_block(f, then, indent)?;
+            if let Some(otherwise) = otherwise {
+                write!(f, " else ")?;
+                write_block(f, otherwise, indent)?;
+            }
+            Ok(())
+        }
+        Statement::Until(predicate, body) => {
+            write!(f, "do ")?;
+            write_block(f, body, indent)?;
+            write!(f, " until {predicate};")
+        }
+        Statement::Loop(body) => {
+            write!(f, "loop ")?;
+            write_block(f, body, indent)
+        }
+    }
+}
+
+// Writes the braces of a block and the statements between them, but no line break after.
+fn write_block(
+    f: &mut fmt::Formatter,
+    statements: &[Spanned<Statement>],
+    indent: usize,
+) -> fmt::Result {
+    if statements.is_empty() {
+        return write!(f, "{{}}");
+    }
+    writeln!(f, "{{")?;
+    for statement in statements {
+        write!(f, "{}", INDENT.repeat(indent + 1))?;
+        write_statement(f, &statement.node, indent + 1)?;
+        writeln!(f)?;
+    }
+    write!(f, "{}}}", INDENT.repeat(indent))
+}
+
+// Writes only the parentheses the grammar needs, unlike `Debug`, which writes them all.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_operand(f, self, 0)
+    }
+}
+
+// How tightly each operator binds, following the grammar: `and` and `or` are the
+// loosest, then comparisons, terms, factors and powers.
+fn precedence(op: Opcode) -> u8 {
+    match op {
+        Opcode::And | Opcode::Or => 1,
+        Opcode::Eq | Opcode::Neq | Opcode::Grt | Opcode::Let | Opcode::Geq | Opcode::Leq => 2,
+        Opcode::Not => 2,
+        Opcode::Add | Opcode::Sub => 3,
+        Opcode::Mul | Opcode::Div | Opcode::Mod => 4,
+        Opcode::Exp => 5,
+    }
+}
+
+// Writes `expr` where the grammar expects an operand that binds at least as tightly as
+// `precedence`, wrapping it in parentheses if it doesn't.
+fn write_operand(f: &mut fmt::Formatter, expr: &Expr, precedence: u8) -> fmt::Result {
+    match expr {
+        Expr::Op(l, op, r) | Expr::Predicate(l, op, r) => {
+            let own = self::precedence(*op);
+            // Powers group to the right, comparisons not at all and the rest to the left.
+            let (left, right) = match own {
+                5 => (own + 1, own),
+                2 => (own + 1, own + 1),
+                _ => (own, own + 1),
+            };
+            let wrap = own < precedence;
+            if wrap {
+                write!(f, "(")?;
+            }
+            write_operand(f, l, left)?;
+            write!(f, " {op} ")?;
+            write_operand(f, r, right)?;
+            if wrap {
+                write!(f, ")")?;
+            }
+            Ok(())
+        }
+        Expr::Number(negative, number) => {
+            write!(f, "{}{number}", if *negative { "-" } else { "" })
+        }
+        Expr::Integer(n) => write!(f, "{n}"),
+        // Floats keep their point, so that they don't read back as integers.
+        Expr::Float(n) if n.fract() == 0.0 => write!(f, "{n:.1}"),
+        Expr::Float(n) => write!(f, "{n}"),
+        Expr::Id(id) => write!(f, "{id}"),
+        Expr::Str(s) => write!(f, "'{s}'"),
+        Expr::Call(id, args) => {
+            write!(f, "{id}(")?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{arg}")?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, option, prelude::*};
+
+    use super::*;
+    use crate::{
+        ast::{walk_statement_mut, Span, VisitorMut},
+        parser::Parser,
+    };
+
+    #[test]
+    fn expressions() {
+        let cases = [
+            ("(1 + 2) * (3 - (4 - 5))", "(1 + 2) * (3 - (4 - 5))"),
+            ("((1 + 2) + 3) * 4", "(1 + 2 + 3) * 4"),
+            ("(2 ^ 3) ^ 2 + 2 ^ (3 ^ 2)", "(2 ^ 3) ^ 2 + 2 ^ 3 ^ 2"),
+            ("(a * b) % (c / d)", "a * b % (c / d)"),
+            ("max((a), (-1.50), 3.0)", "max(a, -1.5, 3.0)"),
+        ];
+        for (source, printed) in cases {
+            let expr = crate::parser::vit_grammar::ExprParser::new()
+                .parse(source)
+                .unwrap();
+            assert_eq!(expr.to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn statements() {
+        let source = "import \"lib/m.vit\"; struct P {x,y:float} struct Q {} let p: P;
+            if (a == 1 and (b == 2)) or (c < d + 1 and (e > f or g != h)) { loop { break; } }
+            else { do { read p.x; } until p.x > 0; } if a != 0 {} write 'a'; write a;";
+        assert_eq!(
+            print(&Parser::new().parse(source).unwrap()),
+            "import \"lib/m.vit\";
+struct P { x, y: float }
+struct Q {}
+let p: P;
+if a == 1 and b == 2 or (c < d + 1 and (e > f or g != h)) {
+    loop {
+        break;
+    }
+} else {
+    do {
+        read p.x;
+    } until p.x > 0;
+}
+if a != 0 {}
+write 'a';
+write a;
+"
+        );
+    }
+
+    // Clears the spans, which printing doesn't keep.
+    struct Unspan;
+
+    impl VisitorMut for Unspan {
+        fn visit_statement_mut(&mut self, statement: &mut Spanned<Statement>) {
+            statement.span = Span::default();
+            walk_statement_mut(self, statement);
+        }
+    }
+
+    const KEYWORDS: [&str; 14] = [
+        "let", "const", "struct", "import", "read", "write", "if", "else", "loop", "do", "until",
+        "break", "and", "or",
+    ];
+
+    fn id() -> impl Strategy<Value = String> {
+        "[a-zA-Z][a-zA-Z0-9_]{0,4}".prop_filter("keywords aren't names", |id| {
+            !KEYWORDS.contains(&id.as_str())
+        })
+    }
+
+    fn path() -> impl Strategy<Value = String> {
+        (option::of((id(), "::")), vec(id(), 1..3)).prop_map(|(module, fields)| {
+            let (module, separator) = module.unwrap_or_default();
+            format!("{module}{separator}{}", fields.join("."))
+        })
+    }
+
+    // Expressions as the parser gives them, with numbers wrapped in `Number`.
+    fn expr() -> impl Strategy<Value = Expr> {
+        let number = prop_oneof![
+            (0..=i32::MAX).prop_map(Expr::Integer),
+            (0..100_000u32).prop_map(|n| Expr::Float(n as f64 / 8.0)),
+        ];
+        let leaf = prop_oneof![
+            (any::<bool>(), number).prop_map(|(negative, n)| Expr::Number(negative, Box::new(n))),
+            path().prop_map(Expr::Id),
+            "[a-z ,.!]*".prop_map(Expr::Str),
+        ];
+        leaf.prop_recursive(4, 24, 3, |inner| {
+            let op = prop_oneof![
+                Just(Opcode::Add),
+                Just(Opcode::Sub),
+                Just(Opcode::Mul),
+                Just(Opcode::Div),
+                Just(Opcode::Mod),
+                Just(Opcode::Exp),
+            ];
+            prop_oneof![
+                (inner.clone(), op, inner.clone()).prop_map(|(l, op, r)| Expr::Op(
+                    Box::new(l),
+                    op,
+                    Box::new(r)
+                )),
+                (id(), vec(inner, 0..3)).prop_map(|(id, args)| Expr::Call(id, args)),
+            ]
+        })
+    }
+
+    fn predicate() -> impl Strategy<Value = Expr> {
+        let op = prop_oneof![
+            Just(Opcode::Eq),
+            Just(Opcode::Neq),
+            Just(Opcode::Grt),
+            Just(Opcode::Let),
+            Just(Opcode::Geq),
+            Just(Opcode::Leq),
+        ];
+        let comparison =
+            (expr(), op, expr()).prop_map(|(l, op, r)| Expr::Op(Box::new(l), op, Box::new(r)));
+        comparison.prop_recursive(3, 8, 2, |inner| {
+            let connective = prop_oneof![Just(Opcode::And), Just(Opcode::Or)];
+            (inner.clone(), connective, inner)
+                .prop_map(|(l, op, r)| Expr::Op(Box::new(l), op, Box::new(r)))
+        })
+    }
+
+    fn spanned(node: Statement) -> Spanned<Statement> {
+        Spanned {
+            node,
+            span: Span::default(),
+        }
+    }
+
+    fn statement() -> impl Strategy<Value = Spanned<Statement>> {
+        let field = (id(), option::of(id()));
+        let simple = prop_oneof![
+            (id(), option::of(id()), option::of(expr()))
+                .prop_map(|(id, ty, value)| Statement::Declaration(id, ty, value.map(Box::new))),
+            (id(), expr()).prop_map(|(id, value)| Statement::Constant(id, Box::new(value))),
+            (id(), vec(field, 0..3)).prop_map(|(id, fields)| Statement::Struct(id, fields)),
+            (path(), expr()).prop_map(|(id, value)| Statement::Assignment(id, Box::new(value))),
+            path().prop_map(Statement::Read),
+            "[a-z ,.!]*".prop_map(|text| Statement::WriteLiteral(format!("'{text}'"))),
+            path().prop_map(Statement::WriteId),
+            Just(Statement::Break),
+        ];
+        simple.prop_map(spanned).prop_recursive(3, 16, 3, |inner| {
+            let block = vec(inner, 0..3);
+            prop_oneof![
+                (predicate(), block.clone(), option::of(block.clone()))
+                    .prop_map(|(p, then, otherwise)| Statement::If(Box::new(p), then, otherwise)),
+                (predicate(), block.clone())
+                    .prop_map(|(p, body)| Statement::Until(Box::new(p), body)),
+                block.prop_map(Statement::Loop),
+            ]
+            .prop_map(spanned)
+        })
+    }
+
+    fn program() -> impl Strategy<Value = Vec<Spanned<Statement>>> {
+        let import = "[a-z/]{1,8}\\.vit".prop_map(|path| spanned(Statement::Import(path)));
+        (vec(import, 0..2), vec(statement(), 0..6)).prop_map(|(mut imports, statements)| {
+            imports.extend(statements);
+            imports
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn printed_programs_parse_back(program in program()) {
+            let source = print(&program);
+            let mut parsed = Parser::new().parse(&source).map_err(TestCaseError::fail)?;
+            for statement in &mut parsed {
+                Unspan.visit_statement_mut(statement);
+            }
+            prop_assert_eq!(parsed, program, "{}", source);
+        }
+
+        #[test]
+        fn printed_expressions_parse_back(expr in expr()) {
+            let parser = crate::parser::vit_grammar::ExprParser::new();
+            prop_assert_eq!(*parser.parse(&expr.to_string()).unwrap(), expr);
+        }
+    }
+}
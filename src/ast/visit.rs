@@ -0,0 +1,209 @@
+use super::{Expr, Spanned, Statement};
+
+/// Goes through a program, calling `visit_statement` for every statement and `visit_expr`
+/// for every expression, nested ones included. By default each method only walks into
+/// what it's given, so a visitor overrides the methods it cares about and calls the
+/// matching `walk_` function where it wants to keep going deeper.
+pub trait Visitor {
+    /// Called for every statement. Walks into it by default.
+    fn visit_statement(&mut self, statement: &Spanned<Statement>) {
+        walk_statement(self, statement);
+    }
+
+    /// Called for every expression. Walks into it by default.
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+/// Like `Visitor`, but the visitor can change what it visits.
+pub trait VisitorMut {
+    /// Called for every statement. Walks into it by default.
+    fn visit_statement_mut(&mut self, statement: &mut Spanned<Statement>) {
+        walk_statement_mut(self, statement);
+    }
+
+    /// Called for every expression. Walks into it by default.
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+/// Visits the expressions of a statement and the statements of its blocks, in the order
+/// they appear in the source.
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Spanned<Statement>) {
+    match &statement.node {
+        Statement::Declaration(_, _, value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Statement::Constant(_, value) | Statement::Assignment(_, value) => {
+            visitor.visit_expr(value)
+        }
+        Statement::If(predicate, then, otherwise) => {
+            visitor.visit_expr(predicate);
+            for statement in then.iter().chain(otherwise.iter().flatten()) {
+                visitor.visit_statement(statement);
+            }
+        }
+        Statement::Until(predicate, body) => {
+            for statement in body {
+                visitor.visit_statement(statement);
+            }
+            visitor.visit_expr(predicate);
+        }
+        Statement::Loop(body) => {
+            for statement in body {
+                visitor.visit_statement(statement);
+            }
+        }
+        Statement::Struct(..)
+        | Statement::Import(_)
+        | Statement::Read(_)
+        | Statement::WriteLiteral(_)
+        | Statement::WriteId(_)
+        | Statement::Break => (),
+    }
+}
+
+/// Visits the operands of an expression, left to right.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Number(_, number) => visitor.visit_expr(number),
+        Expr::Call(_, args) => {
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        Expr::Op(l, _, r) | Expr::Predicate(l, _, r) => {
+            visitor.visit_expr(l);
+            visitor.visit_expr(r);
+        }
+        Expr::Integer(_) | Expr::Float(_) | Expr::Id(_) | Expr::Str(_) => (),
+    }
+}
+
+/// Like `walk_statement`, for a `VisitorMut`.
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    statement: &mut Spanned<Statement>,
+) {
+    match &mut statement.node {
+        Statement::Declaration(_, _, value) => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Statement::Constant(_, value) | Statement::Assignment(_, value) => {
+            visitor.visit_expr_mut(value)
+        }
+        Statement::If(predicate, then, otherwise) => {
+            visitor.visit_expr_mut(predicate);
+            for statement in then.iter_mut().chain(otherwise.iter_mut().flatten()) {
+                visitor.visit_statement_mut(statement);
+            }
+        }
+        Statement::Until(predicate, body) => {
+            for statement in body {
+                visitor.visit_statement_mut(statement);
+            }
+            visitor.visit_expr_mut(predicate);
+        }
+        Statement::Loop(body) => {
+            for statement in body {
+                visitor.visit_statement_mut(statement);
+            }
+        }
+        Statement::Struct(..)
+        | Statement::Import(_)
+        | Statement::Read(_)
+        | Statement::WriteLiteral(_)
+        | Statement::WriteId(_)
+        | Statement::Break => (),
+    }
+}
+
+/// Like `walk_expr`, for a `VisitorMut`.
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Number(_, number) => visitor.visit_expr_mut(number),
+        Expr::Call(_, args) => {
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+        Expr::Op(l, _, r) | Expr::Predicate(l, _, r) => {
+            visitor.visit_expr_mut(l);
+            visitor.visit_expr_mut(r);
+        }
+        Expr::Integer(_) | Expr::Float(_) | Expr::Id(_) | Expr::Str(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    // Counts the statements, and the uses of each name in expressions.
+    #[derive(Default)]
+    struct Uses {
+        statements: usize,
+        names: Vec<String>,
+    }
+
+    impl Visitor for Uses {
+        fn visit_statement(&mut self, statement: &Spanned<Statement>) {
+            self.statements += 1;
+            walk_statement(self, statement);
+        }
+
+        /// Called for every expression. Walks into it by default.
+        fn visit_expr(&mut self, expr: &Expr) {
+            match expr {
+                Expr::Id(id) => self.names.push(id.clone()),
+                _ => walk_expr(self, expr),
+            }
+        }
+    }
+
+    // Renames a variable wherever an expression uses it.
+    struct Rename(&'static str, &'static str);
+
+    impl VisitorMut for Rename {
+        /// Called for every expression. Walks into it by default.
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            match expr {
+                Expr::Id(id) if id == self.0 => *id = self.1.to_string(),
+                _ => walk_expr_mut(self, expr),
+            }
+        }
+    }
+
+    const PROGRAM: &str = "let a = 1;
+if a > 0 { loop { a = a - max(a, b); break; } } else { do { read a; } until a == c; }";
+
+    #[test]
+    fn visitor() {
+        let mut uses = Uses::default();
+        for statement in &Parser::new().parse(PROGRAM).unwrap() {
+            uses.visit_statement(statement);
+        }
+        assert_eq!(uses.statements, 7);
+        assert_eq!(uses.names, ["a", "a", "a", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn visitor_mut() {
+        let mut program = Parser::new().parse(PROGRAM).unwrap();
+        for statement in &mut program {
+            Rename("a", "z").visit_statement_mut(statement);
+        }
+        // Expressions change, but the statements that name variables don't.
+        assert_eq!(
+            format!("{program:?}"),
+            "[Declaration(\"a\", None, Some(1)), If((z > 0), [Loop([Assignment(\"a\", (z - max(z, b))), Break])], Some([Until((z == c), [Read(\"a\")])]))]"
+        );
+    }
+}
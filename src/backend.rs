@@ -0,0 +1,218 @@
+// Backends that translate the IR lowered by `vit` into other languages. Programs built by
+// any of them must behave like the p-code run by `vm`.
+
+pub mod c;
+pub mod wat;
+pub mod x86_64;
+
+#[cfg(test)]
+mod programs;
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt, fs, path::Path};
+
+    use super::programs;
+    use crate::{
+        modules::{self, Module},
+        vit,
+        vm::Machine,
+    };
+
+    // A program that every backend runs with the same input as the vm.
+    pub(super) struct Case {
+        pub name: String,
+        pub modules: Vec<Module>,
+        pub input: String,
+        generated: Option<String>, // The source, for a generated program.
+    }
+
+    impl Case {
+        fn source(name: &str, source: &str, input: &str) -> Case {
+            let modules = modules::load(Path::new("main.vit"), |_| Ok(source.to_string())).unwrap();
+            Case {
+                name: name.to_string(),
+                modules,
+                input: input.to_string(),
+                generated: None,
+            }
+        }
+
+        fn example(name: &str, input: &str) -> Case {
+            let path = format!("examples/{name}.vit");
+            let modules = modules::load(Path::new(&path), |path| fs::read_to_string(path));
+            Case {
+                name: name.to_string(),
+                modules: modules.unwrap(),
+                input: input.to_string(),
+                generated: None,
+            }
+        }
+
+        fn generated(seed: u64) -> Case {
+            let (source, input) = programs::program(seed);
+            Case {
+                generated: Some(source.clone()),
+                ..Case::source(&format!("generated_{seed}"), &source, &input)
+            }
+        }
+
+        // The output of the vm, and the exit code of `vit run`: 0 when it finished without
+        // errors.
+        pub fn expected(&self) -> (String, i32) {
+            let mut output = vec![];
+            let result = Machine::load(&vit::link(self.modules.clone()).unwrap())
+                .unwrap()
+                .run(&mut self.input.as_bytes(), &mut output);
+
+            let code = result.map_or_else(|error| error.kind.exit_code(), |()| 0);
+            (String::from_utf8(output).unwrap(), code)
+        }
+    }
+
+    // Names the case in a failed assertion, showing a generated program as it can't be
+    // found anywhere else.
+    impl fmt::Display for Case {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match &self.generated {
+                Some(source) => write!(f, "{}, with input {:?}:\n{source}", self.name, self.input),
+                None => write!(f, "{}", self.name),
+            }
+        }
+    }
+
+    // Writes the value of each expression, separated by spaces.
+    fn values(expressions: &[&str]) -> String {
+        expressions
+            .iter()
+            .enumerate()
+            .map(|(i, expr)| format!("let v{i} = {expr}; write v{i}; write ' ';\n"))
+            .collect()
+    }
+
+    // Writes whether each condition holds.
+    fn conditions(conditions: &[&str]) -> String {
+        conditions
+            .iter()
+            .map(|condition| format!("if {condition} {{ write 'T'; }} else {{ write 'F'; }}\n"))
+            .collect()
+    }
+
+    pub(super) fn cases() -> Vec<Case> {
+        let integers = values(&[
+            "2147483647 + 1",
+            "7 / -2",
+            "-7 % 3",
+            "2 ^ 31",
+            "3 ^ 40",
+            "2 ^ -1",
+            "-1 ^ -3",
+            "-2147483647 - 1",
+            "(-2147483647 - 1) / -1",
+        ]);
+        let floats = values(&[
+            "0.1 + 0.2",
+            "7.5 % 2",
+            "-7.5 % 2",
+            "1 / 3.0",
+            "2.0 ^ 0.5",
+            "10.0 ^ 300",
+            "0 - 10.0 ^ 15",
+            "10.0 ^ 20",
+            "0 - 2.0 ^ 70",
+            "2.0 ^ 53 - 1",
+            "12345678901234.5 + 0",
+            "10.0 ^ 14 + 1 / 3.0",
+            "0.9999996",
+            "0.0000001",
+            "-0.0000004",
+            "1.0 / 0",
+            "sqrt(-1)",
+        ]);
+        let comparisons = conditions(&[
+            "3 < 2.5",
+            "2 >= 2.0",
+            "sqrt(-1) == sqrt(-1)",
+            "sqrt(-1) != 1",
+            "sqrt(-1) < 1",
+            "1 > 2 or 2 > 1 and 3 == 3",
+        ]);
+        let conversions = values(&[
+            "int(2.9)",
+            "float(3)",
+            "int(10.0 ^ 20)",
+            "int(0 - 10.0 ^ 20)",
+            "int(sqrt(-1))",
+            "floor(-2.5)",
+            "abs(-2147483647 - 1)",
+            "abs(-2.5)",
+            "min(2, 1.5)",
+            "max(2, 1.5)",
+            "min(sqrt(-1), 1.0)",
+        ]);
+        let random = values(&["random(100) - random(10) * 100 + random(7) % 3"]);
+        let numbers = "let a; read a; let b: float; read b; let c = a + b; write c;";
+        let division = "let a; read a; write 'before'; let b = 1 / a; write b;";
+
+        let mut cases = vec![
+            Case::example("collatz", "27\n"),
+            Case::example("even_or_odd", "-7\n"),
+            Case::example("fib", "30\n"),
+            Case::example("fizzbuzz", "15\n"),
+            Case::example("greeting", "Ana\n"),
+            Case::example("points", "1\n2\n4\n6\n"),
+            Case::example("square", "3\n"),
+            Case::source("integers", &integers, ""),
+            Case::source("floats", &floats, ""),
+            Case::source("comparisons", &comparisons, ""),
+            Case::source(
+                "conversions",
+                &format!("{conversions} let a: int = 2.9; write a; let b: float = 3; write b;"),
+                "",
+            ),
+            Case::source(
+                "strings",
+                "let s = 'héllo' + ', \"world\"??=\\t\\\\'; write s; let n = len(s); write n;
+                let t: str; read t; n = len(t); write n;
+                if t == 'abc' { write 'T'; } if t != 'abc' { write 'F'; }",
+                "abc\r\n",
+            ),
+            Case::source(
+                "control_flow",
+                "let total = 0; let i = 0;
+                loop {
+                    i = i + 1;
+                    if i > 10 { break; }
+                    let j = 0;
+                    do { let k = j * i; total = total + k; j = j + 1; } until k > 20;
+                }
+                write total;
+                if total % 2 == 0 and total > 100 or i == 0 {
+                    let total = total / 2; write total;
+                } else {
+                    write 'no';
+                }",
+                "",
+            ),
+            Case::source(
+                "random",
+                &format!("let i = 0; do {{ {random} i = i + 1; }} until i == 20;"),
+                "",
+            ),
+            Case::source("input", numbers, " -12 \n2.5e1\n"),
+            Case::source("invalid_int", numbers, "12a\n2\n"),
+            Case::source("invalid_float", numbers, "1\n0x10\n"),
+            Case::source("end_of_input", numbers, "1\n"),
+            Case::source("division_by_zero", division, "0\n"),
+            Case::source("no_division_by_zero", division, "3\n"),
+            Case::source(
+                "negative_power_of_zero",
+                "let a; read a; let b = a ^ -1;",
+                "0\n",
+            ),
+            Case::source("random_bound", "let a = random(0);", ""),
+        ];
+        cases.extend((0..50).map(Case::generated));
+        cases
+    }
+}
@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::Opcode,
+    ir::{Block, Constant, Expr, ExprKind, Statement, Type, Variable},
+};
+
+// Translates a program into a self-contained C99 file. Variables become locals of `main`
+// and loops, ifs and do-until become structured C. The runtime below keeps the semantics
+// of the vm: ints wrap around, `%` and `^` behave like their p-code and floats are written
+// with the same format. `random` is seeded with VIT_SEED, which can be set when compiling.
+pub fn generate(program: &Block) -> String {
+    let mut generator = Generator {
+        output: String::new(),
+        indent: 1,
+        names: HashMap::new(),
+        temporaries: 0,
+    };
+
+    generator.output.push_str(RUNTIME);
+    generator.output.push_str("\nint main(void) {\n");
+    generator.scope(program);
+    generator.line("return 0;");
+    generator.output.push_str("}\n");
+    generator.output
+}
+
+const RUNTIME: &str = r#"/* Generated by vit. */
+#include <ctype.h>
+#include <errno.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#ifndef VIT_SEED
+#define VIT_SEED 0
+#endif
+
+static uint64_t vit_seed = VIT_SEED;
+
+/* The exit codes of runtime errors, those of `vit run`. */
+enum { VIT_OTHER = 2, VIT_DIVISION_BY_ZERO = 3, VIT_INPUT = 5 };
+
+static void vit_error(int code, const char *format, ...) {
+    va_list args;
+
+    fflush(stdout);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(code);
+}
+
+static inline int32_t vit_add(int32_t a, int32_t b) {
+    return (int32_t)((uint32_t)a + (uint32_t)b);
+}
+
+static inline int32_t vit_sub(int32_t a, int32_t b) {
+    return (int32_t)((uint32_t)a - (uint32_t)b);
+}
+
+static inline int32_t vit_mul(int32_t a, int32_t b) {
+    return (int32_t)((uint32_t)a * (uint32_t)b);
+}
+
+static inline int32_t vit_div(int32_t a, int32_t b) {
+    if (b == 0) {
+        vit_error(VIT_DIVISION_BY_ZERO, "division by zero.");
+    }
+    return b == -1 ? vit_sub(0, a) : a / b;
+}
+
+/* A negative exponent gives the truncated result of 1 / base^-exponent. */
+static inline int32_t vit_pow(int32_t base, int32_t exponent) {
+    uint32_t result = 1, factor = (uint32_t)base;
+
+    if (exponent < 0) {
+        if (base == 0) {
+            vit_error(VIT_DIVISION_BY_ZERO, "division by zero.");
+        }
+        if (base == 1 || base == -1) {
+            return exponent % 2 == 0 ? 1 : base;
+        }
+        return 0;
+    }
+    for (; exponent > 0; exponent /= 2) {
+        if (exponent % 2 == 1) {
+            result *= factor;
+        }
+        factor *= factor;
+    }
+    return (int32_t)result;
+}
+
+/* Out of range floats saturate and NaN becomes 0. */
+static inline int32_t vit_to_int(double n) {
+    if (isnan(n)) {
+        return 0;
+    }
+    if (n >= 2147483647.0) {
+        return INT32_MAX;
+    }
+    if (n <= -2147483648.0) {
+        return INT32_MIN;
+    }
+    return (int32_t)n;
+}
+
+static inline int32_t vit_abs(int32_t n) {
+    return n < 0 ? vit_sub(0, n) : n;
+}
+
+static inline int32_t vit_min(int32_t a, int32_t b) {
+    return a < b ? a : b;
+}
+
+static inline int32_t vit_max(int32_t a, int32_t b) {
+    return a < b ? b : a;
+}
+
+static inline double vit_fmin(double a, double b) {
+    return a < b ? a : b;
+}
+
+static inline double vit_fmax(double a, double b) {
+    return a < b ? b : a;
+}
+
+/* Knuth's MMIX linear congruential generator. */
+static inline int32_t vit_random(int32_t bound) {
+    if (bound <= 0) {
+        vit_error(VIT_OTHER, "random expects a positive bound, found %d.", (int)bound);
+    }
+    vit_seed = vit_seed * 6364136223846793005ULL + 1442695040888963407ULL;
+    return (int32_t)((vit_seed >> 33) % (uint64_t)bound);
+}
+
+/* The number of characters of a UTF-8 string. */
+static inline int32_t vit_len(const char *s) {
+    int32_t length = 0;
+
+    for (; *s != '\0'; s++) {
+        if (((unsigned char)*s & 0xC0) != 0x80) {
+            length++;
+        }
+    }
+    return length;
+}
+
+static inline const char *vit_concat(const char *a, const char *b) {
+    size_t length = strlen(a);
+    char *result = malloc(length + strlen(b) + 1);
+
+    strcpy(result, a);
+    strcpy(result + length, b);
+    return result;
+}
+
+static inline char *vit_read_line(void) {
+    size_t length = 0, capacity = 16;
+    char *line = malloc(capacity);
+    int c;
+
+    while ((c = getchar()) != EOF && c != '\n') {
+        if (length + 1 == capacity) {
+            capacity *= 2;
+            line = realloc(line, capacity);
+        }
+        line[length++] = (char)c;
+    }
+    if (c == EOF && length == 0) {
+        vit_error(VIT_INPUT, "unexpected end of input.");
+    }
+    while (length > 0 && line[length - 1] == '\r') {
+        length--;
+    }
+    line[length] = '\0';
+    return line;
+}
+
+static inline int32_t vit_read_int(void) {
+    char *line = vit_read_line(), *start = line, *end;
+    long n;
+
+    while (isspace((unsigned char)*start)) {
+        start++;
+    }
+    errno = 0;
+    n = strtol(start, &end, 10);
+    while (isspace((unsigned char)*end)) {
+        end++;
+    }
+    if (end == start || *end != '\0' || errno != 0 || n < INT32_MIN || n > INT32_MAX) {
+        vit_error(VIT_INPUT, "invalid input: expected an integer, found '%s'.", line);
+    }
+    free(line);
+    return (int32_t)n;
+}
+
+static inline double vit_read_float(void) {
+    char *line = vit_read_line(), *start = line, *end;
+    double n;
+
+    while (isspace((unsigned char)*start)) {
+        start++;
+    }
+    n = strtod(start, &end);
+    while (isspace((unsigned char)*end)) {
+        end++;
+    }
+    if (end == start || *end != '\0' || strpbrk(start, "xX") != NULL) {
+        vit_error(VIT_INPUT, "invalid input: expected a number, found '%s'.", line);
+    }
+    free(line);
+    return n;
+}
+
+static inline const char *vit_read_str(void) {
+    return vit_read_line();
+}
+
+static inline void vit_write_int(int32_t n) {
+    printf("%d", (int)n);
+}
+
+/* Up to six decimal places, always with a decimal point. */
+static inline void vit_write_float(double n) {
+    double whole = floor(fabs(n));
+    unsigned long long units, millionths;
+    char fraction[7];
+    int length = 6;
+
+    if (isnan(n)) {
+        fputs("nan", stdout);
+        return;
+    }
+    if (isinf(n)) {
+        fputs(n < 0 ? "-inf" : "inf", stdout);
+        return;
+    }
+    /* From 2^53 on every double is an integer, written with all its digits. */
+    if (whole >= 9007199254740992.0) {
+        printf("%s%.0f.0", n < 0 ? "-" : "", whole);
+        return;
+    }
+    units = (unsigned long long)whole;
+    millionths = (unsigned long long)((fabs(n) - whole) * 1e6 + 0.5);
+    if (millionths == 1000000) {
+        units++;
+        millionths = 0;
+    }
+    sprintf(fraction, "%06llu", millionths);
+    while (length > 1 && fraction[length - 1] == '0') {
+        length--;
+    }
+    fraction[length] = '\0';
+    printf("%s%llu.%s", n < 0 && (units != 0 || millionths != 0) ? "-" : "", units, fraction);
+}
+
+static inline void vit_write_str(const char *s) {
+    fputs(s, stdout);
+}
+
+static inline void vit_write_bool(int b) {
+    fputs(b ? "true" : "false", stdout);
+}
+"#;
+
+struct Generator {
+    output: String,
+    indent: usize,
+    names: HashMap<u32, String>, // Name of the variable alive at each address.
+    temporaries: usize,
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        self.output.push_str(&"    ".repeat(self.indent));
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+
+    // Generates the statements of `block`, which has already been opened.
+    fn scope(&mut self, block: &Block) {
+        let names = self.declare(&block.variables);
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.names = names;
+    }
+
+    // Declares `variables` and returns the names that were visible before. Names end with
+    // the address of the variable, so a variable never hides another one.
+    fn declare(&mut self, variables: &[Variable]) -> HashMap<u32, String> {
+        let names = self.names.clone();
+
+        for variable in variables {
+            let name = format!("{}_{}", variable.name.replace('.', "_"), variable.address);
+            let (ty, zero) = match variable.ty {
+                Type::Float => ("double ", "0.0"),
+                Type::Str => ("const char *", "\"\""),
+                Type::Bool => ("int ", "0"),
+                _ => ("int32_t ", "0"),
+            };
+            self.line(&format!("{ty}{name} = {zero};"));
+            self.names.insert(variable.address, name);
+        }
+        names
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.indent += 1;
+        self.scope(block);
+        self.indent -= 1;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Store(address, value) => {
+                let value = self.expression(value);
+                self.line(&format!("{} = {value};", self.names[address]));
+            }
+            Statement::Read(address, ty) => {
+                self.line(&format!(
+                    "{} = vit_read_{}();",
+                    self.names[address],
+                    suffix(*ty)
+                ));
+            }
+            Statement::Write(value) => {
+                let code = self.expression(value);
+                self.line(&format!("vit_write_{}({code});", suffix(value.ty)));
+            }
+            Statement::If(_, condition, if_block, else_block) => {
+                let condition = self.condition(condition);
+                self.line(&format!("if ({condition}) {{"));
+                self.block(if_block);
+                if let Some(e_block) = else_block {
+                    self.line("} else {");
+                    self.block(e_block);
+                }
+                self.line("}");
+            }
+            Statement::Loop(_, block) => {
+                self.line("for (;;) {");
+                self.block(block);
+                self.line("}");
+            }
+            Statement::Until(_, block, condition) => {
+                // The condition may use variables declared in the block, so it's checked
+                // at the end of the block instead of in a do-while.
+                self.line("for (;;) {");
+                self.indent += 1;
+                let names = self.declare(&block.variables);
+                for statement in &block.statements {
+                    self.statement(statement);
+                }
+                let condition = self.condition(condition);
+                self.line(&format!("if ({condition}) {{"));
+                self.indent += 1;
+                self.line("break;");
+                self.indent -= 1;
+                self.line("}");
+                self.names = names;
+                self.indent -= 1;
+                self.line("}");
+            }
+            Statement::Break(_) => self.line("break;"),
+            Statement::Block(block) => {
+                self.line("{");
+                self.block(block);
+                self.line("}");
+            }
+            Statement::Located(_, code) => {
+                for statement in code {
+                    self.statement(statement);
+                }
+            }
+        }
+    }
+
+    fn condition(&mut self, condition: &Expr) -> String {
+        let code = self.expression(condition);
+
+        // Comparisons are wrapped in parentheses, which the `if` already provides.
+        match condition.kind {
+            ExprKind::Binary(..) if code.starts_with('(') => code[1..code.len() - 1].to_string(),
+            _ => code,
+        }
+    }
+
+    // C doesn't define the order in which operands are evaluated, so calls to `random`
+    // are moved to temporaries before the statement, in the order the vm makes them.
+    fn expression(&mut self, expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Constant(constant) => literal(constant),
+            ExprKind::Load(address) => self.names[address].clone(),
+            ExprKind::Binary(left, op, right) => {
+                let mut l = self.expression(left);
+                let mut r = self.expression(right);
+
+                // Promoting an int explicitly keeps `1.0 / 0` from looking like a mistake.
+                if left.ty != right.ty && left.ty.is_numeric() && right.ty.is_numeric() {
+                    let int = if left.ty == Type::Int { &mut l } else { &mut r };
+                    *int = format!("(double){int}");
+                }
+                binary(expr.ty, left.ty, op, &l, &r)
+            }
+            ExprKind::Convert(value) => {
+                let code = self.expression(value);
+                match (value.ty, expr.ty) {
+                    (Type::Float, Type::Int) => format!("vit_to_int({code})"),
+                    (Type::Int, Type::Float) => format!("(double){code}"),
+                    _ => code,
+                }
+            }
+            ExprKind::Call(name, args) => {
+                let arg_type = args.first().map_or(Type::Unknown, |arg| arg.ty);
+                let args: Vec<String> = args.iter().map(|arg| self.expression(arg)).collect();
+                let args = args.join(", ");
+
+                match (*name, expr.ty) {
+                    ("random", _) => {
+                        let temporary = format!("r{}", self.temporaries);
+                        self.temporaries += 1;
+                        self.line(&format!("int32_t {temporary} = vit_random({args});"));
+                        temporary
+                    }
+                    ("abs", Type::Float) => format!("fabs({args})"),
+                    ("min", Type::Float) => format!("vit_fmin({args})"),
+                    ("max", Type::Float) => format!("vit_fmax({args})"),
+                    ("floor", _) => format!("vit_to_int(floor({args}))"),
+                    ("int", _) if arg_type == Type::Int => args,
+                    ("int", _) => format!("vit_to_int({args})"),
+                    ("float", _) => format!("(double){args}"),
+                    ("abs", _) => format!("vit_abs({args})"),
+                    ("min", _) => format!("vit_min({args})"),
+                    ("max", _) => format!("vit_max({args})"),
+                    ("sqrt", _) => format!("sqrt({args})"),
+                    ("len", _) => format!("vit_len({args})"),
+                    (name, _) => unreachable!("unknown built-in function: {name}"),
+                }
+            }
+        }
+    }
+}
+
+fn suffix(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "float",
+        Type::Str => "str",
+        Type::Bool => "bool",
+        _ => "int",
+    }
+}
+
+// Every operation is either a call or wrapped in parentheses, so it can be used as an
+// operand without caring about precedence.
+fn binary(ty: Type, operand: Type, op: &Opcode, l: &str, r: &str) -> String {
+    let infix = match op {
+        Opcode::Eq => "==",
+        Opcode::Neq => "!=",
+        Opcode::Grt => ">",
+        Opcode::Let => "<",
+        Opcode::Geq => ">=",
+        Opcode::Leq => "<=",
+        // Both operands are always evaluated, like in the vm.
+        Opcode::And => "&",
+        Opcode::Or => "|",
+        Opcode::Add => "+",
+        Opcode::Sub => "-",
+        Opcode::Mul => "*",
+        _ => "/",
+    };
+
+    match (ty, operand, op) {
+        (Type::Str, _, _) => format!("vit_concat({l}, {r})"),
+        (Type::Bool, Type::Str, _) => format!("(strcmp({l}, {r}) {infix} 0)"),
+        (Type::Int, _, Opcode::Add) => format!("vit_add({l}, {r})"),
+        (Type::Int, _, Opcode::Sub) => format!("vit_sub({l}, {r})"),
+        (Type::Int, _, Opcode::Mul) => format!("vit_mul({l}, {r})"),
+        (Type::Int, _, Opcode::Div) => format!("vit_div({l}, {r})"),
+        (Type::Int, _, Opcode::Exp) => format!("vit_pow({l}, {r})"),
+        (Type::Float, _, Opcode::Exp) => format!("pow({l}, {r})"),
+        _ => format!("({l} {infix} {r})"),
+    }
+}
+
+fn literal(constant: &Constant) -> String {
+    match constant {
+        Constant::Int(i32::MIN) => "INT32_MIN".to_string(),
+        Constant::Int(n) => n.to_string(),
+        Constant::Float(n) if n.is_nan() => "NAN".to_string(),
+        Constant::Float(n) if n.is_infinite() => {
+            format!("{}INFINITY", if *n < 0.0 { "-" } else { "" })
+        }
+        Constant::Float(n) => format!("{n:?}"),
+        Constant::Str(s) => {
+            let mut result = String::from("\"");
+            for byte in s.bytes() {
+                match byte {
+                    b'"' | b'\\' | b'?' => {
+                        result.push('\\');
+                        result.push(byte as char);
+                    }
+                    b'\n' => result.push_str("\\n"),
+                    b'\t' => result.push_str("\\t"),
+                    b' '..=b'~' => result.push(byte as char),
+                    _ => result.push_str(&format!("\\{byte:03o}")),
+                }
+            }
+            result.push('"');
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::Write,
+        process::{self, Command, Stdio},
+    };
+
+    use super::*;
+    use crate::{backend::tests::cases, vit};
+
+    // Builds each program with the system's C compiler and checks that it behaves like the
+    // vm: same output, and the same exit code when the vm fails.
+    #[test]
+    fn behaves_like_the_vm() {
+        let directory = env::temp_dir().join(format!("vit-c-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        for case in cases() {
+            let file = directory.join(format!("{}.c", case.name));
+            let executable = directory.join(&case.name);
+            fs::write(&file, generate(&vit::lower(case.modules.clone()).unwrap())).unwrap();
+
+            let status = Command::new("cc")
+                .args(["-std=c99", "-o"])
+                .arg(&executable)
+                .arg(&file)
+                .arg("-lm")
+                .status()
+                .unwrap();
+            assert!(status.success(), "{case}: the C compiler failed.");
+
+            let mut child = Command::new(&executable)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            // The program may stop before reading all of it.
+            let _ = child.stdin.take().unwrap().write_all(case.input.as_bytes());
+            let output = child.wait_with_output().unwrap();
+
+            let (expected, code) = case.expected();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "{case}");
+            assert_eq!(output.status.code(), Some(code), "{case}");
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+// Random programs for checking that the backends agree with the vm. Every program
+// compiles: it only uses variables in scope, with values of their type, and its loops
+// end. Running it may still fail, dividing by zero for one, which the backends must do
+// the same way.
+//
+// A program on which a backend disagrees goes into tests/programs once cut down to what
+// still shows the difference, so it's checked from then on.
+
+#[derive(Clone, Copy, PartialEq)]
+enum Type {
+    Int,
+    Float,
+    Str,
+}
+
+struct Variable {
+    name: String,
+    ty: Type,
+    assignable: bool, // Loop counters aren't, nor hidden, so that loops end.
+}
+
+struct Generator {
+    state: u64,
+    scopes: Vec<Vec<Variable>>,
+    names: usize,
+    input: String,
+    source: String,
+}
+
+// The source of the program for `seed`, and the input it reads.
+pub fn program(seed: u64) -> (String, String) {
+    let mut generator = Generator {
+        state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        scopes: vec![vec![]],
+        names: 0,
+        input: String::new(),
+        source: String::new(),
+    };
+    for _ in 0..3 {
+        generator.read();
+    }
+    for _ in 0..generator.below(8) + 4 {
+        generator.statement(0);
+    }
+    (generator.source, generator.input)
+}
+
+impl Generator {
+    // A number from 0 up to `n`, not included.
+    fn below(&mut self, n: u64) -> u64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) % n
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        self.source.push_str(&"    ".repeat(depth));
+        self.source.push_str(text);
+        self.source.push('\n');
+    }
+
+    // Declares a variable in the innermost scope, with a new name or, now and then, with
+    // that of one in an outer scope, which it hides until the scope ends.
+    fn declare(&mut self, ty: Type, assignable: bool) -> String {
+        let inner = self.scopes.last().unwrap();
+        let outer: Vec<String> = self
+            .visible()
+            .into_iter()
+            // A loop counter stays visible, for its loop to end.
+            .filter(|variable| variable.assignable)
+            .filter(|variable| inner.iter().all(|v| v.name != variable.name))
+            .map(|variable| variable.name.clone())
+            .collect();
+        let name = if !outer.is_empty() && self.chance(20) {
+            outer[self.below(outer.len() as u64) as usize].clone()
+        } else {
+            self.names += 1;
+            format!("v{}", self.names)
+        };
+        self.scopes.last_mut().unwrap().push(Variable {
+            name: name.clone(),
+            ty,
+            assignable,
+        });
+        name
+    }
+
+    // The variables that can be used, which are those not hidden by another.
+    fn visible(&self) -> Vec<&Variable> {
+        let mut visible: Vec<&Variable> = vec![];
+        for variable in self.scopes.iter().rev().flatten() {
+            if visible.iter().all(|v| v.name != variable.name) {
+                visible.push(variable);
+            }
+        }
+        visible
+    }
+
+    fn pick(&mut self, filter: impl Fn(&Variable) -> bool) -> Option<(String, Type)> {
+        let found: Vec<(String, Type)> = self
+            .visible()
+            .into_iter()
+            .filter(|variable| filter(variable))
+            .map(|variable| (variable.name.clone(), variable.ty))
+            .collect();
+        match found.len() {
+            0 => None,
+            n => Some(found[self.below(n as u64) as usize].clone()),
+        }
+    }
+
+    fn read(&mut self) {
+        let (ty, annotation, value) = match self.below(3) {
+            0 => (
+                Type::Int,
+                "int",
+                format!("{}", self.below(200) as i64 - 100),
+            ),
+            1 => (Type::Float, "float", format!("{}.5", self.below(50))),
+            _ => (Type::Str, "str", format!("word{}", self.below(10))),
+        };
+        let name = self.declare(ty, true);
+        self.line(0, &format!("let {name}: {annotation};"));
+        self.line(0, &format!("read {name};"));
+        self.input.push_str(&value);
+        self.input.push('\n');
+    }
+
+    fn statement(&mut self, depth: usize) {
+        let nested = depth < 3;
+        match self.below(10) {
+            0..=2 => {
+                let ty = self.ty();
+                let value = self.expr(ty, 3);
+                let name = self.declare(ty, true);
+                let annotation = match ty {
+                    Type::Float if self.chance(50) => ": float",
+                    _ => "",
+                };
+                self.line(depth, &format!("let {name}{annotation} = {value};"));
+            }
+            3 | 4 => match self.pick(|variable| variable.assignable) {
+                // A string made from variables could double each time round a loop.
+                Some((name, Type::Str)) => {
+                    let value = format!(
+                        "({} + {})",
+                        self.literal(Type::Str),
+                        self.literal(Type::Str)
+                    );
+                    self.line(depth, &format!("{name} = {value};"));
+                }
+                Some((name, ty)) => {
+                    let value = self.expr(ty, 3);
+                    self.line(depth, &format!("{name} = {value};"));
+                }
+                None => self.write(depth),
+            },
+            5 if nested => {
+                let condition = self.condition(2);
+                self.line(depth, &format!("if {condition} {{"));
+                self.block(depth);
+                if self.chance(50) {
+                    self.line(depth, "} else {");
+                    self.block(depth);
+                }
+                self.line(depth, "}");
+            }
+            6 if nested => {
+                let times = self.below(5);
+                let counter = self.declare(Type::Int, false);
+                self.line(depth, &format!("let {counter} = 0;"));
+                if self.chance(50) {
+                    self.line(depth, "do {");
+                    self.block(depth);
+                    self.line(depth + 1, &format!("{counter} = {counter} + 1;"));
+                    self.line(depth, &format!("}} until {counter} >= {times};"));
+                } else {
+                    self.line(depth, "loop {");
+                    self.line(depth + 1, &format!("if {counter} >= {times} {{"));
+                    self.line(depth + 2, "break;");
+                    self.line(depth + 1, "}");
+                    self.line(depth + 1, &format!("{counter} = {counter} + 1;"));
+                    self.block(depth);
+                    self.line(depth, "}");
+                }
+            }
+            _ => self.write(depth),
+        }
+    }
+
+    fn block(&mut self, depth: usize) {
+        self.scopes.push(vec![]);
+        for _ in 0..self.below(4) + 1 {
+            self.statement(depth + 1);
+        }
+        self.scopes.pop();
+    }
+
+    fn write(&mut self, depth: usize) {
+        match self.pick(|_| true) {
+            Some((name, _)) if self.chance(80) => {
+                self.line(depth, &format!("write {name};"));
+                self.line(depth, "write ' ';");
+            }
+            _ => self.line(depth, "write '\\n';"),
+        }
+    }
+
+    fn ty(&mut self) -> Type {
+        match self.below(5) {
+            0..=2 => Type::Int,
+            3 => Type::Float,
+            _ => Type::Str,
+        }
+    }
+
+    // An expression of type `ty`, with operators nested up to `depth` deep.
+    fn expr(&mut self, ty: Type, depth: usize) -> String {
+        if depth == 0 || self.chance(30) {
+            return self.atom(ty);
+        }
+        let operand = |generator: &mut Generator| generator.expr(ty, depth - 1);
+        match ty {
+            Type::Int => match self.below(10) {
+                0..=3 => {
+                    let (l, r) = (operand(self), operand(self));
+                    let op = ["+", "-", "*"][self.below(3) as usize];
+                    format!("({l} {op} {r})")
+                }
+                4 => {
+                    let (l, r) = (operand(self), operand(self));
+                    let op = ["/", "%"][self.below(2) as usize];
+                    format!("({l} {op} {r})")
+                }
+                5 => format!("({} ^ {})", operand(self), self.below(4)),
+                6 => {
+                    let function = ["abs", "min", "max"][self.below(3) as usize];
+                    match function {
+                        "abs" => format!("abs({})", operand(self)),
+                        _ => format!("{function}({}, {})", operand(self), operand(self)),
+                    }
+                }
+                7 => format!("int({})", self.expr(Type::Float, depth - 1)),
+                8 => format!("len({})", self.expr(Type::Str, depth - 1)),
+                _ => self.atom(ty),
+            },
+            Type::Float => match self.below(6) {
+                0..=2 => {
+                    let (l, r) = (operand(self), operand(self));
+                    let op = ["+", "-", "*", "/"][self.below(4) as usize];
+                    format!("({l} {op} {r})")
+                }
+                3 => format!("float({})", self.expr(Type::Int, depth - 1)),
+                4 => {
+                    let function = ["sqrt", "floor", "abs"][self.below(3) as usize];
+                    format!("{function}({})", operand(self))
+                }
+                _ => self.atom(ty),
+            },
+            Type::Str => format!("({} + {})", operand(self), operand(self)),
+        }
+    }
+
+    fn atom(&mut self, ty: Type) -> String {
+        if self.chance(60) {
+            if let Some((name, _)) = self.pick(|variable| variable.ty == ty) {
+                return name;
+            }
+        }
+        self.literal(ty)
+    }
+
+    fn literal(&mut self, ty: Type) -> String {
+        match ty {
+            Type::Int if self.chance(20) => format!("-{}", self.below(10)),
+            Type::Int => format!("{}", self.below(20)),
+            Type::Float => format!("{}.{}", self.below(10), self.below(100)),
+            Type::Str => format!("'{}'", ["a", "bc", "", "d e"][self.below(4) as usize]),
+        }
+    }
+
+    fn condition(&mut self, depth: usize) -> String {
+        if depth > 0 && self.chance(30) {
+            let (l, r) = (self.condition(depth - 1), self.condition(depth - 1));
+            let op = ["and", "or"][self.below(2) as usize];
+            return format!("({l} {op} {r})");
+        }
+        let ty = [Type::Int, Type::Int, Type::Float, Type::Str][self.below(4) as usize];
+        let (l, r) = (self.expr(ty, 2), self.expr(ty, 2));
+        let op = match ty {
+            Type::Str => ["==", "!="][self.below(2) as usize],
+            _ => ["==", "!=", "<", ">", "<=", ">="][self.below(6) as usize],
+        };
+        format!("{l} {op} {r}")
+    }
+}
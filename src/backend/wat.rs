@@ -0,0 +1,593 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::Opcode,
+    ir::{Block, Constant, Expr, ExprKind, Statement, Type, Variable},
+};
+
+// Translates a program into a WebAssembly text module. Variables become locals of the
+// exported `main` function and loops use `block`/`loop`, with the labels of the p-code.
+// Strings live in linear memory as a 32-bit length followed by their UTF-8 bytes.
+//
+// The host provides, in the `vit` module:
+//   read_int: () -> i32, read_float: () -> f64, read_str: () -> i32,
+//   write_int: (i32), write_float: (f64), write_str: (i32), write_bool: (i32),
+//   pow: (f64, f64) -> f64, and error: (i32, i32), which must not return.
+// error stops the program with an exit code, which is that of `vit run` for the error, and
+// a message.
+// read_str stores the line it reads in memory it gets from the exported `alloc`. Before
+// calling `main`, the host can set the exported `seed` global used by `random`.
+pub fn generate(program: &Block) -> String {
+    let mut generator = Generator {
+        code: String::new(),
+        indent: 2,
+        locals: vec![],
+        names: HashMap::new(),
+        strings: HashMap::new(),
+        data: vec![],
+        data_end: 4, // The empty string is at address 0.
+    };
+
+    let messages = [
+        ("division_by_zero", "division by zero."),
+        ("invalid_bound", "random expects a positive bound."),
+        ("out_of_memory", "out of memory."),
+    ]
+    .map(|(name, message)| (name, generator.string(message)));
+
+    generator.scope(program);
+
+    let mut module = String::from("(module\n");
+    module.push_str(IMPORTS);
+    module.push_str("  (memory (export \"memory\") 1)\n");
+    for (name, address) in messages {
+        module.push_str(&format!("  (global ${name} i32 (i32.const {address}))\n"));
+    }
+    module.push_str(&format!(
+        "  (global $heap (mut i32) (i32.const {}))\n",
+        (generator.data_end + 7) & !7
+    ));
+    module.push_str(&generator.data.concat());
+    module.push_str(RUNTIME);
+
+    module.push_str("\n  (func $main (export \"main\")\n");
+    for (name, ty) in &generator.locals {
+        module.push_str(&format!("    (local ${name} {ty})\n"));
+    }
+    module.push_str(&generator.code);
+    module.push_str("  )\n)\n");
+    module
+}
+
+const IMPORTS: &str = r#"  (import "vit" "read_int" (func $read_int (result i32)))
+  (import "vit" "read_float" (func $read_float (result f64)))
+  (import "vit" "read_str" (func $read_str (result i32)))
+  (import "vit" "write_int" (func $write_int (param i32)))
+  (import "vit" "write_float" (func $write_float (param f64)))
+  (import "vit" "write_str" (func $write_str (param i32)))
+  (import "vit" "write_bool" (func $write_bool (param i32)))
+  (import "vit" "pow" (func $fpow (param f64 f64) (result f64)))
+  (import "vit" "error" (func $host_error (param i32 i32)))
+"#;
+
+const RUNTIME: &str = r#"
+  (global $seed (export "seed") (mut i64) (i64.const 0))
+
+  (func $error (param $code i32) (param $message i32)
+    (call $host_error (local.get $code) (local.get $message))
+    unreachable)
+
+  ;; Reserves `size` bytes, growing the memory when needed.
+  (func $alloc (export "alloc") (param $size i32) (result i32)
+    (local $pointer i32)
+    (local $needed i32)
+    (local.set $pointer (global.get $heap))
+    (global.set $heap
+      (i32.and (i32.add (i32.add (local.get $pointer) (local.get $size)) (i32.const 7))
+               (i32.const -8)))
+    (local.set $needed
+      (i32.sub (global.get $heap) (i32.mul (memory.size) (i32.const 65536))))
+    (if (i32.gt_s (local.get $needed) (i32.const 0))
+      (then
+        (if (i32.eq (memory.grow (i32.shr_u (i32.add (local.get $needed) (i32.const 65535))
+                                            (i32.const 16)))
+                    (i32.const -1))
+          (then (call $error (i32.const 2) (global.get $out_of_memory))))))
+    (local.get $pointer))
+
+  (func $div (param $a i32) (param $b i32) (result i32)
+    (if (i32.eqz (local.get $b))
+      (then (call $error (i32.const 3) (global.get $division_by_zero))))
+    (if (result i32) (i32.eq (local.get $b) (i32.const -1))
+      (then (i32.sub (i32.const 0) (local.get $a)))
+      (else (i32.div_s (local.get $a) (local.get $b)))))
+
+  ;; A negative exponent gives the truncated result of 1 / base^-exponent.
+  (func $pow (param $base i32) (param $exponent i32) (result i32)
+    (local $result i32)
+    (if (i32.lt_s (local.get $exponent) (i32.const 0))
+      (then
+        (if (i32.eqz (local.get $base))
+          (then (call $error (i32.const 3) (global.get $division_by_zero))))
+        (if (i32.eq (local.get $base) (i32.const 1))
+          (then (return (i32.const 1))))
+        (if (i32.eq (local.get $base) (i32.const -1))
+          (then (return (select (i32.const -1) (i32.const 1)
+                                (i32.and (local.get $exponent) (i32.const 1))))))
+        (return (i32.const 0))))
+    (local.set $result (i32.const 1))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $exponent)))
+        (if (i32.and (local.get $exponent) (i32.const 1))
+          (then (local.set $result (i32.mul (local.get $result) (local.get $base)))))
+        (local.set $base (i32.mul (local.get $base) (local.get $base)))
+        (local.set $exponent (i32.shr_u (local.get $exponent) (i32.const 1)))
+        (br $next)))
+    (local.get $result))
+
+  (func $abs (param $n i32) (result i32)
+    (select (i32.sub (i32.const 0) (local.get $n)) (local.get $n)
+            (i32.lt_s (local.get $n) (i32.const 0))))
+
+  (func $min (param $a i32) (param $b i32) (result i32)
+    (select (local.get $a) (local.get $b) (i32.lt_s (local.get $a) (local.get $b))))
+
+  (func $max (param $a i32) (param $b i32) (result i32)
+    (select (local.get $b) (local.get $a) (i32.lt_s (local.get $a) (local.get $b))))
+
+  (func $fmin (param $a f64) (param $b f64) (result f64)
+    (select (local.get $a) (local.get $b) (f64.lt (local.get $a) (local.get $b))))
+
+  (func $fmax (param $a f64) (param $b f64) (result f64)
+    (select (local.get $b) (local.get $a) (f64.lt (local.get $a) (local.get $b))))
+
+  ;; Knuth's MMIX linear congruential generator.
+  (func $random (param $bound i32) (result i32)
+    (if (i32.le_s (local.get $bound) (i32.const 0))
+      (then (call $error (i32.const 2) (global.get $invalid_bound))))
+    (global.set $seed
+      (i64.add (i64.mul (global.get $seed) (i64.const 6364136223846793005))
+               (i64.const 1442695040888963407)))
+    (i32.wrap_i64
+      (i64.rem_u (i64.shr_u (global.get $seed) (i64.const 33))
+                 (i64.extend_i32_u (local.get $bound)))))
+
+  ;; The number of characters of a string: bytes that don't continue a UTF-8 sequence.
+  (func $len (param $s i32) (result i32)
+    (local $end i32)
+    (local $count i32)
+    (local.set $end (i32.add (i32.add (local.get $s) (i32.const 4)) (i32.load (local.get $s))))
+    (local.set $s (i32.add (local.get $s) (i32.const 4)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $s) (local.get $end)))
+        (if (i32.ne (i32.and (i32.load8_u (local.get $s)) (i32.const 0xC0)) (i32.const 0x80))
+          (then (local.set $count (i32.add (local.get $count) (i32.const 1)))))
+        (local.set $s (i32.add (local.get $s) (i32.const 1)))
+        (br $next)))
+    (local.get $count))
+
+  (func $concat (param $a i32) (param $b i32) (result i32)
+    (local $result i32)
+    (local.set $result
+      (call $alloc (i32.add (i32.add (i32.load (local.get $a)) (i32.load (local.get $b)))
+                            (i32.const 4))))
+    (i32.store (local.get $result) (i32.add (i32.load (local.get $a)) (i32.load (local.get $b))))
+    (memory.copy (i32.add (local.get $result) (i32.const 4))
+                 (i32.add (local.get $a) (i32.const 4))
+                 (i32.load (local.get $a)))
+    (memory.copy (i32.add (i32.add (local.get $result) (i32.const 4)) (i32.load (local.get $a)))
+                 (i32.add (local.get $b) (i32.const 4))
+                 (i32.load (local.get $b)))
+    (local.get $result))
+
+  (func $str_eq (param $a i32) (param $b i32) (result i32)
+    (local $i i32)
+    (if (i32.ne (i32.load (local.get $a)) (i32.load (local.get $b)))
+      (then (return (i32.const 0))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (i32.load (local.get $a))))
+        (if (i32.ne (i32.load8_u (i32.add (i32.add (local.get $a) (i32.const 4)) (local.get $i)))
+                    (i32.load8_u (i32.add (i32.add (local.get $b) (i32.const 4)) (local.get $i))))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 1))
+"#;
+
+struct Generator {
+    code: String,
+    indent: usize,
+    locals: Vec<(String, &'static str)>,
+    names: HashMap<u32, String>, // Local of the variable alive at each address.
+    strings: HashMap<String, u32>, // Address of each string constant.
+    data: Vec<String>,
+    data_end: u32,
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        self.code.push_str(&"  ".repeat(self.indent));
+        self.code.push_str(line);
+        self.code.push('\n');
+    }
+
+    fn scope(&mut self, block: &Block) {
+        let names = self.declare(&block.variables);
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+        self.names = names;
+    }
+
+    // Locals belong to the whole function, so variables of different scopes share one
+    // when they have the same name, address and type.
+    fn declare(&mut self, variables: &[Variable]) -> HashMap<u32, String> {
+        let names = self.names.clone();
+
+        for variable in variables {
+            let ty = value_type(variable.ty);
+            let mut name = format!("{}_{}", variable.name, variable.address);
+            if self
+                .locals
+                .iter()
+                .any(|(local, t)| *local == name && *t != ty)
+            {
+                name = format!("{name}_{ty}");
+            }
+            if !self.locals.iter().any(|(local, _)| *local == name) {
+                self.locals.push((name.clone(), ty));
+            }
+            self.names.insert(variable.address, name);
+        }
+        names
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.indent += 1;
+        self.scope(block);
+        self.indent -= 1;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Store(address, value) => {
+                self.expression(value);
+                self.line(&format!("local.set ${}", self.names[address]));
+            }
+            Statement::Read(address, ty) => {
+                self.line(&format!("call $read_{}", suffix(*ty)));
+                self.line(&format!("local.set ${}", self.names[address]));
+            }
+            Statement::Write(value) => {
+                self.expression(value);
+                self.line(&format!("call $write_{}", suffix(value.ty)));
+            }
+            Statement::If(_, condition, if_block, else_block) => {
+                self.expression(condition);
+                self.line("if");
+                self.block(if_block);
+                if let Some(e_block) = else_block {
+                    self.line("else");
+                    self.block(e_block);
+                }
+                self.line("end");
+            }
+            Statement::Loop(label, block) => {
+                self.line(&format!("block $E{label}"));
+                self.indent += 1;
+                self.line(&format!("loop $L{label}"));
+                self.block(block);
+                self.indent += 1;
+                self.line(&format!("br $L{label}"));
+                self.indent -= 1;
+                self.line("end");
+                self.indent -= 1;
+                self.line("end");
+            }
+            Statement::Until(label, block, condition) => {
+                self.line(&format!("block $E{label}"));
+                self.indent += 1;
+                self.line(&format!("loop $L{label}"));
+                self.indent += 1;
+                let names = self.declare(&block.variables);
+                for statement in &block.statements {
+                    self.statement(statement);
+                }
+                self.expression(condition);
+                self.names = names;
+                self.line("i32.eqz");
+                self.line(&format!("br_if $L{label}"));
+                self.indent -= 1;
+                self.line("end");
+                self.indent -= 1;
+                self.line("end");
+            }
+            Statement::Break(label) => self.line(&format!("br $E{label}")),
+            Statement::Block(block) => self.scope(block),
+            Statement::Located(_, code) => {
+                for statement in code {
+                    self.statement(statement);
+                }
+            }
+        }
+    }
+
+    // Pushes the value of `expr`. Operands are pushed from left to right, like in p-code.
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Constant(constant) => {
+                let line = match constant {
+                    Constant::Int(n) => format!("i32.const {n}"),
+                    Constant::Float(n) if n.is_nan() => "f64.const nan".to_string(),
+                    Constant::Float(n) => format!("f64.const {n:?}"),
+                    Constant::Str(s) => format!("i32.const {}", self.string(s)),
+                };
+                self.line(&line);
+            }
+            ExprKind::Load(address) => self.line(&format!("local.get ${}", self.names[address])),
+            ExprKind::Binary(left, op, right) => {
+                let float = left.ty == Type::Float || right.ty == Type::Float;
+                self.expression(left);
+                if float && left.ty == Type::Int {
+                    self.line("f64.convert_i32_s");
+                }
+                self.expression(right);
+                if float && right.ty == Type::Int {
+                    self.line("f64.convert_i32_s");
+                }
+                for line in binary(left.ty, op, float).lines() {
+                    self.line(line);
+                }
+            }
+            ExprKind::Convert(value) => {
+                self.expression(value);
+                self.convert(value.ty, expr.ty);
+            }
+            ExprKind::Call(name, args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let arg = args.first().map_or(Type::Unknown, |arg| arg.ty);
+
+                match (*name, expr.ty) {
+                    ("abs", Type::Float) => self.line("f64.abs"),
+                    ("min" | "max", Type::Float) => self.line(&format!("call $f{name}")),
+                    ("sqrt", _) => {
+                        self.convert(arg, Type::Float);
+                        self.line("f64.sqrt");
+                    }
+                    ("floor", _) => {
+                        self.convert(arg, Type::Float);
+                        self.line("f64.floor");
+                        self.line("i32.trunc_sat_f64_s");
+                    }
+                    ("int", _) => self.convert(arg, Type::Int),
+                    ("float", _) => self.convert(arg, Type::Float),
+                    (name, _) => self.line(&format!("call ${name}")),
+                }
+            }
+        }
+    }
+
+    // Floats are truncated towards zero; out of range values saturate and NaN becomes 0.
+    fn convert(&mut self, from: Type, to: Type) {
+        match (from, to) {
+            (Type::Float, Type::Int) => self.line("i32.trunc_sat_f64_s"),
+            (Type::Int, Type::Float) => self.line("f64.convert_i32_s"),
+            _ => (),
+        }
+    }
+
+    // Adds a string to the data of the module, once, and returns its address.
+    fn string(&mut self, s: &str) -> u32 {
+        if let Some(address) = self.strings.get(s) {
+            return *address;
+        }
+
+        let address = self.data_end;
+        let mut bytes = (s.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(s.as_bytes());
+
+        let escaped: String = bytes
+            .iter()
+            .map(|byte| match byte {
+                b'"' | b'\\' => format!("\\{}", *byte as char),
+                b' '..=b'~' => (*byte as char).to_string(),
+                _ => format!("\\{byte:02x}"),
+            })
+            .collect();
+        self.data
+            .push(format!("  (data (i32.const {address}) \"{escaped}\")\n"));
+
+        self.data_end = (address + bytes.len() as u32 + 3) & !3;
+        self.strings.insert(s.to_string(), address);
+        address
+    }
+}
+
+fn value_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "f64",
+        _ => "i32",
+    }
+}
+
+fn suffix(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "float",
+        Type::Str => "str",
+        Type::Bool => "bool",
+        _ => "int",
+    }
+}
+
+// The instructions of `op` for operands of type `operand`, or for floats when `float` is set.
+fn binary(operand: Type, op: &Opcode, float: bool) -> String {
+    let name = match op {
+        Opcode::Add if operand == Type::Str => return "call $concat".to_string(),
+        Opcode::Eq if operand == Type::Str => return "call $str_eq".to_string(),
+        Opcode::Neq if operand == Type::Str => return "call $str_eq\ni32.eqz".to_string(),
+        Opcode::And => return "i32.and".to_string(),
+        Opcode::Or => return "i32.or".to_string(),
+        Opcode::Exp if float => return "call $fpow".to_string(),
+        Opcode::Exp => return "call $pow".to_string(),
+        Opcode::Div if !float => return "call $div".to_string(),
+        Opcode::Add => "add",
+        Opcode::Sub => "sub",
+        Opcode::Mul => "mul",
+        Opcode::Div => "div",
+        Opcode::Eq => "eq",
+        Opcode::Neq => "ne",
+        Opcode::Grt if float => "gt",
+        Opcode::Let if float => "lt",
+        Opcode::Geq if float => "ge",
+        Opcode::Leq if float => "le",
+        Opcode::Grt => "gt_s",
+        Opcode::Let => "lt_s",
+        Opcode::Geq => "ge_s",
+        _ => "le_s",
+    };
+    format!("{}.{name}", if float { "f64" } else { "i32" })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Cursor};
+
+    use wasmi::{Caller, Engine, Error, Extern, Linker, Module, Store};
+
+    use super::*;
+    use crate::{backend::tests::cases, vit, vm};
+
+    // A host that reads from and writes to memory, like the vm.
+    struct Host {
+        input: Cursor<Vec<u8>>,
+        output: String,
+        error: String, // The message of the error that stopped the program.
+    }
+
+    // Stops the program with `code`, the exit code of `vit run` for the error.
+    fn exit(caller: &mut Caller<Host>, code: i32, message: String) -> Error {
+        caller.data_mut().error = message;
+        Error::i32_exit(code)
+    }
+
+    fn read_line(caller: &mut Caller<Host>) -> Result<String, Error> {
+        let mut line = String::new();
+        if caller.data_mut().input.read_line(&mut line).unwrap() == 0 {
+            return Err(exit(caller, 5, "unexpected end of input.".to_string()));
+        }
+        Ok(line.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    fn string(caller: &Caller<Host>, address: i32) -> String {
+        let memory = caller.get_export("memory").and_then(Extern::into_memory);
+        let data = memory.unwrap().data(caller);
+        let start = address as usize + 4;
+        let length = u32::from_le_bytes(data[start - 4..start].try_into().unwrap()) as usize;
+        String::from_utf8(data[start..start + length].to_vec()).unwrap()
+    }
+
+    fn link(engine: &Engine) -> Linker<Host> {
+        let mut linker = Linker::new(engine);
+        linker
+            .func_wrap("vit", "read_int", |mut caller: Caller<Host>| {
+                let line = read_line(&mut caller)?;
+                line.trim().parse::<i32>().map_err(|_| {
+                    let message = format!("invalid input: expected an integer, found '{line}'.");
+                    exit(&mut caller, 5, message)
+                })
+            })
+            .unwrap()
+            .func_wrap("vit", "read_float", |mut caller: Caller<Host>| {
+                let line = read_line(&mut caller)?;
+                line.trim().parse::<f64>().map_err(|_| {
+                    let message = format!("invalid input: expected a number, found '{line}'.");
+                    exit(&mut caller, 5, message)
+                })
+            })
+            .unwrap()
+            .func_wrap("vit", "read_str", |mut caller: Caller<Host>| {
+                let line = read_line(&mut caller)?;
+                let alloc = caller.get_export("alloc").and_then(Extern::into_func);
+                let alloc = alloc.unwrap().typed::<i32, i32>(&caller)?;
+                let address = alloc.call(&mut caller, line.len() as i32 + 4)?;
+
+                let memory = caller.get_export("memory").and_then(Extern::into_memory);
+                let mut bytes = (line.len() as u32).to_le_bytes().to_vec();
+                bytes.extend(line.as_bytes());
+                memory
+                    .unwrap()
+                    .write(&mut caller, address as usize, &bytes)
+                    .unwrap();
+                Ok(address)
+            })
+            .unwrap()
+            .func_wrap("vit", "write_int", |mut caller: Caller<Host>, n: i32| {
+                caller.data_mut().output.push_str(&n.to_string());
+            })
+            .unwrap()
+            .func_wrap("vit", "write_float", |mut caller: Caller<Host>, n: f64| {
+                caller.data_mut().output.push_str(&vm::format_float(n));
+            })
+            .unwrap()
+            .func_wrap("vit", "write_str", |mut caller: Caller<Host>, s: i32| {
+                let s = string(&caller, s);
+                caller.data_mut().output.push_str(&s);
+            })
+            .unwrap()
+            .func_wrap("vit", "write_bool", |mut caller: Caller<Host>, b: i32| {
+                caller.data_mut().output.push_str(&(b != 0).to_string());
+            })
+            .unwrap()
+            .func_wrap("vit", "pow", |base: f64, exponent: f64| base.powf(exponent))
+            .unwrap()
+            .func_wrap(
+                "vit",
+                "error",
+                |mut caller: Caller<Host>, code: i32, message: i32| {
+                    let message = string(&caller, message);
+                    Err::<(), _>(exit(&mut caller, code, message))
+                },
+            )
+            .unwrap();
+        linker
+    }
+
+    // Runs each program with an interpreter and checks that it behaves like the vm: same
+    // output, and the same exit code when the vm fails.
+    #[test]
+    fn behaves_like_the_vm() {
+        let engine = Engine::default();
+        let linker = link(&engine);
+
+        for case in cases() {
+            let wat = generate(&vit::lower(case.modules.clone()).unwrap());
+            let module = Module::new(&engine, &wat)
+                .unwrap_or_else(|e| panic!("{case}: invalid module: {e}\n{wat}"));
+
+            let host = Host {
+                input: Cursor::new(case.input.clone().into_bytes()),
+                output: String::new(),
+                error: String::new(),
+            };
+            let mut store = Store::new(&engine, host);
+            let instance = linker.instantiate_and_start(&mut store, &module).unwrap();
+            let main = instance.get_typed_func::<(), ()>(&store, "main").unwrap();
+            let result = main.call(&mut store, ());
+
+            let code = match result {
+                Ok(()) => 0,
+                Err(error) => error
+                    .i32_exit_status()
+                    .unwrap_or_else(|| panic!("{case}: {error}")),
+            };
+            let (expected, expected_code) = case.expected();
+            assert_eq!(store.data().output, expected, "{case}");
+            assert_eq!(code, expected_code, "{case}: {}", store.data().error);
+        }
+    }
+}
@@ -0,0 +1,1306 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::Opcode,
+    ir::{Block, Constant, Expr, ExprKind, Statement, Type, Variable},
+};
+
+// Translates a program into GNU assembler text for x86-64 Linux, to be built with
+// `as -o program.o program.s && ld -o program program.o`. Expressions are evaluated like
+// in the vm: each one leaves its value in %rax, and the left operand of a binary operation
+// waits on the stack while the right one is evaluated. Floats travel as their bits and are
+// computed with SSE2. Every variable has a cell in `vit_variables`.
+//
+// The runtime only needs Linux system calls. Output is buffered, strings are a 64-bit
+// length followed by their bytes and live in memory taken with brk that is never freed.
+// Float powers and parsing use the x87 unit, so their results may differ from the vm in
+// the last bit. `random` is seeded with VIT_SEED, which can be set with `as --defsym`.
+pub fn generate(program: &Block) -> String {
+    let mut generator = Generator {
+        code: String::new(),
+        strings: HashMap::new(),
+        data: String::new(),
+        cells: 0,
+    };
+    generator.scope(program);
+
+    let mut output = String::from(HEADER);
+    output.push_str(&generator.code);
+    output.push_str("    xor %edi, %edi\n    jmp vit_exit\n");
+    output.push_str(RUNTIME);
+    output.push_str("\n    .section .rodata\n");
+    output.push_str(&generator.data);
+    if generator.cells > 0 {
+        output.push_str(&format!(
+            "\n    .bss\n    .align 8\nvit_variables:\n    .zero {}\n",
+            generator.cells * 8
+        ));
+    }
+    output
+}
+
+const HEADER: &str = r#"# Generated by vit.
+    .ifndef VIT_SEED
+    .set VIT_SEED, 0
+    .endif
+
+    # A string: its length followed by its bytes.
+    .macro vit_string name, text
+\name:
+    .quad 2f - 1f
+1:  .ascii "\text"
+2:
+    .endm
+
+    .text
+    .globl _start
+_start:
+    mov $12, %eax
+    xor %edi, %edi
+    syscall
+    mov %rax, vit_heap(%rip)
+    mov %rax, vit_heap_end(%rip)
+"#;
+
+const RUNTIME: &str = r#"
+# Writes the buffered output and exits with the status in %edi.
+vit_exit:
+    push %rdi
+    call vit_flush
+    pop %rdi
+    mov $60, %eax
+    syscall
+
+vit_flush:
+    lea vit_output(%rip), %rsi
+    mov vit_output_length(%rip), %rdx
+1:  test %rdx, %rdx
+    jz 2f
+    mov $1, %eax
+    mov $1, %edi
+    syscall
+    test %rax, %rax
+    jle 2f
+    add %rax, %rsi
+    sub %rax, %rdx
+    jmp 1b
+2:  movq $0, vit_output_length(%rip)
+    ret
+
+# Appends the %rdx bytes at %rsi to the output.
+vit_put:
+    test %rdx, %rdx
+    jz 2f
+    mov vit_output_length(%rip), %rax
+    cmp $4096, %rax
+    jb 1f
+    push %rsi
+    push %rdx
+    call vit_flush
+    pop %rdx
+    pop %rsi
+    xor %eax, %eax
+1:  lea vit_output(%rip), %rcx
+    movzbl (%rsi), %r8d
+    mov %r8b, (%rcx,%rax)
+    inc %rax
+    mov %rax, vit_output_length(%rip)
+    inc %rsi
+    dec %rdx
+    jmp vit_put
+2:  ret
+
+# Writes the output so far and the message at %rdi, and exits with the status in %esi,
+# the exit code of `vit run` for the error.
+vit_error:
+    push %rsi
+    push %rdi
+    call vit_flush
+    pop %rsi
+    mov (%rsi), %rdx
+    add $8, %rsi
+    mov $1, %eax
+    mov $2, %edi
+    syscall
+    mov $1, %eax
+    mov $2, %edi
+    lea vit_newline+8(%rip), %rsi
+    mov $1, %edx
+    syscall
+    pop %rdi
+    jmp vit_exit
+
+vit_division_by_zero:
+    lea vit_division_by_zero_message(%rip), %rdi
+    mov $3, %esi
+    jmp vit_error
+
+vit_out_of_memory:
+    lea vit_out_of_memory_message(%rip), %rdi
+    mov $2, %esi
+    jmp vit_error
+
+# Makes sure the heap reaches the address in %rdi.
+vit_reserve:
+    cmp vit_heap_end(%rip), %rdi
+    jbe 1f
+    push %rdi
+    add $0xfffff, %rdi
+    and $-0x100000, %rdi
+    push %rdi
+    mov $12, %eax
+    syscall
+    pop %rdi
+    cmp %rdi, %rax
+    jb vit_out_of_memory
+    mov %rax, vit_heap_end(%rip)
+    pop %rdi
+1:  ret
+
+# Returns %rdi bytes of memory.
+vit_alloc:
+    mov vit_heap(%rip), %rax
+    lea 7(%rax,%rdi), %rdi
+    and $-8, %rdi
+    push %rax
+    push %rdi
+    call vit_reserve
+    pop %rdi
+    mov %rdi, vit_heap(%rip)
+    pop %rax
+    ret
+
+vit_write_str:
+    mov (%rdi), %rdx
+    lea 8(%rdi), %rsi
+    jmp vit_put
+
+vit_write_bool:
+    test %edi, %edi
+    lea vit_false(%rip), %rdi
+    lea vit_true(%rip), %rax
+    cmovnz %rax, %rdi
+    jmp vit_write_str
+
+# Writes the unsigned %rax with at least %edi digits.
+vit_write_digits:
+    sub $32, %rsp
+    lea 32(%rsp), %rsi
+    mov $10, %ecx
+1:  xor %edx, %edx
+    div %rcx
+    add $'0', %dl
+    dec %rsi
+    mov %dl, (%rsi)
+    dec %edi
+    test %rax, %rax
+    jnz 1b
+    cmp $0, %edi
+    jg 1b
+    lea 32(%rsp), %rdx
+    sub %rsi, %rdx
+    call vit_put
+    add $32, %rsp
+    ret
+
+vit_write_int:
+    movslq %edi, %rax
+    test %rax, %rax
+    jns 1f
+    neg %rax
+    push %rax
+    lea vit_minus(%rip), %rdi
+    call vit_write_str
+    pop %rax
+1:  mov $1, %edi
+    jmp vit_write_digits
+
+# Writes a float rounded to 6 decimals, without trailing zeros, like the vm. From 2^53 on,
+# where every float is an integer, all the digits of that integer are written.
+vit_write_float:
+    movq %rdi, %xmm0
+    ucomisd %xmm0, %xmm0
+    jp 5f
+    mov %rdi, %rax
+    btr $63, %rax
+    movabs $0x7ff0000000000000, %rcx
+    cmp %rcx, %rax
+    je 6f
+    movq %rax, %xmm0
+    ucomisd vit_two_53(%rip), %xmm0
+    jae 8f
+    cvttsd2si %xmm0, %rsi
+    cvtsi2sd %rsi, %xmm1
+    subsd %xmm1, %xmm0
+    mulsd vit_million(%rip), %xmm0
+    addsd vit_half(%rip), %xmm0
+    cvttsd2si %xmm0, %rax
+    cmp $1000000, %rax
+    jb 1f
+    inc %rsi
+    xor %eax, %eax
+1:  push %rax
+    push %rsi
+    test %rdi, %rdi
+    jns 2f
+    or %rsi, %rax
+    jz 2f
+    lea vit_minus(%rip), %rdi
+    call vit_write_str
+2:  pop %rax
+    mov $1, %edi
+    call vit_write_digits
+    lea vit_point(%rip), %rdi
+    call vit_write_str
+    pop %rax
+    mov $1, %edi
+    test %rax, %rax
+    jz vit_write_digits
+    mov $6, %edi
+    mov $10, %ecx
+4:  mov %rax, %r8
+    xor %edx, %edx
+    div %rcx
+    test %rdx, %rdx
+    jnz 7f
+    dec %edi
+    jmp 4b
+7:  mov %r8, %rax
+    jmp vit_write_digits
+5:  lea vit_nan(%rip), %rdi
+    jmp vit_write_str
+6:  lea vit_inf(%rip), %rax
+    lea vit_negative_inf(%rip), %rsi
+    test %rdi, %rdi
+    cmovs %rsi, %rax
+    mov %rax, %rdi
+    jmp vit_write_str
+8:  push %rax
+    test %rdi, %rdi
+    jns 9f
+    lea vit_minus(%rip), %rdi
+    call vit_write_str
+9:  pop %rax
+    call vit_write_big
+    lea vit_point(%rip), %rdi
+    call vit_write_str
+    xor %eax, %eax
+    mov $1, %edi
+    jmp vit_write_digits
+
+# Writes the integer that is the float with the bits %rax, at least 2^53 and positive. Its
+# mantissa goes into 17 64-bit limbs, shifted by its exponent, which are divided by 10^19
+# until nothing is left; the remainders are the digits, 19 at a time, last ones first.
+vit_write_big:
+    push %rbx
+    sub $272, %rsp
+    mov %rax, %rcx
+    shr $52, %rcx
+    sub $1075, %ecx
+    movabs $0xfffffffffffff, %rdx
+    and %rdx, %rax
+    bts $52, %rax
+    xor %r8d, %r8d
+1:  movq $0, (%rsp,%r8,8)
+    inc %r8
+    cmp $17, %r8
+    jb 1b
+    mov %ecx, %r8d
+    shr $6, %r8d
+    and $63, %ecx
+    xor %edx, %edx
+    shld %cl, %rax, %rdx
+    shl %cl, %rax
+    mov %rax, (%rsp,%r8,8)
+    mov %rdx, 8(%rsp,%r8,8)
+    xor %ebx, %ebx
+    movabs $10000000000000000000, %r9
+2:  mov $16, %r8
+    xor %edx, %edx
+3:  mov (%rsp,%r8,8), %rax
+    div %r9
+    mov %rax, (%rsp,%r8,8)
+    dec %r8
+    jns 3b
+    mov %rdx, 136(%rsp,%rbx,8)
+    inc %rbx
+    xor %eax, %eax
+    mov $16, %r8
+4:  or (%rsp,%r8,8), %rax
+    dec %r8
+    jns 4b
+    test %rax, %rax
+    jnz 2b
+    dec %rbx
+    mov 136(%rsp,%rbx,8), %rax
+    mov $1, %edi
+    call vit_write_digits
+5:  dec %rbx
+    js 6f
+    mov 136(%rsp,%rbx,8), %rax
+    mov $19, %edi
+    call vit_write_digits
+    jmp 5b
+6:  add $272, %rsp
+    pop %rbx
+    ret
+
+# Returns the next byte of the input in %eax, or -1 at its end.
+vit_getc:
+    mov vit_input_position(%rip), %rax
+    cmp vit_input_length(%rip), %rax
+    jb 1f
+    xor %eax, %eax
+    xor %edi, %edi
+    lea vit_input(%rip), %rsi
+    mov $4096, %edx
+    syscall
+    test %rax, %rax
+    jle 2f
+    mov %rax, vit_input_length(%rip)
+    xor %eax, %eax
+1:  lea vit_input(%rip), %rcx
+    movzbl (%rcx,%rax), %edx
+    inc %rax
+    mov %rax, vit_input_position(%rip)
+    mov %edx, %eax
+    ret
+2:  mov $-1, %eax
+    ret
+
+# Reads a line without its line break.
+vit_read_str:
+vit_read_line:
+    push %rbx
+    push %r12
+    mov vit_heap(%rip), %rbx
+    lea 8(%rbx), %r12
+    mov %r12, %rdi
+    call vit_reserve
+1:  call vit_getc
+    cmp $-1, %eax
+    je 2f
+    cmp $10, %eax
+    je 3f
+    push %rax
+    lea 1(%r12), %rdi
+    call vit_reserve
+    pop %rax
+    mov %al, (%r12)
+    inc %r12
+    jmp 1b
+2:  lea 8(%rbx), %rax
+    cmp %rax, %r12
+    jne 3f
+    lea vit_end_of_input(%rip), %rdi
+    mov $5, %esi
+    jmp vit_error
+3:  lea 8(%rbx), %rax
+    cmp %rax, %r12
+    je 4f
+    cmpb $13, -1(%r12)
+    jne 4f
+    dec %r12
+    jmp 3b
+4:  mov %r12, %rdx
+    sub %rax, %rdx
+    mov %rdx, (%rbx)
+    lea 7(%r12), %rdi
+    and $-8, %rdi
+    mov %rdi, vit_heap(%rip)
+    mov %rbx, %rax
+    pop %r12
+    pop %rbx
+    ret
+
+# Sets %rsi and %rdx to the start and end of the string at %rdi without the whitespace
+# around it.
+vit_trim:
+    lea 8(%rdi), %rsi
+    mov (%rdi), %rdx
+    add %rsi, %rdx
+1:  cmp %rdx, %rsi
+    je 3f
+    movzbl (%rsi), %eax
+    cmp $32, %eax
+    je 2f
+    sub $9, %eax
+    cmp $4, %eax
+    ja 4f
+2:  inc %rsi
+    jmp 1b
+4:  movzbl -1(%rdx), %eax
+    cmp $32, %eax
+    je 5f
+    sub $9, %eax
+    cmp $4, %eax
+    ja 3f
+5:  dec %rdx
+    jmp 4b
+3:  ret
+
+# Fails because the line at %rsi isn't what the message at %rdi expected.
+vit_invalid_input:
+    call vit_concat
+    mov %rax, %rdi
+    lea vit_quote_end(%rip), %rsi
+    call vit_concat
+    mov %rax, %rdi
+    mov $5, %esi
+    jmp vit_error
+
+vit_read_int:
+    call vit_read_line
+    push %rax
+    mov %rax, %rdi
+    call vit_trim
+    xor %r8d, %r8d
+    mov $0x80000000, %r9d
+    cmp %rdx, %rsi
+    je 5f
+    movzbl (%rsi), %eax
+    cmp $'+', %eax
+    je 1f
+    cmp $'-', %eax
+    jne 2f
+    mov $1, %r8d
+1:  inc %rsi
+2:  cmp %rdx, %rsi
+    je 5f
+    xor %eax, %eax
+3:  movzbl (%rsi), %ecx
+    sub $'0', %ecx
+    cmp $9, %ecx
+    ja 5f
+    imul $10, %rax, %rax
+    add %rcx, %rax
+    cmp %r9, %rax
+    ja 5f
+    inc %rsi
+    cmp %rdx, %rsi
+    jne 3b
+    test %r8d, %r8d
+    jz 4f
+    neg %eax
+    pop %rcx
+    ret
+4:  cmp %r9, %rax
+    je 5f
+    pop %rcx
+    ret
+5:  pop %rsi
+    lea vit_expected_integer(%rip), %rdi
+    jmp vit_invalid_input
+
+# Sets %eax to 1 when the %rcx bytes at %rsi are the string at %rdi, ignoring case.
+vit_matches:
+    xor %eax, %eax
+    cmp (%rdi), %rcx
+    jne 2f
+    xor %r9d, %r9d
+1:  cmp %rcx, %r9
+    je 3f
+    movzbl (%rsi,%r9), %r10d
+    or $0x20, %r10d
+    cmpb %r10b, 8(%rdi,%r9)
+    jne 2f
+    inc %r9
+    jmp 1b
+3:  mov $1, %eax
+2:  ret
+
+# Accepts what Rust's f64::from_str accepts. The first 18 significant digits are scaled by
+# a power of ten computed with the x87 unit.
+vit_read_float:
+    call vit_read_line
+    push %rax
+    push %rbx
+    sub $8, %rsp
+    mov %rax, %rdi
+    call vit_trim
+    xor %r8d, %r8d
+    cmp %rdx, %rsi
+    je 20f
+    movzbl (%rsi), %eax
+    cmp $'+', %eax
+    je 1f
+    cmp $'-', %eax
+    jne 2f
+    mov $1, %r8d
+1:  inc %rsi
+2:  mov %rdx, %rcx
+    sub %rsi, %rcx
+    lea vit_word_inf(%rip), %rdi
+    call vit_matches
+    test %eax, %eax
+    jnz 21f
+    lea vit_word_infinity(%rip), %rdi
+    call vit_matches
+    test %eax, %eax
+    jnz 21f
+    lea vit_word_nan(%rip), %rdi
+    call vit_matches
+    test %eax, %eax
+    jnz 22f
+    xor %eax, %eax
+    xor %edi, %edi
+    xor %r9d, %r9d
+    xor %r10d, %r10d
+    xor %r11d, %r11d
+    xor %ebx, %ebx
+3:  cmp %rdx, %rsi
+    je 10f
+    movzbl (%rsi), %ecx
+    sub $'0', %ecx
+    cmp $9, %ecx
+    ja 6f
+    inc %r10
+    cmp $18, %r11d
+    jb 4f
+    test %edi, %edi
+    jnz 5f
+    inc %r9
+    jmp 5f
+4:  imul $10, %rax, %rax
+    add %rcx, %rax
+    test %rax, %rax
+    jz 7f
+    inc %r11d
+7:  test %edi, %edi
+    jz 5f
+    dec %r9
+5:  inc %rsi
+    jmp 3b
+6:  cmp $'.' - '0', %ecx
+    jne 8f
+    test %edi, %edi
+    jnz 20f
+    mov $1, %edi
+    jmp 5b
+8:  add $'0', %ecx
+    or $0x20, %ecx
+    cmp $'e', %ecx
+    jne 20f
+    test %r10, %r10
+    jz 20f
+    inc %rsi
+    xor %edi, %edi
+    cmp %rdx, %rsi
+    je 20f
+    movzbl (%rsi), %ecx
+    cmp $'+', %ecx
+    je 9f
+    cmp $'-', %ecx
+    jne 11f
+    mov $1, %edi
+9:  inc %rsi
+11: cmp %rdx, %rsi
+    je 20f
+12: movzbl (%rsi), %ecx
+    sub $'0', %ecx
+    cmp $9, %ecx
+    ja 20f
+    imul $10, %rbx, %rbx
+    add %rcx, %rbx
+    mov $100000, %ecx
+    cmp %rcx, %rbx
+    cmova %rcx, %rbx
+    inc %rsi
+    cmp %rdx, %rsi
+    jne 12b
+    test %edi, %edi
+    jz 13f
+    neg %rbx
+13: add %rbx, %r9
+10: test %r10, %r10
+    jz 20f
+    test %rax, %rax
+    jz 23f
+    mov %rax, (%rsp)
+    fildll (%rsp)
+    mov %r9, %rcx
+    test %rcx, %rcx
+    jns 14f
+    neg %rcx
+14: fld1
+    fldl vit_ten(%rip)
+15: test $1, %rcx
+    jz 16f
+    fmul %st, %st(1)
+16: fmul %st, %st
+    shr %rcx
+    jnz 15b
+    fstp %st(0)
+    test %r9, %r9
+    js 17f
+    fmulp
+    jmp 18f
+17: fxch
+    fdiv %st(1), %st
+    fstp %st(1)
+18: fstpl (%rsp)
+    mov (%rsp), %rax
+    jmp 24f
+21: movabs $0x7ff0000000000000, %rax
+    jmp 24f
+22: movabs $0x7ff8000000000000, %rax
+    jmp 24f
+23: xor %eax, %eax
+24: test %r8d, %r8d
+    jz 25f
+    btc $63, %rax
+25: add $8, %rsp
+    pop %rbx
+    pop %rcx
+    ret
+20: add $8, %rsp
+    pop %rbx
+    pop %rsi
+    lea vit_expected_number(%rip), %rdi
+    jmp vit_invalid_input
+
+vit_concat:
+    push %rdi
+    push %rsi
+    mov (%rdi), %rdi
+    add (%rsi), %rdi
+    add $8, %rdi
+    call vit_alloc
+    pop %r9
+    pop %rsi
+    mov (%rsi), %rcx
+    mov %rcx, %rdx
+    add (%r9), %rdx
+    mov %rdx, (%rax)
+    mov %rax, %r8
+    add $8, %rsi
+    lea 8(%rax), %rdi
+    rep movsb
+    mov (%r9), %rcx
+    lea 8(%r9), %rsi
+    rep movsb
+    mov %r8, %rax
+    ret
+
+vit_str_eq:
+    mov (%rdi), %rcx
+    cmp (%rsi), %rcx
+    jne 1f
+    lea 8(%rdi), %rdi
+    lea 8(%rsi), %rsi
+    repe cmpsb
+    jne 1f
+    mov $1, %eax
+    ret
+1:  xor %eax, %eax
+    ret
+
+# The number of characters of a string: bytes that don't continue a UTF-8 sequence.
+vit_len:
+    mov (%rdi), %rcx
+    lea 8(%rdi), %rsi
+    xor %eax, %eax
+1:  test %rcx, %rcx
+    jz 3f
+    movzbl (%rsi), %edx
+    and $0xc0, %edx
+    cmp $0x80, %edx
+    je 2f
+    inc %eax
+2:  inc %rsi
+    dec %rcx
+    jmp 1b
+3:  ret
+
+vit_div:
+    test %esi, %esi
+    jz vit_division_by_zero
+    mov %edi, %eax
+    cmp $-1, %esi
+    je 1f
+    cltd
+    idiv %esi
+    ret
+1:  neg %eax
+    ret
+
+# A negative exponent gives the truncated result of 1 / base^-exponent.
+vit_pow:
+    test %esi, %esi
+    js 3f
+    mov $1, %eax
+1:  test %esi, %esi
+    jz 2f
+    test $1, %esi
+    jz 4f
+    imul %edi, %eax
+4:  imul %edi, %edi
+    shr %esi
+    jmp 1b
+2:  ret
+3:  test %edi, %edi
+    jz vit_division_by_zero
+    mov $1, %eax
+    cmp $1, %edi
+    je 2b
+    cmp $-1, %edi
+    jne 5f
+    test $1, %esi
+    jz 2b
+    mov $-1, %eax
+    ret
+5:  xor %eax, %eax
+    ret
+
+# Computes 2^(y * log2 |x|) with the x87 unit, after handling the cases where that formula
+# doesn't apply.
+vit_fpow:
+    sub $16, %rsp
+    movq %rdi, %xmm0
+    movq %rsi, %xmm1
+    xorpd %xmm3, %xmm3
+    ucomisd %xmm3, %xmm1
+    jp 1f
+    je 10f
+1:  ucomisd vit_one(%rip), %xmm0
+    jp 2f
+    je 10f
+2:  ucomisd %xmm0, %xmm0
+    jp 11f
+    ucomisd %xmm1, %xmm1
+    jp 11f
+    xor %r8d, %r8d
+    ucomisd %xmm3, %xmm0
+    jae 4f
+    mov %rsi, %rax
+    btr $63, %rax
+    movq %rax, %xmm2
+    ucomisd vit_two_53(%rip), %xmm2
+    jae 3f
+    cvttsd2si %xmm1, %rax
+    cvtsi2sd %rax, %xmm2
+    ucomisd %xmm1, %xmm2
+    jne 11f
+    and $1, %eax
+    mov %eax, %r8d
+3:  btr $63, %rdi
+    movq %rdi, %xmm0
+4:  ucomisd vit_one(%rip), %xmm0
+    je 12f
+    ucomisd %xmm3, %xmm0
+    je 13f
+    mov %rdi, (%rsp)
+    mov %rsi, 8(%rsp)
+    fldl 8(%rsp)
+    fldl (%rsp)
+    fyl2x
+    fldl vit_exponent_limit(%rip)
+    fcomi %st(1), %st
+    fcmovnb %st(1), %st
+    fstp %st(1)
+    fldl vit_negative_exponent_limit(%rip)
+    fcomi %st(1), %st
+    fcmovb %st(1), %st
+    fstp %st(1)
+    fld %st(0)
+    frndint
+    fxch
+    fsub %st(1), %st
+    f2xm1
+    fld1
+    faddp
+    fscale
+    fstp %st(1)
+    fstpl (%rsp)
+    mov (%rsp), %rax
+    jmp 14f
+10: mov vit_one(%rip), %rax
+    add $16, %rsp
+    ret
+11: movabs $0x7ff8000000000000, %rax
+    add $16, %rsp
+    ret
+12: mov vit_one(%rip), %rax
+    jmp 14f
+13: xor %eax, %eax
+    ucomisd %xmm3, %xmm1
+    ja 14f
+    movabs $0x7ff0000000000000, %rax
+14: test %r8d, %r8d
+    jz 15f
+    btc $63, %rax
+15: add $16, %rsp
+    ret
+
+vit_abs:
+    mov %edi, %eax
+    neg %eax
+    cmovs %edi, %eax
+    ret
+
+vit_min:
+    mov %esi, %eax
+    cmp %esi, %edi
+    cmovl %edi, %eax
+    ret
+
+vit_max:
+    mov %edi, %eax
+    cmp %esi, %edi
+    cmovl %esi, %eax
+    ret
+
+vit_fmin:
+    movq %rdi, %xmm0
+    movq %rsi, %xmm1
+    mov %rsi, %rax
+    ucomisd %xmm0, %xmm1
+    cmova %rdi, %rax
+    ret
+
+vit_fmax:
+    movq %rdi, %xmm0
+    movq %rsi, %xmm1
+    mov %rdi, %rax
+    ucomisd %xmm0, %xmm1
+    cmova %rsi, %rax
+    ret
+
+# Truncates towards zero. Out of range values saturate and NaN becomes 0.
+vit_to_int:
+    movq %rdi, %xmm0
+    xor %eax, %eax
+    ucomisd %xmm0, %xmm0
+    jp 1f
+    mov $0x7fffffff, %eax
+    ucomisd vit_int_max(%rip), %xmm0
+    jae 1f
+    mov $0x80000000, %eax
+    ucomisd vit_int_min(%rip), %xmm0
+    jbe 1f
+    cvttsd2si %xmm0, %eax
+1:  ret
+
+vit_floor:
+    call vit_to_int
+    cmp $0x80000000, %eax
+    je 1f
+    cvtsi2sd %eax, %xmm1
+    movq %rdi, %xmm0
+    ucomisd %xmm0, %xmm1
+    jbe 1f
+    dec %eax
+1:  ret
+
+# Knuth's MMIX linear congruential generator.
+vit_random:
+    test %edi, %edi
+    jle 1f
+    movabs $6364136223846793005, %rax
+    imulq vit_seed(%rip), %rax
+    movabs $1442695040888963407, %rcx
+    add %rcx, %rax
+    mov %rax, vit_seed(%rip)
+    shr $33, %rax
+    xor %edx, %edx
+    mov %edi, %ecx
+    div %rcx
+    mov %edx, %eax
+    ret
+1:  lea vit_invalid_bound(%rip), %rdi
+    mov $2, %esi
+    jmp vit_error
+
+    .data
+    .align 8
+vit_seed:
+    .quad VIT_SEED
+
+    .section .rodata
+    .align 8
+vit_million:
+    .double 1e6
+vit_half:
+    .double 0.5
+vit_one:
+    .double 1.0
+vit_ten:
+    .double 10.0
+vit_two_53:
+    .double 9007199254740992.0
+vit_int_max:
+    .double 2147483647.0
+vit_int_min:
+    .double -2147483648.0
+vit_exponent_limit:
+    .double 2048.0
+vit_negative_exponent_limit:
+    .double -2048.0
+    vit_string vit_empty, ""
+    vit_string vit_newline, "\n"
+    vit_string vit_true, "true"
+    vit_string vit_false, "false"
+    vit_string vit_minus, "-"
+    vit_string vit_point, "."
+    vit_string vit_nan, "nan"
+    vit_string vit_inf, "inf"
+    vit_string vit_negative_inf, "-inf"
+    vit_string vit_word_inf, "inf"
+    vit_string vit_word_infinity, "infinity"
+    vit_string vit_word_nan, "nan"
+    vit_string vit_quote_end, "'."
+    vit_string vit_expected_integer, "invalid input: expected an integer, found '"
+    vit_string vit_expected_number, "invalid input: expected a number, found '"
+    vit_string vit_end_of_input, "unexpected end of input."
+    vit_string vit_division_by_zero_message, "division by zero."
+    vit_string vit_invalid_bound, "random expects a positive bound."
+    vit_string vit_out_of_memory_message, "out of memory."
+
+    .bss
+    .align 8
+vit_heap:
+    .zero 8
+vit_heap_end:
+    .zero 8
+vit_output_length:
+    .zero 8
+vit_output:
+    .zero 4096
+vit_input_position:
+    .zero 8
+vit_input_length:
+    .zero 8
+vit_input:
+    .zero 4096
+"#;
+
+struct Generator {
+    code: String,
+    strings: HashMap<String, usize>, // Number of the label of each string constant.
+    data: String,
+    cells: u32,
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        self.code.push_str("    ");
+        self.code.push_str(line);
+        self.code.push('\n');
+    }
+
+    fn label(&mut self, label: &str) {
+        self.code.push_str(label);
+        self.code.push_str(":\n");
+    }
+
+    fn scope(&mut self, block: &Block) {
+        self.declare(&block.variables);
+        for statement in &block.statements {
+            self.statement(statement);
+        }
+    }
+
+    // Variables start as 0, 0.0 or the empty string each time their scope is entered.
+    fn declare(&mut self, variables: &[Variable]) {
+        for variable in variables {
+            let cell = cell(variable.address);
+            if variable.ty == Type::Str {
+                self.line("lea vit_empty(%rip), %rax");
+                self.line(&format!("mov %rax, {cell} # {}", variable.name));
+            } else {
+                self.line(&format!("movq $0, {cell} # {}", variable.name));
+            }
+            self.cells = self.cells.max(variable.address + 1);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Store(address, value) => {
+                self.expression(value);
+                self.line(&format!("mov %rax, {}", cell(*address)));
+            }
+            Statement::Read(address, ty) => {
+                self.line(&format!("call vit_read_{}", suffix(*ty)));
+                self.line(&format!("mov %rax, {}", cell(*address)));
+            }
+            Statement::Write(value) => {
+                self.expression(value);
+                self.line("mov %rax, %rdi");
+                self.line(&format!("call vit_write_{}", suffix(value.ty)));
+            }
+            Statement::If(label, condition, if_block, else_block) => {
+                self.expression(condition);
+                self.line("test %eax, %eax");
+                self.line(&format!(
+                    "jz .L{}{label}",
+                    if else_block.is_some() { "F" } else { "E" }
+                ));
+                self.scope(if_block);
+                if let Some(e_block) = else_block {
+                    self.line(&format!("jmp .LE{label}"));
+                    self.label(&format!(".LF{label}"));
+                    self.scope(e_block);
+                }
+                self.label(&format!(".LE{label}"));
+            }
+            Statement::Loop(label, block) => {
+                self.label(&format!(".LL{label}"));
+                self.scope(block);
+                self.line(&format!("jmp .LL{label}"));
+                self.label(&format!(".LE{label}"));
+            }
+            Statement::Until(label, block, condition) => {
+                self.label(&format!(".LL{label}"));
+                self.scope(block);
+                self.expression(condition);
+                self.line("test %eax, %eax");
+                self.line(&format!("jz .LL{label}"));
+                self.label(&format!(".LE{label}"));
+            }
+            Statement::Break(label) => self.line(&format!("jmp .LE{label}")),
+            Statement::Block(block) => self.scope(block),
+            Statement::Located(_, code) => {
+                for statement in code {
+                    self.statement(statement);
+                }
+            }
+        }
+    }
+
+    // Leaves the value of `expr` in %rax, evaluating its operands from left to right.
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Constant(constant) => {
+                let line = match constant {
+                    Constant::Int(n) => format!("mov ${n}, %eax"),
+                    Constant::Float(n) => format!("movabs ${:#x}, %rax", n.to_bits()),
+                    Constant::Str(s) => format!("lea {}(%rip), %rax", self.string(s)),
+                };
+                self.line(&line);
+            }
+            ExprKind::Load(address) => self.line(&format!("mov {}, %rax", cell(*address))),
+            ExprKind::Binary(left, op, right) => {
+                self.operands(left, right);
+                if left.ty == Type::Float || right.ty == Type::Float {
+                    self.float(left.ty, "%rdi", "%xmm0");
+                    self.float(right.ty, "%rsi", "%xmm1");
+                    self.lines(&float_binary(op));
+                } else if left.ty == Type::Str {
+                    self.lines(&string_binary(op));
+                } else {
+                    self.lines(&int_binary(op));
+                }
+            }
+            ExprKind::Convert(value) => {
+                self.expression(value);
+                self.line("mov %rax, %rdi");
+                self.convert(value.ty, expr.ty);
+            }
+            ExprKind::Call(name, args) => {
+                match args.as_slice() {
+                    [arg] => {
+                        self.expression(arg);
+                        self.line("mov %rax, %rdi");
+                    }
+                    [left, right] => self.operands(left, right),
+                    _ => (),
+                }
+                let arg = args.first().map_or(Type::Unknown, |arg| arg.ty);
+
+                match (*name, expr.ty) {
+                    ("abs", Type::Float) => self.lines("mov %rdi, %rax\nbtr $63, %rax"),
+                    ("min" | "max", Type::Float) => self.line(&format!("call vit_f{name}")),
+                    ("sqrt", _) => {
+                        self.float(arg, "%rdi", "%xmm0");
+                        self.lines("sqrtsd %xmm0, %xmm0\nmovq %xmm0, %rax");
+                    }
+                    ("floor", _) if arg == Type::Int => self.line("mov %edi, %eax"),
+                    ("int", _) => self.convert(arg, Type::Int),
+                    ("float", _) => self.convert(arg, Type::Float),
+                    (name, _) => self.line(&format!("call vit_{name}")),
+                }
+            }
+        }
+    }
+
+    fn lines(&mut self, lines: &str) {
+        for line in lines.lines() {
+            self.line(line);
+        }
+    }
+
+    // Evaluates two operands into %rdi and %rsi.
+    fn operands(&mut self, left: &Expr, right: &Expr) {
+        self.expression(left);
+        self.line("push %rax");
+        self.expression(right);
+        self.line("mov %rax, %rsi");
+        self.line("pop %rdi");
+    }
+
+    // Moves the number of type `ty` in `register` to `xmm` as a float.
+    fn float(&mut self, ty: Type, register: &str, xmm: &str) {
+        if ty == Type::Int {
+            let register = register.replace('r', "e");
+            self.line(&format!("cvtsi2sd {register}, {xmm}"));
+        } else {
+            self.line(&format!("movq {register}, {xmm}"));
+        }
+    }
+
+    // Converts the number in %rdi into %rax.
+    fn convert(&mut self, from: Type, to: Type) {
+        match (from, to) {
+            (Type::Float, Type::Int) => self.line("call vit_to_int"),
+            (Type::Int, Type::Float) => self.lines("cvtsi2sd %edi, %xmm0\nmovq %xmm0, %rax"),
+            _ => self.line("mov %rdi, %rax"),
+        }
+    }
+
+    // Adds a string to the read-only data, once, and returns its label.
+    fn string(&mut self, s: &str) -> String {
+        let count = self.strings.len();
+        let number = *self.strings.entry(s.to_string()).or_insert(count);
+        let label = format!(".Lstr{number}");
+
+        if number == count {
+            let escaped: String = s
+                .bytes()
+                .map(|byte| match byte {
+                    b'"' | b'\\' => format!("\\{}", byte as char),
+                    b' '..=b'~' => (byte as char).to_string(),
+                    _ => format!("\\{byte:03o}"),
+                })
+                .collect();
+            self.data
+                .push_str(&format!("    vit_string {label}, \"{escaped}\"\n"));
+        }
+        label
+    }
+}
+
+fn cell(address: u32) -> String {
+    format!("vit_variables+{}(%rip)", address * 8)
+}
+
+fn suffix(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "float",
+        Type::Str => "str",
+        Type::Bool => "bool",
+        _ => "int",
+    }
+}
+
+fn int_binary(op: &Opcode) -> String {
+    let condition = match op {
+        Opcode::Add => return "lea (%rdi,%rsi), %eax".to_string(),
+        Opcode::Sub => return "mov %edi, %eax\nsub %esi, %eax".to_string(),
+        Opcode::Mul => return "mov %edi, %eax\nimul %esi, %eax".to_string(),
+        Opcode::Div => return "call vit_div".to_string(),
+        Opcode::Exp => return "call vit_pow".to_string(),
+        Opcode::And => return "mov %edi, %eax\nand %esi, %eax".to_string(),
+        Opcode::Or => return "mov %edi, %eax\nor %esi, %eax".to_string(),
+        Opcode::Eq => "e",
+        Opcode::Neq => "ne",
+        Opcode::Grt => "g",
+        Opcode::Let => "l",
+        Opcode::Geq => "ge",
+        _ => "le",
+    };
+    format!("cmp %esi, %edi\nset{condition} %al\nmovzbl %al, %eax")
+}
+
+// Operates on %xmm0 and %xmm1. Comparisons with NaN are false, except !=.
+fn float_binary(op: &Opcode) -> String {
+    let (compare, condition) = match op {
+        Opcode::Add => return "addsd %xmm1, %xmm0\nmovq %xmm0, %rax".to_string(),
+        Opcode::Sub => return "subsd %xmm1, %xmm0\nmovq %xmm0, %rax".to_string(),
+        Opcode::Mul => return "mulsd %xmm1, %xmm0\nmovq %xmm0, %rax".to_string(),
+        Opcode::Div => return "divsd %xmm1, %xmm0\nmovq %xmm0, %rax".to_string(),
+        Opcode::Exp => {
+            return "movq %xmm0, %rdi\nmovq %xmm1, %rsi\ncall vit_fpow".to_string();
+        }
+        Opcode::Eq => {
+            return "ucomisd %xmm1, %xmm0\nsete %al\nsetnp %cl\nand %cl, %al\nmovzbl %al, %eax"
+                .to_string();
+        }
+        Opcode::Neq => {
+            return "ucomisd %xmm1, %xmm0\nsetne %al\nsetp %cl\nor %cl, %al\nmovzbl %al, %eax"
+                .to_string();
+        }
+        Opcode::Grt => ("%xmm1, %xmm0", "a"),
+        Opcode::Geq => ("%xmm1, %xmm0", "ae"),
+        Opcode::Let => ("%xmm0, %xmm1", "a"),
+        _ => ("%xmm0, %xmm1", "ae"),
+    };
+    format!("ucomisd {compare}\nset{condition} %al\nmovzbl %al, %eax")
+}
+
+fn string_binary(op: &Opcode) -> String {
+    match op {
+        Opcode::Add => "call vit_concat",
+        Opcode::Eq => "call vit_str_eq",
+        _ => "call vit_str_eq\nxor $1, %eax",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::Write,
+        process::{self, Command, Stdio},
+    };
+
+    use super::*;
+    use crate::{backend::tests::cases, vit};
+
+    // Assembles and links each program with the system's binutils and checks that it
+    // behaves like the vm: same output, and the same exit code when the vm fails.
+    #[test]
+    fn behaves_like_the_vm() {
+        let directory = env::temp_dir().join(format!("vit-x86_64-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        for case in cases() {
+            let file = directory.join(format!("{}.s", case.name));
+            let object = directory.join(format!("{}.o", case.name));
+            let executable = directory.join(&case.name);
+            fs::write(&file, generate(&vit::lower(case.modules.clone()).unwrap())).unwrap();
+
+            let status = Command::new("as")
+                .arg("-o")
+                .arg(&object)
+                .arg(&file)
+                .status()
+                .unwrap();
+            assert!(status.success(), "{case}: the assembler failed.");
+            let status = Command::new("ld")
+                .arg("-o")
+                .arg(&executable)
+                .arg(&object)
+                .status()
+                .unwrap();
+            assert!(status.success(), "{case}: the linker failed.");
+
+            let mut child = Command::new(&executable)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            // The program may stop before reading all of it.
+            let _ = child.stdin.take().unwrap().write_all(case.input.as_bytes());
+            let output = child.wait_with_output().unwrap();
+
+            let (expected, code) = case.expected();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "{case}");
+            assert_eq!(output.status.code(), Some(code), "{case}");
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
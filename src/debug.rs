@@ -0,0 +1,428 @@
+use std::{
+    collections::BTreeSet,
+    error,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    modules::Module,
+    vit::{self, DebugInfo},
+    vm::{Machine, Process, RuntimeError},
+};
+
+// Runs the program in `modules` under the control of commands read from `input`, one a
+// line. `source` is the text of the main module, the only one with lines to stop at. The
+// program starts stopped at its first statement and reads its own input from the lines
+// after the command that resumed it.
+//
+//   break <line>     stops when a statement on the line is about to run
+//   delete <line>    removes the breakpoint on the line
+//   continue, c      runs until a breakpoint, a change of a watched variable or the end
+//   step, s          runs until the next statement, entering ifs and loops
+//   next, n          runs until the next statement outside the current one
+//   watch <name>     stops whenever the value of the variable changes
+//   print <name>     shows the value of the variable
+//   scopes           shows the variables in scope, from the outermost scope in
+//   quit, q          stops debugging
+pub fn run(
+    source: &str,
+    modules: Vec<Module>,
+    seed: u64,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<(), Box<dyn error::Error>> {
+    let (code, info) = vit::link_debug(modules).map_err(crate::semantic)?;
+    let machine = Machine::load(&code)?.with_seed(seed);
+    let mut debugger = Debugger {
+        source,
+        info,
+        process: machine.start(),
+        breakpoints: BTreeSet::new(),
+        watches: vec![],
+        line: None,
+    };
+
+    let mut events = vec![];
+    if !debugger.stop_at(None, &|_, _| true) {
+        debugger.resume(|_, _| true, &mut events, input, output)?;
+    }
+    loop {
+        for event in events.drain(..) {
+            writeln!(output, "{event}")?;
+        }
+        if debugger.process.finished() {
+            writeln!(output, "the program finished.")?;
+            break;
+        }
+        writeln!(output, "{}", debugger.location())?;
+
+        write!(output, "(vit) ")?;
+        output.flush()?;
+        let mut command = String::new();
+        if input.read_line(&mut command)? == 0 {
+            break;
+        }
+
+        let (command, argument) = command
+            .trim()
+            .split_once(' ')
+            .map_or((command.trim(), ""), |(c, a)| (c, a.trim()));
+        // Errors of the program end it, but mistakes in commands don't.
+        let result = match command {
+            "break" | "b" => debugger.add_breakpoint(argument, &mut events),
+            "delete" => debugger.delete_breakpoint(argument, &mut events),
+            "continue" | "c" => Ok(debugger.resume(
+                |debugger, line| {
+                    let line = debugger.line_number(line);
+                    debugger.breakpoints.contains(&line)
+                },
+                &mut events,
+                input,
+                output,
+            )?),
+            "step" | "s" => Ok(debugger.step(&mut events, input, output)?),
+            "next" | "n" => {
+                let current = debugger.line;
+                Ok(debugger.resume(
+                    |debugger, line| current.is_none_or(|current| !debugger.nested(line, current)),
+                    &mut events,
+                    input,
+                    output,
+                )?)
+            }
+            "watch" => debugger.watch(argument, &mut events),
+            "print" | "p" => debugger
+                .variable(argument)
+                .map(|(address, value)| events.push(format!("{argument} #{address} = {value}"))),
+            "scopes" => {
+                events.extend(debugger.scopes());
+                Ok(())
+            }
+            "quit" | "q" => break,
+            "" => Ok(()),
+            _ => Err(format!("unknown command: {command}.")),
+        };
+        if let Err(message) = result {
+            events.push(format!("error: {message}"));
+        }
+    }
+    Ok(output.flush()?)
+}
+
+struct Debugger<'a> {
+    source: &'a str,
+    info: DebugInfo,
+    process: Process<'a>,
+    breakpoints: BTreeSet<usize>,          // Line numbers.
+    watches: Vec<(String, usize, String)>, // Name, address and last value shown.
+    line: Option<usize>,                   // Index in `info.lines` of the current statement.
+}
+
+impl Debugger<'_> {
+    // Runs the program until an instruction starts a statement that `stop` accepts, a
+    // watched variable changes or the program ends.
+    fn resume(
+        &mut self,
+        stop: impl Fn(&Self, usize) -> bool,
+        events: &mut Vec<String>,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        while !self.process.finished() {
+            let previous = self.process.pc();
+            self.process.step(input, output)?;
+            if self.check_watches(events) {
+                self.line = self.containing(previous);
+                return Ok(());
+            }
+            if self.stop_at(Some(previous), &stop) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    // Stops at the outermost statement that starts with the next instruction and that
+    // `stop` accepts, if there's one. Jumping back to the start of a loop from its end
+    // doesn't start the loop again, so it only starts the statements in its body.
+    fn stop_at(&mut self, previous: Option<usize>, stop: &impl Fn(&Self, usize) -> bool) -> bool {
+        let pc = self.process.pc();
+        let line = (0..self.info.lines.len())
+            .filter(|&line| {
+                let code = &self.info.lines[line].code;
+                code.start == pc
+                    && !code.is_empty()
+                    && !previous.is_some_and(|previous| code.contains(&previous))
+            })
+            .find(|&line| stop(self, line));
+        self.line = line.or(self.line);
+        line.is_some()
+    }
+
+    // Enters the statement nested in the current one that starts with the same
+    // instruction, if there's one. Otherwise runs to the next statement.
+    fn step(
+        &mut self,
+        events: &mut Vec<String>,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        let pc = self.process.pc();
+        if let Some(current) = self
+            .line
+            .filter(|&line| self.info.lines[line].code.start == pc)
+        {
+            let inner = (current + 1..self.info.lines.len())
+                .find(|&line| self.info.lines[line].code.start == pc && self.nested(line, current));
+            if let Some(inner) = inner {
+                self.line = Some(inner);
+                return Ok(());
+            }
+        }
+        self.resume(|_, _| true, events, input, output)
+    }
+
+    // Whether the code of `line` is part of the code of `other`, and isn't all of it.
+    fn nested(&self, line: usize, other: usize) -> bool {
+        let (inner, outer) = (&self.info.lines[line].code, &self.info.lines[other].code);
+        line != other && outer.start <= inner.start && inner.end <= outer.end
+    }
+
+    // The innermost statement whose code includes the instruction at `pc`.
+    fn containing(&self, pc: usize) -> Option<usize> {
+        (0..self.info.lines.len())
+            .rev()
+            .find(|&line| self.info.lines[line].code.contains(&pc))
+    }
+
+    fn line_number(&self, line: usize) -> usize {
+        self.info.lines[line].span.line(self.source)
+    }
+
+    fn location(&self) -> String {
+        match self.line {
+            Some(line) => {
+                let number = self.line_number(line);
+                let text = self.source.lines().nth(number - 1).unwrap_or_default();
+                format!("line {number}: {}", text.trim())
+            }
+            None => format!("instruction {}", self.process.pc()),
+        }
+    }
+
+    fn add_breakpoint(&mut self, argument: &str, events: &mut Vec<String>) -> Result<(), String> {
+        let number = parse_line(argument)?;
+        if !(0..self.info.lines.len()).any(|line| self.line_number(line) == number) {
+            return Err(format!("no statement starts at line {number}."));
+        }
+        self.breakpoints.insert(number);
+        events.push(format!("breakpoint at line {number}."));
+        Ok(())
+    }
+
+    fn delete_breakpoint(
+        &mut self,
+        argument: &str,
+        events: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let number = parse_line(argument)?;
+        if !self.breakpoints.remove(&number) {
+            return Err(format!("no breakpoint at line {number}."));
+        }
+        events.push(format!("deleted the breakpoint at line {number}."));
+        Ok(())
+    }
+
+    fn watch(&mut self, name: &str, events: &mut Vec<String>) -> Result<(), String> {
+        let (address, value) = self.variable(name)?;
+        events.push(format!("watching {name} #{address} = {value}"));
+        self.watches.push((name.to_string(), address, value));
+        Ok(())
+    }
+
+    // Reports the watched variables whose value changed, and whether there were any.
+    fn check_watches(&mut self, events: &mut Vec<String>) -> bool {
+        let mut changed = false;
+        for (name, address, last) in &mut self.watches {
+            let value = self.process.load(*address).quoted();
+            if value != *last {
+                events.push(format!("{name} #{address}: {last} -> {value}"));
+                *last = value;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // The address and value of the variable called `name` in the innermost scope where
+    // there's one.
+    fn variable(&self, name: &str) -> Result<(usize, String), String> {
+        let pc = self.process.pc();
+        self.info
+            .scopes
+            .iter()
+            .rev()
+            .filter(|scope| scope.code.contains(&pc))
+            .find_map(|scope| scope.variables.iter().find(|v| v.name == name))
+            .map(|variable| {
+                let address = variable.address as usize;
+                (address, self.process.load(address).quoted())
+            })
+            .ok_or_else(|| format!("unknown variable: {name}."))
+    }
+
+    // A line for each scope around the next instruction that has variables, from the
+    // outermost scope in.
+    fn scopes(&self) -> Vec<String> {
+        let pc = self.process.pc();
+        self.info
+            .scopes
+            .iter()
+            .filter(|scope| scope.code.contains(&pc) && !scope.variables.is_empty())
+            .enumerate()
+            .map(|(depth, scope)| {
+                let variables: Vec<String> = scope
+                    .variables
+                    .iter()
+                    .map(|variable| {
+                        let value = self.process.load(variable.address as usize).quoted();
+                        format!(
+                            "{} #{} {} = {value}",
+                            variable.name, variable.address, variable.ty
+                        )
+                    })
+                    .collect();
+                format!("scope {depth}: {}", variables.join(", "))
+            })
+            .collect()
+    }
+}
+
+fn parse_line(argument: &str) -> Result<usize, String> {
+    argument
+        .parse()
+        .map_err(|_| format!("expected a line number, found '{argument}'."))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::modules;
+
+    fn debug(source: &str, commands: &str) -> Result<String, String> {
+        let modules = modules::load(Path::new("main.vit"), |_| Ok(source.to_string()))?;
+        let mut output = vec![];
+        run(source, modules, 0, &mut commands.as_bytes(), &mut output)
+            .map_err(|e| e.to_string())?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    const COUNT: &str = "let n = 3;
+let total = 0;
+loop {
+    if n == 0 {
+        break;
+    }
+    total = total + n;
+    n = n - 1;
+}
+write total;
+";
+
+    #[test]
+    fn stepping() {
+        assert_eq!(
+            debug(COUNT, "s\ns\ns\ns\nn\ns\ns\nn\nn\nc\n"),
+            Ok("line 1: let n = 3;
+(vit) line 2: let total = 0;
+(vit) line 3: loop {
+(vit) line 4: if n == 0 {
+(vit) line 7: total = total + n;
+(vit) line 8: n = n - 1;
+(vit) line 4: if n == 0 {
+(vit) line 7: total = total + n;
+(vit) line 8: n = n - 1;
+(vit) line 4: if n == 0 {
+(vit) 6the program finished.
+"
+            .to_string())
+        );
+        // Stepping over the loop runs all of it.
+        assert_eq!(
+            debug(COUNT, "n\nn\nn\nq\n"),
+            Ok("line 1: let n = 3;
+(vit) line 2: let total = 0;
+(vit) line 3: loop {
+(vit) line 10: write total;
+(vit) "
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn breakpoints() {
+        assert_eq!(
+            debug(COUNT, "b 5\nb 6\nc\nprint total\ndelete 5\nc\n"),
+            Ok("line 1: let n = 3;
+(vit) breakpoint at line 5.
+line 1: let n = 3;
+(vit) error: no statement starts at line 6.
+line 1: let n = 3;
+(vit) line 5: break;
+(vit) total #1 = 6
+line 5: break;
+(vit) deleted the breakpoint at line 5.
+line 5: break;
+(vit) 6the program finished.
+"
+            .to_string())
+        );
+    }
+
+    #[test]
+    fn watches() {
+        assert_eq!(
+            debug(COUNT, "n\nn\nwatch total\nc\nc\nscopes\nq\n"),
+            Ok("line 1: let n = 3;
+(vit) line 2: let total = 0;
+(vit) line 3: loop {
+(vit) watching total #1 = 0
+line 3: loop {
+(vit) total #1: 0 -> 3
+line 7: total = total + n;
+(vit) total #1: 3 -> 5
+line 7: total = total + n;
+(vit) scope 0: n #0 int = 2, total #1 int = 5
+line 7: total = total + n;
+(vit) "
+                .to_string())
+        );
+        assert_eq!(
+            debug(
+                "let a = 1;\nif a > 0 {\n    let b = 'x';\n    write b;\n}\n",
+                "s\ns\ns\nscopes\nprint c\nq\n"
+            ),
+            Ok("line 1: let a = 1;
+(vit) line 2: if a > 0 {
+(vit) line 3: let b = 'x';
+(vit) line 4: write b;
+(vit) scope 0: a #0 int = 1
+scope 1: b #1 str = 'x'
+line 4: write b;
+(vit) error: unknown variable: c.
+line 4: write b;
+(vit) "
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(
+            debug("let a = 0;\nlet b = 1 / a;\n", "c\n"),
+            Err("division by zero.".to_string())
+        );
+    }
+}
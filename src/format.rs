@@ -0,0 +1,337 @@
+use crate::{
+    ast::{Spanned, Statement},
+    parser::Parser,
+};
+
+const INDENT: &str = "    ";
+
+// Formats a program in the canonical layout: one statement per line, four spaces of
+// indentation per block, single spaces around operators and only the parentheses the
+// grammar needs. Comments are kept: those on a line of their own stay before the
+// statement that follows them, and those after code stay at the end of its line. Those
+// inside a statement, which is written on one line, go on lines of their own before it.
+// Single blank lines between statements are kept too.
+//
+// Formatting its own output gives it back unchanged.
+pub fn format(source: &str) -> Result<String, String> {
+    let program = Parser::new().parse(source)?;
+    let mut formatter = Formatter {
+        source,
+        comments: comments(source),
+        next: 0,
+        position: 0,
+        opened: false,
+        lines: vec![],
+    };
+
+    for statement in &program {
+        formatter.statement(statement, 0);
+    }
+    formatter.comments_before(source.len(), 0);
+
+    Ok(formatter
+        .lines
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect())
+}
+
+// A comment, from its `//` up to the end of its line. It trails code when the line has
+// some before it.
+struct Comment {
+    start: usize,
+    end: usize,
+    trailing: bool,
+}
+
+// Finds the comments in `source`, skipping the `//` inside strings and module paths.
+fn comments(source: &str) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut quote = None;
+    let mut line_start = 0;
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (_, '\n') => line_start = i + 1,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '/') if matches!(chars.peek(), Some((_, '/'))) => {
+                let end = source[i..].find('\n').map_or(source.len(), |n| i + n);
+                comments.push(Comment {
+                    start: i,
+                    end,
+                    trailing: !source[line_start..i].trim().is_empty(),
+                });
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+            }
+            _ => (),
+        }
+    }
+    comments
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: Vec<Comment>,
+    next: usize,     // The first comment not written yet.
+    position: usize, // The end of the source written so far.
+    opened: bool,    // Whether the last line opened a block.
+    lines: Vec<String>,
+}
+
+impl Formatter<'_> {
+    fn push(&mut self, indent: usize, text: String) {
+        self.lines.push(INDENT.repeat(indent) + &text);
+        self.opened = text.ends_with('{');
+    }
+
+    fn append(&mut self, text: &str) {
+        if let Some(line) = self.lines.last_mut() {
+            line.push_str(text);
+            self.opened = line.ends_with('{');
+        }
+    }
+
+    // Keeps a blank line before what starts at `start` if the source had one, unless it
+    // would open a block or the file.
+    fn gap(&mut self, start: usize) {
+        let blank = self.source[self.position.min(start)..start]
+            .matches('\n')
+            .count()
+            > 1;
+        if blank && !self.lines.is_empty() && !self.opened {
+            self.lines.push(String::new());
+        }
+    }
+
+    // Writes the comments that come before `end` in the source.
+    fn comments_before(&mut self, end: usize, indent: usize) {
+        while let Some(comment) = self.comments.get(self.next).filter(|c| c.start < end) {
+            let (start, end, trailing) = (comment.start, comment.end, comment.trailing);
+            let text = self.source[start..end].trim_end().to_string();
+            self.next += 1;
+
+            if trailing && !self.lines.is_empty() {
+                self.append(&format!(" {text}"));
+            } else {
+                self.gap(start);
+                self.push(indent, text);
+            }
+            self.position = self.position.max(end);
+        }
+    }
+
+    // Writes the comments inside a statement, from the current position to `end`, on lines
+    // of their own.
+    fn inner_comments(&mut self, end: usize, indent: usize) {
+        while let Some(comment) = self.comments.get(self.next).filter(|c| c.start < end) {
+            let text = self.source[comment.start..comment.end]
+                .trim_end()
+                .to_string();
+            self.next += 1;
+            self.push(indent, text);
+        }
+    }
+
+    // Writes what follows the closing brace of a block, as `else {`, on the line of the
+    // brace unless comments come before it in the source. Its keyword is at `keyword` and
+    // it ends at `end`.
+    fn after_block(&mut self, keyword: usize, end: usize, text: String, indent: usize) {
+        let next = self.next;
+        self.comments_before(keyword, indent);
+        self.inner_comments(end, indent);
+        if self.next == next {
+            self.append(&format!(" {text}"));
+        } else {
+            self.push(indent, text);
+        }
+    }
+
+    fn statement(&mut self, statement: &Spanned<Statement>, indent: usize) {
+        let Spanned { node, span } = statement;
+        self.comments_before(span.start, indent);
+        self.gap(span.start);
+        self.position = span.start;
+
+        match node {
+            Statement::If(predicate, then, otherwise) => {
+                self.inner_comments(self.find(span.start, '{'), indent);
+                self.push(indent, format!("if {predicate} {{"));
+                self.block(then, '{', indent);
+                if let Some(otherwise) = otherwise {
+                    let keyword = self.find(self.position, 'e');
+                    let open = self.find(keyword, '{');
+                    self.after_block(keyword, open, "else {".to_string(), indent);
+                    self.block(otherwise, '{', indent);
+                }
+            }
+            Statement::Until(predicate, body) => {
+                self.push(indent, "do {".to_string());
+                self.block(body, '{', indent);
+                let keyword = self.find(self.position, 'u');
+                let text = format!("until {predicate};");
+                self.after_block(keyword, span.end, text, indent);
+            }
+            Statement::Loop(body) => {
+                self.inner_comments(self.find(span.start, '{'), indent);
+                self.push(indent, "loop {".to_string());
+                self.block(body, '{', indent);
+            }
+            _ => {
+                self.inner_comments(span.end, indent);
+                self.push(indent, node.to_string());
+            }
+        }
+        self.position = span.end;
+    }
+
+    // Writes the statements of a block and its closing brace, the opening one being
+    // already written. The block starts at the first `open` after the current position.
+    fn block(&mut self, statements: &[Spanned<Statement>], open: char, indent: usize) {
+        let (lines, next) = (self.lines.len(), self.next);
+        self.position = self.find(self.position, open) + 1;
+        self.opened = true;
+
+        for statement in statements {
+            self.statement(statement, indent + 1);
+        }
+        let close = self.find(self.position, '}');
+        self.comments_before(close, indent + 1);
+        self.position = close + 1;
+
+        if self.lines.len() == lines && self.next == next {
+            self.append("}");
+        } else {
+            self.push(indent, "}".to_string());
+        }
+    }
+
+    // The index of the first `c` in the source from `from` on, outside strings and comments.
+    fn find(&self, from: usize, c: char) -> usize {
+        let mut quote = None;
+        let mut comment = false;
+        for (i, d) in self.source[from..].char_indices() {
+            match (quote, d) {
+                (_, '\n') if comment => comment = false,
+                _ if comment => (),
+                (Some(q), d) if d == q => quote = None,
+                (Some(_), _) => (),
+                (None, '\'' | '"') => quote = Some(d),
+                (None, '/') if self.source[from + i..].starts_with("//") => comment = true,
+                (None, d) if d == c => return from + i,
+                _ => (),
+            }
+        }
+        self.source.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        assert_eq!(
+            format("let a=1;let b:float = 2.0 ;if a==1{write  'x';}else{ b=b+1; }").unwrap(),
+            "let a = 1;\nlet b: float = 2.0;\nif a == 1 {\n    write 'x';\n} else {\n    b = b + 1;\n}\n"
+        );
+        assert_eq!(
+            format("import \"lib/m.vit\";\nstruct P {x,y:float,}\nloop{ do {read a;}until a>0 or a<-5; break; }\nif a == 1 {}").unwrap(),
+            "import \"lib/m.vit\";\nstruct P { x, y: float }\nloop {\n    do {\n        read a;\n    } until a > 0 or a < -5;\n    break;\n}\nif a == 1 {}\n"
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        let cases = [
+            (
+                "let a = (1 + 2) * (3 - (4 - 5));",
+                "let a = (1 + 2) * (3 - (4 - 5));",
+            ),
+            ("let a = ((1 + 2) + 3) * 4;", "let a = (1 + 2 + 3) * 4;"),
+            (
+                "let a = (2 ^ 3) ^ 2 + 2 ^ (3 ^ 2);",
+                "let a = (2 ^ 3) ^ 2 + 2 ^ 3 ^ 2;",
+            ),
+            ("let a = (a * b) % (c / d);", "let a = a * b % (c / d);"),
+            ("let a = max((a), (-1.50));", "let a = max(a, -1.5);"),
+            (
+                "if (a == 1 and (b == 2)) or (c < d + 1 and (e > f or g != h)) {}",
+                "if a == 1 and b == 2 or (c < d + 1 and (e > f or g != h)) {}",
+            ),
+        ];
+        for (source, formatted) in cases {
+            assert_eq!(
+                format(source).unwrap(),
+                format!("{formatted}\n"),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let source = "// Reads a number.\nlet a;   // The number.\nread a; // '{'\n\n\n\
+            if a > 0 { // Positive.\n  // Nothing to do.\n}\nloop {\nwrite 'a // b';\n\n  // Done.\n} // End.\n// Last.\n";
+        let formatted = "// Reads a number.\nlet a; // The number.\nread a; // '{'\n\n\
+            if a > 0 { // Positive.\n    // Nothing to do.\n}\nloop {\n    write 'a // b';\n\n    // Done.\n} // End.\n// Last.\n";
+        assert_eq!(format(source).unwrap(), formatted);
+    }
+
+    #[test]
+    fn comment_placement() {
+        let cases = [
+            (
+                "if a == 1 {\n    write 'x';\n}\n// Otherwise.\nelse {\n    write 'y';\n}\n",
+                "if a == 1 {\n    write 'x';\n}\n// Otherwise.\nelse {\n    write 'y';\n}\n",
+            ),
+            (
+                "if a == 1 { write 'x'; } // One.\nelse { write 'y'; }\n",
+                "if a == 1 {\n    write 'x';\n} // One.\nelse {\n    write 'y';\n}\n",
+            ),
+            (
+                "do { read a; } // Again.\nuntil a > 0;\n",
+                "do {\n    read a;\n} // Again.\nuntil a > 0;\n",
+            ),
+            (
+                "let a = 1 + // One.\n    2; // Two.\nlet b = a;\n",
+                "// One.\nlet a = 1 + 2; // Two.\nlet b = a;\n",
+            ),
+            ("if a // A.\n    == 1 {\n}\n", "// A.\nif a == 1 {}\n"),
+            (
+                "do {} // D.\n\n// E.\nuntil a > 0 // F.\n    or a < 0;\n",
+                "do {} // D.\n\n// E.\n// F.\nuntil a > 0 or a < 0;\n",
+            ),
+            (
+                "if a == 1 {} else // G.\n{}\n",
+                "if a == 1 {}\n// G.\nelse {}\n",
+            ),
+            (
+                "struct P { x, // The x.\n    y }\nlet p: P;\n",
+                "// The x.\nstruct P { x, y }\nlet p: P;\n",
+            ),
+            (
+                "struct P { x, // The x.\n    y }\n\nlet p: P;\n",
+                "// The x.\nstruct P { x, y }\n\nlet p: P;\n",
+            ),
+        ];
+        for (source, formatted) in cases {
+            assert_eq!(format(source).unwrap(), formatted, "{source}");
+            assert_eq!(format(formatted).unwrap(), formatted, "{source}");
+        }
+    }
+
+    #[test]
+    fn idempotent() {
+        for source in [
+            "let a = 1;// x\n\n\n// y\nif a == 1 { // z\n} else { //w\nloop{break;}}\n",
+            "do { a = a - 1; } until (a < 0 or a > 9) and b == 1;\nlet b = 1.0 + -2 ^ -3;\n",
+            "struct P {} let p : P; // p\nwrite p.x;",
+        ] {
+            let once = format(source).unwrap();
+            assert_eq!(format(&once).unwrap(), once, "{source}");
+        }
+    }
+}
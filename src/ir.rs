@@ -0,0 +1,160 @@
+use std::fmt;
+
+use crate::ast::{Opcode, Span};
+
+// The program as seen by the backends. `vit` resolves every name into an address, checks
+// the types and makes conversions explicit, so a backend only translates each node. The
+// p-code generated from it is the reference every other backend must agree with.
+
+// A sequence of statements in a scope of its own. `variables` lists the variables declared
+// in the scope, ordered by address, with the type they ended up with.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Block {
+    pub variables: Vec<Variable>,
+    pub statements: Vec<Statement>,
+}
+
+// Two variables alive at the same time never share an address, but variables of sibling
+// scopes may reuse it with different types.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Variable {
+    pub name: String,
+    pub address: u32,
+    pub ty: Type,
+}
+
+// Loops and ifs carry the number of their labels in the p-code.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Statement {
+    Store(u32, Expr),
+    Read(u32, Type),
+    Write(Expr),
+    If(u32, Expr, Block, Option<Block>),
+    Loop(u32, Block),
+    Until(u32, Block, Expr), // The condition is evaluated in the scope of the block.
+    Break(u32),              // Leaves the loop with this label.
+    Block(Block),
+    Located(Span, Vec<Statement>), // The code of the statement in this span of the source.
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Expr {
+    pub ty: Type,
+    pub kind: ExprKind,
+}
+
+// Operands are evaluated from left to right, all of them: `and` and `or` don't short
+// circuit. Arithmetic and comparisons may mix an int with a float, in which case the int
+// is promoted. `%` doesn't appear here: it is lowered to `a - int(a / b) * b`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ExprKind {
+    Constant(Constant),
+    Load(u32),
+    Binary(Box<Expr>, Opcode, Box<Expr>),
+    Convert(Box<Expr>), // Converts a number to the type of this expression.
+    Call(&'static str, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn constant(constant: Constant) -> Expr {
+        Expr {
+            ty: constant.ty(),
+            kind: ExprKind::Constant(constant),
+        }
+    }
+}
+
+// A string is a single value, so a string variable takes one memory cell like any number.
+// A variable declared without an initializer or annotation is Unknown until the first
+// assignment or read gives it a type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Type {
+    Int,
+    Float,
+    Str,
+    Bool,
+    Unknown,
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Result<Type, String> {
+        match name {
+            "int" => Ok(Type::Int),
+            "float" => Ok(Type::Float),
+            "str" => Ok(Type::Str),
+            _ => Err(format!("unknown type: {name}.")),
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        self == Type::Int || self == Type::Float
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Type::Int => "int",
+                Type::Float => "float",
+                Type::Str => "str",
+                Type::Bool => "bool",
+                Type::Unknown => "unknown",
+            }
+        )
+    }
+}
+
+// A value known at compile time. Strings hold their value, with escape sequences already
+// replaced.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Constant {
+    Int(i32),
+    Float(f64),
+    Str(String),
+}
+
+impl Constant {
+    pub fn ty(&self) -> Type {
+        match self {
+            Constant::Int(_) => Type::Int,
+            Constant::Float(_) => Type::Float,
+            Constant::Str(_) => Type::Str,
+        }
+    }
+
+    pub fn as_float(&self) -> f64 {
+        match self {
+            Constant::Int(n) => *n as f64,
+            Constant::Float(n) => *n,
+            Constant::Str(_) => f64::NAN,
+        }
+    }
+}
+
+// Formats the constant as the operand of a `ldc` instruction. Floats always keep their
+// decimal point, so `2.0` isn't loaded as an integer.
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Int(n) => write!(f, "{n}"),
+            Constant::Float(n) => write!(f, "{n:?}"),
+            Constant::Str(s) => {
+                let escaped = s
+                    .replace('\\', "\\\\")
+                    .replace('\n', "\\n")
+                    .replace('\t', "\\t");
+                write!(f, "\"{escaped}\"")
+            }
+        }
+    }
+}
@@ -1,36 +1,634 @@
-use std::{error::Error, fs};
+use std::{
+    cell::RefCell,
+    error, fmt, fs,
+    io::{self, BufWriter},
+    path::Path,
+};
 
-mod ast;
+pub mod ast;
+mod backend;
+mod debug;
+mod format;
+mod ir;
+#[cfg(feature = "serde")]
+pub mod lsp;
+pub mod modules;
 pub mod parser;
+pub mod pcode;
+mod repl;
+mod trace;
 pub mod vit;
+pub mod vm;
+mod watch;
 
-pub fn run<'a>(config: Config) -> Result<(), Box<dyn Error + 'a>> {
-    let source_code = fs::read_to_string(&config.file_name)?;
-    let program = parser::Parser::new().parse(&source_code)?;
-    let result = vit::build(program)?;
+// The name of the main module when its source comes from stdin.
+const STDIN: &str = "stdin";
 
-    fs::write(config.target_name, result)?;
+const USAGE: &str = "Usage: vit [command] [options] <file>
 
+Commands:
+    build    Compiles the file; the command when none is given
+    run      Compiles the file and runs it, or runs p-code or bytecode built before
+    debug    Runs the file under a debugger
+    repl     Runs statements as they are typed
+    fmt      Rewrites the file in the canonical layout
+    parse    Writes the parsed file to stdout as JSON
+    asm      Translates a p-code file into bytecode
+    disasm   Translates a bytecode file into p-code
+    lsp      Serves the Language Server Protocol over stdin and stdout
+
+Options:
+    -o, --output <path>        Where build, asm and disasm write, - for stdout
+    --target <target>          pcode, c, wat or x86_64-linux, for build
+    --emit <stage>[=<path>]    tokens, ast, ir, pcode, opt-pcode, ast-json or ir-json,
+                               for build and parse; several may be given
+    --seed <number>            The seed of random, for run, debug and repl
+    --overflow <mode>          wrap, trap or saturate, for run
+    --trace                    Writes each instruction run to stderr, for run
+    --profile                  Writes how often each line ran to stderr, for run
+    --check                    Fails on unformatted files instead of rewriting them, for fmt
+    --watch                    Builds or runs again whenever the file or its imports change
+    -h, --help                 Shows this message
+    -V, --version              Shows the version of vit
+
+A file named - is read from stdin, and what comes from it is written to stdout.
+";
+
+pub fn run<'a>(config: Config) -> Result<(), Box<dyn error::Error + 'a>> {
+    if !config.watch {
+        return execute(&config, &|path| fs::read(path));
+    }
+    watch::watch(&mut watch::Polling, &mut io::stdout(), |output| {
+        let read = RefCell::new(vec![]);
+        let result = execute(&config, &|path| {
+            read.borrow_mut().push(path.to_path_buf());
+            fs::read(path)
+        });
+        match result {
+            Err(error) => writeln!(output, "{}", describe(&*error))?,
+            Ok(()) if matches!(config.command, Command::Build) => writeln!(output, "No errors.")?,
+            Ok(()) => writeln!(output)?,
+        }
+        Ok(read.into_inner())
+    })?;
     Ok(())
 }
 
+// How vit shows an error that stopped it.
+pub fn describe(error: &(dyn error::Error + 'static)) -> String {
+    match error.downcast_ref::<vm::RuntimeError>() {
+        Some(error) => format!("runtime error: {error}"),
+        None => error.to_string(),
+    }
+}
+
+// Runs the command, reading files other than stdin with `read_file`.
+fn execute<'a>(
+    config: &Config,
+    read_file: &dyn Fn(&Path) -> io::Result<Vec<u8>>,
+) -> Result<(), Box<dyn error::Error + 'a>> {
+    let stdin = match config.file_name.as_str() {
+        "-" if !matches!(config.command, Command::Repl | Command::Lsp) => {
+            let mut bytes = vec![];
+            io::Read::read_to_end(&mut io::stdin(), &mut bytes)?;
+            Some(bytes)
+        }
+        _ => None,
+    };
+    let input = Path::new(match stdin {
+        Some(_) => STDIN,
+        None => &config.file_name,
+    });
+    let read = |path: &Path| match &stdin {
+        Some(bytes) if path == Path::new(STDIN) => Ok(bytes.clone()),
+        _ => read_file(path),
+    };
+    let read_to_string = |path: &Path| {
+        String::from_utf8(read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+    let located =
+        |path: &Path, e: io::Error| io::Error::new(e.kind(), format!("{}: {e}", path.display()));
+    let source = || read_to_string(input).map_err(|e| located(input, e));
+    let modules = || modules::load(input, read_to_string);
+    let write = |path: &str, contents: &[u8]| match path {
+        "-" => io::Write::write_all(&mut io::stdout(), contents),
+        path => fs::write(path, contents).map_err(|e| located(Path::new(path), e)),
+    };
+    let target = &config.target_name;
+
+    match (&config.command, config.target) {
+        (Command::Help, _) => io::Write::write_all(&mut io::stdout(), USAGE.as_bytes())?,
+        (Command::Version, _) => {
+            let version = format!("vit {}\n", env!("CARGO_PKG_VERSION"));
+            io::Write::write_all(&mut io::stdout(), version.as_bytes())?
+        }
+        (Command::Build | Command::Parse, _) if !config.emit.is_empty() => {
+            for (emit, path) in &config.emit {
+                write(path, stage(*emit, source, modules)?.as_bytes())?
+            }
+        }
+        (Command::Parse, _) => unreachable!("parse emits the ast by default."),
+        (Command::Build, Target::Pcode) => {
+            write(target, vit::link(modules()?).map_err(semantic)?.as_bytes())?
+        }
+        (Command::Build, Target::C) => {
+            let program = vit::lower(modules()?).map_err(semantic)?;
+            write(target, backend::c::generate(&program).as_bytes())?
+        }
+        (Command::Build, Target::Wat) => {
+            let program = vit::lower(modules()?).map_err(semantic)?;
+            write(target, backend::wat::generate(&program).as_bytes())?
+        }
+        (Command::Build, Target::X86_64Linux) => {
+            let program = vit::lower(modules()?).map_err(semantic)?;
+            write(target, backend::x86_64::generate(&program).as_bytes())?
+        }
+        (Command::Run, _) => {
+            let code = read(input).map_err(|e| located(input, e))?;
+            let (machine, info, source) = if built(input, &code) {
+                let machine = vm::Machine::load_bytes(&code).map_err(syntax)?;
+                (machine, vit::DebugInfo::default(), String::new())
+            } else {
+                let (code, info) = vit::link_debug(modules()?).map_err(semantic)?;
+                (vm::Machine::load(&code)?, info, source()?)
+            };
+            let machine = machine
+                .with_seed(config.seed)
+                .with_overflow(config.overflow);
+            let (mut input, mut output) = (io::stdin().lock(), BufWriter::new(io::stdout()));
+
+            let result = if config.profile {
+                let report = &mut io::stderr();
+                trace::profile(&machine, &info, &source, &mut input, &mut output, report)
+            } else if config.trace {
+                trace::trace(&machine, &mut input, &mut output, &mut io::stderr())
+            } else {
+                machine.run(&mut input, &mut output)
+            };
+            result.map_err(|error| locate(error, &info, &source))?
+        }
+        (Command::Asm, _) => {
+            let code = vm::bytecode::assemble(&source()?).map_err(syntax)?;
+            write(target, &code)?
+        }
+        (Command::Disasm, _) => {
+            let code = vm::bytecode::disassemble(&read(input).map_err(|e| located(input, e))?)
+                .map_err(syntax)?;
+            write(target, code.as_bytes())?
+        }
+        (Command::Debug, _) => debug::run(
+            &source()?,
+            modules()?,
+            config.seed,
+            &mut io::stdin().lock(),
+            &mut BufWriter::new(io::stdout()),
+        )?,
+        (Command::Fmt, _) => {
+            let source = source()?;
+            let formatted = format::format(&source).map_err(syntax)?;
+            if config.check {
+                if formatted != source {
+                    return Err(format!("{} is not formatted.", input.display()).into());
+                }
+            } else if stdin.is_some() {
+                write("-", formatted.as_bytes())?
+            } else if formatted != source {
+                write(&config.file_name, formatted.as_bytes())?
+            }
+        }
+        #[cfg(feature = "serde")]
+        (Command::Lsp, _) => lsp::serve(&mut io::stdin().lock(), &mut io::stdout().lock())?,
+        #[cfg(not(feature = "serde"))]
+        (Command::Lsp, _) => return Err("lsp needs vit to be built with serde.".into()),
+        (Command::Repl, _) => repl::run(
+            config.seed,
+            &mut io::stdin().lock(),
+            &mut BufWriter::new(io::stdout()),
+        )?,
+    }
+
+    Ok(())
+}
+
+// An error that stops vit before it runs a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Usage,    // The arguments given to vit.
+    Syntax,   // A file that doesn't parse.
+    Semantic, // A program that parses but can't be compiled, like one using undeclared names.
+    Io,       // A file that can't be read or written.
+}
+
+impl ErrorKind {
+    // The exit code of vit when it stops with this error. The codes just start at 64, so
+    // they don't clash with those of runtime errors; only usage and I/O errors have the
+    // meaning sysexits.h gives 64 and 74.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Usage => 64,
+            ErrorKind::Syntax => 65,
+            ErrorKind::Semantic => 66,
+            ErrorKind::Io => 74,
+        }
+    }
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: String) -> Error {
+        Error { kind, message }
+    }
+}
+
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.message
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+fn syntax(message: String) -> Error {
+    Error::new(ErrorKind::Syntax, message)
+}
+
+fn semantic(message: String) -> Error {
+    Error::new(ErrorKind::Semantic, message)
+}
+
+// The text of one stage of the pipeline: the tokens and the statements of the main
+// module, or the program as it comes out of the later stages.
+fn stage<'a>(
+    emit: Emit,
+    source: impl Fn() -> io::Result<String>,
+    modules: impl Fn() -> Result<Vec<modules::Module>, Error>,
+) -> Result<String, Box<dyn error::Error + 'a>> {
+    let pcode = || -> Result<Vec<pcode::Instr>, Box<dyn error::Error + 'a>> {
+        Ok(pcode::parse(&vit::link(modules()?).map_err(semantic)?)?)
+    };
+    let lines = |code: Vec<pcode::Instr>| code.iter().map(|instr| format!("{instr}\n")).collect();
+    let parse = |source: &str| parser::Parser::new().parse(source).map_err(syntax);
+    let lower = || vit::lower(modules()?).map_err(semantic);
+
+    Ok(match emit {
+        Emit::Tokens => {
+            let source = source()?;
+            let tokens = parser::tokens(&source).map_err(|(_, message)| syntax(message))?;
+            tokens
+                .iter()
+                .map(|token| {
+                    let start = token.span.start;
+                    let line = token.span.line(&source);
+                    let column = source[..start].rfind('\n').map_or(start, |n| start - n - 1) + 1;
+                    format!(
+                        "{:<8} {:<15} {}\n",
+                        format!("{line}:{column}"),
+                        token.kind,
+                        token.text
+                    )
+                })
+                .collect()
+        }
+        Emit::Ast => format!("{:#?}\n", parse(&source()?)?),
+        Emit::Ir => format!("{:#?}\n", lower()?),
+        Emit::Pcode => lines(pcode()?),
+        Emit::OptPcode => lines(pcode::optimize(pcode()?)),
+        #[cfg(feature = "serde")]
+        Emit::AstJson => serde_json::to_string_pretty(&parse(&source()?)?)? + "\n",
+        #[cfg(feature = "serde")]
+        Emit::IrJson => serde_json::to_string_pretty(&lower()?)? + "\n",
+        #[cfg(not(feature = "serde"))]
+        Emit::AstJson | Emit::IrJson => {
+            return Err("ast-json and ir-json need vit to be built with serde.".into())
+        }
+    })
+}
+
+// Whether `code` was built already, by `build` as p-code or by `asm` as bytecode, rather than
+// being vit source. P-code files have no extension of their own, so it's told by its text.
+fn built(path: &Path, code: &[u8]) -> bool {
+    vm::bytecode::is_bytecode(code)
+        || path.extension().is_none_or(|extension| extension != "vit")
+            && std::str::from_utf8(code).is_ok_and(|text| pcode::parse(text).is_ok())
+}
+
+// Adds the line of the main module where the error happened, if it did in one.
+fn locate(error: vm::RuntimeError, info: &vit::DebugInfo, source: &str) -> vm::RuntimeError {
+    match info.line_at(error.pc) {
+        Some(line) => {
+            let number = line.span.line(source);
+            let text = source.lines().nth(number - 1).unwrap_or_default();
+            vm::RuntimeError {
+                line: Some((number, text.trim().to_string())),
+                ..error
+            }
+        }
+        None => error,
+    }
+}
+
+pub enum Command {
+    Build,  // Writes the generated code to the target file.
+    Run,    // Runs the p-code right away.
+    Asm,    // Translates a p-code file into bytecode.
+    Disasm, // Translates a bytecode file into p-code.
+    Repl,   // Runs statements as they are typed.
+    Debug,  // Runs the program under the control of a debugger.
+    Fmt,    // Rewrites the source file in the canonical layout.
+    Lsp,    // Serves the Language Server Protocol over stdin and stdout.
+    Parse,  // Writes the parsed program to stdout, in the forms given by --emit.
+    Help,   // Writes the usage of vit.
+    Version,
+}
+
+// A stage of the pipeline that build and parse can write out. Build writes each one given
+// with --emit next to the source file, with its own extension, instead of the target, or
+// to stdout when the source comes from stdin. A stage written as `stage=path` goes to that
+// path, and to stdout when it's `-`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,   // The tokens of the file, with their line and column.
+    Ast,      // The statements of the file.
+    Ir,       // The program lowered from the file and its imports.
+    Pcode,    // The p-code that build writes.
+    OptPcode, // The p-code after the peephole optimizer.
+    AstJson,  // The statements of the file as JSON, with their spans.
+    IrJson,   // The lowered program as JSON.
+}
+
+impl Emit {
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Tokens => "tokens",
+            Emit::Ast => "ast",
+            Emit::Ir => "ir",
+            Emit::Pcode => "pcode",
+            Emit::OptPcode => "opt.pcode",
+            Emit::AstJson => "ast.json",
+            Emit::IrJson => "ir.json",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    Pcode,
+    C,           // A C source file, to be built with the system's C compiler.
+    Wat,         // A WebAssembly text module, whose host provides input and output.
+    X86_64Linux, // GNU assembler text for a static Linux executable.
+}
+
 pub struct Config {
-    pub file_name: String,
-    pub target_name: String,
+    pub command: Command,
+    pub target: Target,
+    pub file_name: String,   // `-` for stdin.
+    pub target_name: String, // `-` for stdout.
+    pub seed: u64,
+    pub trace: bool,   // Writes each instruction run to stderr.
+    pub profile: bool, // Writes how many times each line and instruction ran to stderr.
+    pub overflow: vm::Overflow,
+    pub check: bool, // Makes fmt fail on unformatted files instead of rewriting them.
+    pub emit: Vec<(Emit, String)>, // The stages to write, with the path of each.
+    pub watch: bool, // Builds or runs again whenever the file or its imports change.
 }
 
 impl Config {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
         args.next();
+        let mut args = args.peekable();
+
+        let command = match args.peek().map(String::as_str) {
+            Some("run") => Some(Command::Run),
+            Some("build") => Some(Command::Build),
+            Some("asm") => Some(Command::Asm),
+            Some("disasm") => Some(Command::Disasm),
+            Some("debug") => Some(Command::Debug),
+            Some("repl") => Some(Command::Repl),
+            Some("fmt") => Some(Command::Fmt),
+            Some("lsp") => Some(Command::Lsp),
+            Some("parse") => Some(Command::Parse),
+            _ => None,
+        };
+        if command.is_some() {
+            args.next();
+        }
+        let command = command.unwrap_or(Command::Build);
+
+        let mut seed = 0;
+        let (mut trace, mut profile, mut check, mut watch) = (false, false, false, false);
+        let (mut help, mut version) = (false, false);
+        let mut overflow = None;
+        let mut emit = vec![];
+        let mut target = Target::Pcode;
+        let mut output = None;
+        let mut positional = vec![];
+        let mut options = true; // Whether `--` hasn't come yet.
+        while let Some(arg) = args.next() {
+            if !options || arg == "-" || !arg.starts_with('-') {
+                positional.push(arg);
+                continue;
+            }
+            // An option that takes a value takes the next argument, or what follows `=`.
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = |expected: &str| {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{name} expects {expected}."))
+            };
+
+            match name {
+                "--seed" => {
+                    seed = value("a number")?
+                        .parse()
+                        .map_err(|_| "--seed expects a number.")?
+                }
+                "--overflow" => {
+                    overflow = Some(match value("wrap, trap or saturate")?.as_str() {
+                        "wrap" => vm::Overflow::Wrap,
+                        "trap" => vm::Overflow::Trap,
+                        "saturate" => vm::Overflow::Saturate,
+                        _ => return Err("--overflow expects wrap, trap or saturate.".into()),
+                    })
+                }
+                "--emit" => {
+                    let expected = "tokens, ast, ir, pcode, opt-pcode, ast-json or ir-json";
+                    for stage in value(expected)?.split(',') {
+                        let (stage, path) = match stage.split_once('=') {
+                            Some((stage, path)) => (stage, Some(path.to_string())),
+                            None => (stage, None),
+                        };
+                        emit.push((
+                            match stage {
+                                "tokens" => Emit::Tokens,
+                                "ast" => Emit::Ast,
+                                "ir" => Emit::Ir,
+                                "pcode" => Emit::Pcode,
+                                "opt-pcode" => Emit::OptPcode,
+                                "ast-json" => Emit::AstJson,
+                                "ir-json" => Emit::IrJson,
+                                _ => return Err(format!("--emit expects {expected}.")),
+                            },
+                            path,
+                        ));
+                    }
+                }
+                "--target" => {
+                    target = match value("pcode, c, wat or x86_64-linux")?.as_str() {
+                        "pcode" => Target::Pcode,
+                        "c" => Target::C,
+                        "wat" => Target::Wat,
+                        "x86_64-linux" => Target::X86_64Linux,
+                        _ => return Err("--target expects pcode, c, wat or x86_64-linux.".into()),
+                    }
+                }
+                "-o" | "--output" => output = Some(value("a path")?),
+                _ if inline.is_some() => return Err(format!("{name} takes no value.")),
+                "--trace" => trace = true,
+                "--profile" => profile = true,
+                "--check" => check = true,
+                "--watch" => watch = true,
+                "-h" | "--help" => help = true,
+                "-V" | "--version" => version = true,
+                "--" => options = false,
+                _ => return Err(format!("unknown option: {arg}.")),
+            }
+        }
+
+        let command = match command {
+            _ if help => Command::Help,
+            _ if version => Command::Version,
+            command => command,
+        };
+        let file_name = match (&command, positional.len()) {
+            (Command::Help | Command::Version, _) => String::new(),
+            (Command::Repl | Command::Lsp, 0) => String::new(),
+            (Command::Repl | Command::Lsp, _) => {
+                return Err(format!("unexpected argument: {}.", positional[0]))
+            }
+            (_, 0) => return Err("No input file name given.".into()),
+            (_, 1) => positional.remove(0),
+            (_, _) => return Err(format!("unexpected argument: {}.", positional[1])),
+        };
+
+        let runs = matches!(command, Command::Run);
+        let checked = !matches!(command, Command::Help | Command::Version);
+        let errors = [
+            (
+                target != Target::Pcode
+                    && matches!(
+                        command,
+                        Command::Run | Command::Repl | Command::Debug | Command::Lsp
+                    ),
+                "Only p-code can be run; use build for other targets.",
+            ),
+            (
+                (trace || profile) && !runs,
+                "--trace and --profile only apply to run.",
+            ),
+            (
+                trace && profile,
+                "--trace and --profile can't be used together.",
+            ),
+            (
+                overflow.is_some() && !runs,
+                "--overflow only applies to run.",
+            ),
+            (
+                check && !matches!(command, Command::Fmt),
+                "--check only applies to fmt.",
+            ),
+            (
+                !emit.is_empty() && !matches!(command, Command::Build | Command::Parse),
+                "--emit only applies to build and parse.",
+            ),
+            (
+                output.is_some()
+                    && !matches!(command, Command::Build | Command::Asm | Command::Disasm),
+                "-o only applies to build, asm and disasm.",
+            ),
+            (
+                output.is_some() && !emit.is_empty(),
+                "--emit names its own outputs; use --emit stage=path.",
+            ),
+            (
+                watch && !matches!(command, Command::Build | Command::Run),
+                "--watch only applies to build and run.",
+            ),
+            (
+                watch && file_name == "-",
+                "--watch needs a file to watch, not stdin.",
+            ),
+            (
+                file_name == "-" && matches!(command, Command::Debug),
+                "debug reads its commands from stdin, so the file can't come from it.",
+            ),
+        ];
+        if let Some((_, message)) = errors.iter().find(|(error, _)| checked && *error) {
+            return Err(message.to_string());
+        }
 
-        let file_name = if let Some(file) = args.next() {
-            file
-        } else {
-            return Err("No input file name given.");
+        if emit.is_empty() && matches!(command, Command::Parse) {
+            emit.push((Emit::AstJson, None));
+        }
+        // What comes from stdin goes to stdout, and the rest next to the input file.
+        let path = Path::new(&file_name);
+        let output_for = |extension: &str| match file_name.as_str() {
+            "-" => "-".to_string(),
+            _ => path.with_extension(extension).display().to_string(),
         };
+        let emit = emit
+            .into_iter()
+            .map(|(stage, path)| match (path, &command) {
+                (Some(path), _) => (stage, path),
+                (None, Command::Parse) => (stage, "-".to_string()),
+                (None, _) => (stage, output_for(stage.extension())),
+            })
+            .collect();
 
-        let target_name = args.next().unwrap_or_else(|| file_name.replace(".vit", ""));
+        let target_name = output.unwrap_or_else(|| match (&command, target) {
+            (Command::Asm, _) => output_for("vbc"),
+            (Command::Disasm, _) => output_for("pcode"),
+            (_, Target::Pcode) => output_for(""),
+            (_, Target::C) => output_for("c"),
+            (_, Target::Wat) => output_for("wat"),
+            (_, Target::X86_64Linux) => output_for("s"),
+        });
+        if matches!(command, Command::Build | Command::Asm | Command::Disasm)
+            && target_name == file_name
+            && file_name != "-"
+        {
+            return Err(format!(
+                "{file_name} would be overwritten; name the output with -o."
+            ));
+        }
 
-        Ok(Config { file_name, target_name })
+        Ok(Config {
+            command,
+            target,
+            file_name,
+            target_name,
+            seed,
+            trace,
+            profile,
+            overflow: overflow.unwrap_or_default(),
+            check,
+            emit,
+            watch,
+        })
     }
 }
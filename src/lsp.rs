@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    ast::Span,
+    modules,
+    parser::Parser,
+    vit::{self, Definition},
+};
+
+const KEYWORDS: [&str; 14] = [
+    "let", "const", "struct", "import", "read", "write", "if", "else", "loop", "do", "until",
+    "break", "and", "or",
+];
+
+// Speaks the Language Server Protocol over `input` and `output`, which are the standard
+// streams when an editor starts `vit lsp`, until the client sends `exit`. Documents are
+// synced in full, and every change publishes the errors of the parser and the compiler.
+// Variables, constants and records can be followed to their definition and references
+// and show what they are on hover. Keywords are offered as completions.
+pub fn serve(input: &mut impl BufRead, output: &mut impl Write) -> Result<(), String> {
+    let mut server = Server {
+        parser: Parser::new(),
+        documents: HashMap::new(),
+    };
+
+    while let Some(body) = receive(input)? {
+        let message: Value = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            // Without a message there's no id to answer to, which JSON-RPC gives as null.
+            Err(error) => {
+                let error = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": -32700, "message": format!("invalid message: {error}.") },
+                });
+                send(output, &error)?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        match message.get("id") {
+            // A response to a request of ours, which we never send.
+            Some(_) if method.is_empty() => (),
+            Some(id) => {
+                let response = match server.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": message },
+                    }),
+                };
+                send(output, &response)?;
+            }
+            None if method == "exit" => return Ok(()),
+            None => {
+                if let Some(notification) = server.notify(method, params) {
+                    send(output, &notification)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads the body of the next message, framed by a `Content-Length` header. Returns `None`
+// at the end of the input.
+fn receive(input: &mut impl BufRead) -> Result<Option<Vec<u8>>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or("message without a content length.")?;
+    // The length is the client's word, so the buffer only grows with what is really read.
+    let mut body = vec![];
+    input
+        .by_ref()
+        .take(length as u64)
+        .read_to_end(&mut body)
+        .map_err(|e| e.to_string())?;
+    if body.len() < length {
+        return Err("message cut short.".to_string());
+    }
+    Ok(Some(body))
+}
+
+fn send(output: &mut impl Write, message: &Value) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len()).map_err(|e| e.to_string())?;
+    output.flush().map_err(|e| e.to_string())
+}
+
+struct Server {
+    parser: Parser,
+    documents: HashMap<String, Document>, // By URI.
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i32, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "vit", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/completion" => Ok(KEYWORDS
+                .iter()
+                .map(|keyword| json!({ "label": keyword, "kind": 14 }))
+                .collect()),
+            "textDocument/definition" => Ok(self
+                .lookup(params)
+                .and_then(|(uri, document, name)| {
+                    let symbol = document.resolve(name)?;
+                    Some(document.location(uri, symbol.name))
+                })
+                .unwrap_or_default()),
+            "textDocument/references" => {
+                let declaration = params["context"]["includeDeclaration"].as_bool();
+                Ok(self
+                    .lookup(params)
+                    .and_then(|(uri, document, name)| {
+                        let symbol = document.resolve(name)?;
+                        let references = document
+                            .names
+                            .iter()
+                            .filter(|&&other| declaration.unwrap_or(true) || other != symbol.name)
+                            .filter(|&&other| {
+                                document.resolve(other).map(|other| other.name) == Some(symbol.name)
+                            })
+                            .map(|&other| document.location(uri, other))
+                            .collect();
+                        Some(Value::Array(references))
+                    })
+                    .unwrap_or_default())
+            }
+            "textDocument/hover" => Ok(self
+                .lookup(params)
+                .and_then(|(_, document, name)| {
+                    let symbol = document.resolve(name)?;
+                    Some(json!({
+                        "contents": { "kind": "plaintext", "value": symbol.definition.symbol },
+                        "range": range(&document.text, name),
+                    }))
+                })
+                .unwrap_or_default()),
+            _ => Err((-32601, format!("unknown method: {method}."))),
+        }
+    }
+
+    // Handles a notification, returning the one to send back, if any.
+    fn notify(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?.to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str()?,
+            "textDocument/didChange" => {
+                params["contentChanges"].as_array()?.last()?["text"].as_str()?
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return Some(diagnostics(&uri, vec![]));
+            }
+            _ => return None,
+        };
+
+        let (document, errors) = self.analyze(&uri, text.to_string());
+        let errors = errors
+            .into_iter()
+            .map(|(span, message)| {
+                json!({
+                    "range": range(&document.text, span),
+                    "severity": 1,
+                    "source": "vit",
+                    "message": message,
+                })
+            })
+            .collect();
+        self.documents.insert(uri.clone(), document);
+        Some(diagnostics(&uri, errors))
+    }
+
+    // Compiles the document with the modules it imports, which are read from the other
+    // open documents or else from the disk.
+    fn analyze(&self, uri: &str, text: String) -> (Document, Vec<(Span, String)>) {
+        let mut document = Document {
+            names: names(&text),
+            symbols: vec![],
+            text,
+        };
+        if let Err(error) = self.parser.parse_located(&document.text) {
+            return (document, vec![error]);
+        }
+
+        let path = path(uri);
+        let read = |file: &Path| {
+            if file == path {
+                return Ok(document.text.clone());
+            }
+            let open = self
+                .documents
+                .iter()
+                .find(|(uri, _)| self::path(uri) == file);
+            match open {
+                Some((_, open)) => Ok(open.text.clone()),
+                None => fs::read_to_string(file),
+            }
+        };
+        let analysis = match modules::load(&path, read) {
+            Ok(modules) => vit::analyze(modules),
+            Err(error) => return (document, vec![(Span::default(), error.message)]),
+        };
+
+        document.symbols = analysis
+            .definitions
+            .into_iter()
+            .filter_map(|definition| {
+                // The name a statement declares is the first one in it.
+                let name = *document.names.iter().find(|name| {
+                    definition.span.start <= name.start
+                        && name.end <= definition.span.end
+                        && document.text[name.start..name.end] == definition.name
+                })?;
+                let scope = name.end..close(&document.text, definition.span.end);
+                Some(Symbol {
+                    definition,
+                    name,
+                    scope,
+                })
+            })
+            .collect();
+
+        let errors = analysis.error.map(|(span, message)| {
+            // The compiler names the file of every error, which the editor already shows.
+            let prefix = format!("{}: ", path.display());
+            match span {
+                Some(span) => (span, message.trim_start_matches(&prefix).to_string()),
+                None => (Span::default(), message),
+            }
+        });
+        (document, errors.into_iter().collect())
+    }
+
+    // The document and the name at the position given by a request.
+    fn lookup<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Document, Span)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = offset(&document.text, &params["position"])?;
+        let name = document
+            .names
+            .iter()
+            .find(|name| name.start <= offset && offset <= name.end)?;
+        Some((uri, document, *name))
+    }
+}
+
+fn diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+struct Document {
+    text: String,
+    names: Vec<Span>, // The identifiers that may refer to a symbol.
+    symbols: Vec<Symbol>,
+}
+
+// A definition, with the name it declares and the part of the source that can see it:
+// from its statement to the end of the enclosing block.
+struct Symbol {
+    definition: Definition,
+    name: Span,
+    scope: Range<usize>,
+}
+
+impl Document {
+    // The symbol a name refers to: the one it declares, or else the innermost one in
+    // scope with its name.
+    fn resolve(&self, name: Span) -> Option<&Symbol> {
+        let text = &self.text[name.start..name.end];
+        if let Some(symbol) = self.symbols.iter().find(|symbol| symbol.name == name) {
+            return Some(symbol);
+        }
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.definition.name == text)
+            .filter(|symbol| symbol.scope.contains(&name.start))
+            .max_by_key(|symbol| symbol.scope.start)
+    }
+
+    fn location(&self, uri: &str, name: Span) -> Value {
+        json!({ "uri": uri, "range": range(&self.text, name) })
+    }
+}
+
+// The identifiers of `text` that name variables, constants or records: those outside
+// strings and comments that aren't keywords, fields after a `.`, names qualified by a
+// module, types after a `:`, called functions or the names inside a struct declaration.
+fn names(text: &str) -> Vec<Span> {
+    let bytes = text.as_bytes();
+    let before = |i: usize| text[..i].trim_end().chars().next_back();
+    let after = |i: usize| text[i..].trim_start();
+
+    let mut names = vec![];
+    let mut in_struct = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            quote @ (b'\'' | b'"') => {
+                i += 1 + text[i + 1..].find(quote as char).unwrap_or(text.len());
+            }
+            b'/' if text[i..].starts_with("//") => {
+                i += text[i..].find('\n').unwrap_or(text.len() - i);
+            }
+            b'}' if in_struct => in_struct = false,
+            c if c.is_ascii_alphanumeric() => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let word = &text[start..i];
+                in_struct |= word == "struct";
+                if c.is_ascii_alphabetic()
+                    && !in_struct
+                    && !KEYWORDS.contains(&word)
+                    && !matches!(before(start), Some('.' | ':'))
+                    && !after(i).starts_with('(')
+                    && !after(i).starts_with("::")
+                {
+                    names.push(Span { start, end: i });
+                }
+                continue;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    names
+}
+
+// The index of the brace that closes the block around `from`, or the end of `text`.
+fn close(text: &str, from: usize) -> usize {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = text[from..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '/') if chars.peek().is_some_and(|&(_, c)| c == '/') => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            (None, '{') => depth += 1,
+            (None, '}') if depth == 0 => return from + i,
+            (None, '}') => depth -= 1,
+            _ => (),
+        }
+    }
+    text.len()
+}
+
+// The file of a `file://` URI.
+fn path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| std::str::from_utf8(tail.get(..2)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// LSP positions count lines from 0, and columns in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Value {
+    let offset = (0..=offset.min(text.len()))
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or_default();
+    let before = &text[..offset];
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[start..].encode_utf16().count(),
+    })
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+
+    let start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spelled(text: &str) -> Vec<&str> {
+        names(text)
+            .iter()
+            .map(|name| &text[name.start..name.end])
+            .collect()
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(
+            spelled("let a: int = len('b c') + m::X; // d\nstruct P { x, y }\np.x = a1 * 2.5;"),
+            ["a", "p", "a1"]
+        );
+        assert_eq!(close("a; if b { c; } }\nd;", 0), 15);
+    }
+
+    #[test]
+    fn positions() {
+        let text = "let a = 'é😀';\nlet b;";
+        assert_eq!(position(text, 16), json!({ "line": 0, "character": 13 }));
+        assert_eq!(offset(text, &position(text, 16)), Some(16));
+        assert_eq!(
+            offset(text, &json!({ "line": 1, "character": 4 })),
+            Some(22)
+        );
+        assert_eq!(
+            path("file:///tmp/my%20files/a.vit"),
+            Path::new("/tmp/my files/a.vit")
+        );
+    }
+}
@@ -1,16 +1,28 @@
-use std::{env, process};
+use std::{env, io, process};
+
+use vit::ErrorKind;
 
 fn main() {
     let args = env::args();
     let config = match vit::Config::build(args) {
         Err(message) => {
-            eprintln!("{message}");
-            process::exit(1);
+            eprintln!("{message}\nRun vit --help for usage.");
+            process::exit(ErrorKind::Usage.exit_code());
         }
         Ok(config) => config,
     };
 
     if let Err(e) = vit::run(config) {
-        eprintln!("{:?}", e);
+        eprintln!("{}", vit::describe(&*e));
+        let code = if let Some(error) = e.downcast_ref::<vit::vm::RuntimeError>() {
+            error.kind.exit_code()
+        } else if let Some(error) = e.downcast_ref::<vit::Error>() {
+            error.kind.exit_code()
+        } else if e.is::<io::Error>() {
+            ErrorKind::Io.exit_code()
+        } else {
+            1
+        };
+        process::exit(code);
     };
 }
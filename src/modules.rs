@@ -0,0 +1,223 @@
+use std::{
+    fmt::Display,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    ast::{Spanned, Statement},
+    parser::Parser,
+    Error, ErrorKind,
+};
+
+// A parsed source file. Its constants and structs are visible to the files that import
+// it as `name::item`.
+#[derive(Clone)]
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub program: Vec<Spanned<Statement>>,
+}
+
+// Loads the file at `path` and every file it imports, directly or not. Imported paths are
+// relative to the directory of the importing file. Modules are returned in the order they
+// must be compiled: each one after all of its imports, so the file at `path` comes last.
+pub fn load<F>(path: &Path, read: F) -> Result<Vec<Module>, Error>
+where
+    F: Fn(&Path) -> io::Result<String>,
+{
+    let mut loader = Loader {
+        read,
+        parser: Parser::new(),
+        modules: vec![],
+        loading: vec![],
+    };
+
+    loader.load(normalize(path))?;
+    Ok(loader.modules)
+}
+
+// The name of the module in the file at `path`, which is the file name without extension.
+pub fn module_name(path: &Path) -> Result<String, String> {
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+
+    let mut chars = name.chars();
+    if chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Ok(name.to_string())
+    } else {
+        Err(format!("invalid module name: {}.", path.display()))
+    }
+}
+
+struct Loader<F> {
+    read: F,
+    parser: Parser,
+    modules: Vec<Module>,
+    loading: Vec<PathBuf>, // Files whose imports are being loaded, used to detect cycles.
+}
+
+impl<F> Loader<F>
+where
+    F: Fn(&Path) -> io::Result<String>,
+{
+    fn load(&mut self, path: PathBuf) -> Result<(), Error> {
+        if let Some(start) = self.loading.iter().position(|file| *file == path) {
+            let cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .chain([&path])
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(Error::new(
+                ErrorKind::Semantic,
+                format!("import cycle: {}.", cycle.join(" -> ")),
+            ));
+        }
+
+        if self.modules.iter().any(|module| module.path == path) {
+            return Ok(());
+        }
+
+        let error = |kind, e: &dyn Display| Error::new(kind, format!("{}: {e}", path.display()));
+        let source = (self.read)(&path).map_err(|e| error(ErrorKind::Io, &e))?;
+        let program = (self.parser.parse(&source)).map_err(|e| error(ErrorKind::Syntax, &e))?;
+        let name = module_name(&path).map_err(|e| error(ErrorKind::Semantic, &e))?;
+
+        self.loading.push(path.clone());
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        for statement in &program {
+            if let Statement::Import(import) = &statement.node {
+                self.load(normalize(&directory.join(import)))?;
+            }
+        }
+        self.loading.pop();
+
+        if self.modules.iter().any(|module| module.name == name) {
+            return Err(Error::new(
+                ErrorKind::Semantic,
+                format!("{}: module name already in use: {name}.", path.display()),
+            ));
+        }
+
+        self.modules.push(Module {
+            name,
+            path,
+            program,
+        });
+        Ok(())
+    }
+}
+
+// Removes `.` and `..` from a path without touching the file system, so the same file
+// always gets the same path no matter how it was imported.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir
+                if matches!(result.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn reader(files: &[(&str, &str)]) -> impl Fn(&Path) -> io::Result<String> {
+        let files: HashMap<PathBuf, String> = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect();
+
+        move |path| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file not found"))
+        }
+    }
+
+    #[test]
+    fn load_order() {
+        let read = reader(&[
+            (
+                "main.vit",
+                "import \"lib/a.vit\"; import \"lib/b.vit\"; write a::X;",
+            ),
+            ("lib/a.vit", "import \"./c.vit\"; const X = c::Y;"),
+            ("lib/b.vit", "import \"../lib/c.vit\";"),
+            ("lib/c.vit", "const Y = 1;"),
+        ]);
+
+        let modules = load(Path::new("main.vit"), read).unwrap();
+
+        let names: Vec<&str> = modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["c", "a", "b", "main"]);
+        assert_eq!(modules[0].path, PathBuf::from("lib/c.vit"));
+    }
+
+    #[test]
+    fn import_cycle() {
+        let read = reader(&[
+            ("main.vit", "import \"a.vit\";"),
+            ("a.vit", "import \"b.vit\";"),
+            ("b.vit", "import \"a.vit\";"),
+        ]);
+
+        let result = load(Path::new("main.vit"), read);
+
+        assert_eq!(
+            result.err().unwrap(),
+            Error::new(
+                ErrorKind::Semantic,
+                "import cycle: a.vit -> b.vit -> a.vit.".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        let read = reader(&[
+            ("main.vit", "import \"util.vit\";"),
+            ("util.vit", "let a = ;"),
+        ]);
+        let error = load(Path::new("main.vit"), read).err().unwrap();
+        assert!(error.message.starts_with("util.vit: "));
+        assert_eq!(error.kind, ErrorKind::Syntax);
+
+        let read = reader(&[("main.vit", "import \"missing.vit\";")]);
+        let error = load(Path::new("main.vit"), read).err().unwrap();
+        assert!(error.message.starts_with("missing.vit: "));
+        assert_eq!(error.kind, ErrorKind::Io);
+
+        let read = reader(&[
+            ("main.vit", "import \"a/util.vit\"; import \"b/util.vit\";"),
+            ("a/util.vit", ""),
+            ("b/util.vit", ""),
+        ]);
+        let error = load(Path::new("main.vit"), read).err().unwrap();
+        assert!(error.message.contains("module name already in use"));
+        assert_eq!(error.kind, ErrorKind::Semantic);
+    }
+
+    #[test]
+    fn imports_only_at_the_beginning() {
+        let read = reader(&[("main.vit", "let a = 1; import \"util.vit\";")]);
+
+        assert!(load(Path::new("main.vit"), read).is_err());
+    }
+}
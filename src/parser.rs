@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
-use crate::ast::Statement;
-use lalrpop_util::{lalrpop_mod, ParseError};
+use crate::ast::{Span, Spanned, Statement};
+use lalrpop_util::{lalrpop_mod, lexer::MatcherBuilder, ParseError};
 
 lalrpop_mod!(pub vit_grammar);
 
@@ -26,41 +26,155 @@ impl Parser {
                     "r#\"[a-zA-z][a-zA-z0-9_]*\"#".to_string(),
                     "an identifier".to_string(),
                 );
-                map.insert("r#\"'.*'\"#".to_string(), "a string literal".to_string());
+                map.insert("r#\"'[^']*'\"#".to_string(), "a string literal".to_string());
+                map.insert(
+                    "r#\"([0-9]+\\\\.)?[0-9]+\"#".to_string(),
+                    "a number".to_string(),
+                );
+                map.insert(
+                    "r#\"\\\"[^\\\"]*\\\"\"#".to_string(),
+                    "a module path".to_string(),
+                );
                 map
             },
         }
     }
 
-    pub fn parse(&self, source: &str) -> Result<Vec<Statement>, String> {
-        match self.parser.parse(source) {
-            Err(error) => match error {
-                ParseError::InvalidToken { location } => {
-                    Err(format!("invalid token at {location}"))
-                }
-                ParseError::UnrecognizedToken { token, expected } => Err(format!(
+    pub fn parse(&self, source: &str) -> Result<Vec<Spanned<Statement>>, String> {
+        self.parse_located(source).map_err(|(_, message)| message)
+    }
+
+    // Like `parse`, but an error also comes with the span of the source it is about.
+    pub fn parse_located(&self, source: &str) -> Result<Vec<Spanned<Statement>>, (Span, String)> {
+        self.parser.parse(source).map_err(|error| match error {
+            ParseError::InvalidToken { location } => (
+                Span {
+                    start: location,
+                    end: location + 1,
+                },
+                format!("invalid token at {location}"),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => (
+                Span { start, end },
+                format!(
                     "expected {:?}, found {}",
                     expected
                         .iter()
                         .map(|p| self.map.get(p).unwrap_or(p).clone())
                         .collect::<Vec<String>>(),
-                    token.1,
-                )),
-                ParseError::UnrecognizedEof { location, .. } => {
-                    Err(format!("unexpected EoF at location {location}"))
-                }
-                ParseError::User { error } => Err(error.to_string()),
-                ParseError::ExtraToken { token } => Err(format!("extra token: {}", token.1)),
-            },
-            Ok(program) => Ok(program),
-        }
+                    token,
+                ),
+            ),
+            ParseError::UnrecognizedEof { location, .. } => (
+                Span {
+                    start: location,
+                    end: location,
+                },
+                format!("unexpected EoF at location {location}"),
+            ),
+            ParseError::User { error } => (Span::default(), error.to_string()),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => (Span { start, end }, format!("extra token: {token}")),
+        })
     }
 }
 
+// A token of the source as the parser reads it, with what the grammar calls it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token<'a> {
+    pub kind: &'static str,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+// The terminals of vit_grammar.lalrpop, in the order lalrpop hands them to its lexer: the
+// regular expressions, then the fixed tokens sorted, then what the `match` block skips.
+// The longest match wins, and the later terminal when two are as long, which is what makes
+// keywords win over identifiers. Changes to the terminals of the grammar go here too.
+const TERMINALS: [(&str, bool); 42] = [
+    (r#""[^"]*""#, false),
+    (r"'[^']*'", false),
+    (r"([0-9]+\.)?[0-9]+", false),
+    (r"[a-zA-z][a-zA-z0-9_]*", false),
+    (r"!=", false),
+    (r"%", false),
+    (r"\(", false),
+    (r"\)", false),
+    (r"\*", false),
+    (r"\+", false),
+    (r",", false),
+    (r"-", false),
+    (r"\.", false),
+    (r"/", false),
+    (r":", false),
+    (r"::", false),
+    (r";", false),
+    (r"<", false),
+    (r"<=", false),
+    (r"=", false),
+    (r"==", false),
+    (r">", false),
+    (r">=", false),
+    (r"\^", false),
+    (r"and", false),
+    (r"break", false),
+    (r"const", false),
+    (r"do", false),
+    (r"else", false),
+    (r"if", false),
+    (r"import", false),
+    (r"let", false),
+    (r"loop", false),
+    (r"or", false),
+    (r"read", false),
+    (r"struct", false),
+    (r"until", false),
+    (r"write", false),
+    (r"\{", false),
+    (r"\}", false),
+    (r"//[^\n\r]*", true),
+    (r"\s*", true),
+];
+
+// Splits the source into the tokens the parser would get, skipping whitespace and comments.
+pub fn tokens(source: &str) -> Result<Vec<Token<'_>>, (Span, String)> {
+    let builder = MatcherBuilder::new(TERMINALS).map_err(|e| (Span::default(), e.to_string()))?;
+    builder
+        .matcher::<&str>(source)
+        .map(|token| match token {
+            Ok((start, lalrpop_util::lexer::Token(index, text), end)) => Ok(Token {
+                kind: match index {
+                    0 => "module path",
+                    1 => "string literal",
+                    2 => "number",
+                    3 => "identifier",
+                    _ if text.chars().all(char::is_alphabetic) => "keyword",
+                    _ => "symbol",
+                },
+                text,
+                span: Span { start, end },
+            }),
+            Err(ParseError::InvalidToken { location }) => Err((
+                Span {
+                    start: location,
+                    end: location + 1,
+                },
+                format!("invalid token at {location}"),
+            )),
+            Err(error) => Err((Span::default(), error.to_string())),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 #[allow(dead_code)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_declaration() {
@@ -117,7 +231,7 @@ mod tests {
         let parser = Parser::new();
 
         if let Ok(result) = parser.parse("let a = 23 + 8 ^ 2 * 3;") {
-            if let Statement::Declaration(id, expression) = result.get(0).unwrap() {
+            if let Statement::Declaration(id, _, expression) = &result.first().unwrap().node {
                 assert_eq!(
                     String::from("Some((23 + ((8 ^ 2) * 3)))"),
                     format!("{:?}", expression)
@@ -181,4 +295,128 @@ mod tests {
             )
             .is_ok());
     }
+
+    #[test]
+    fn test_strings() {
+        let parser = Parser::new();
+
+        let result = parser
+            .parse("let name: str = 'Ana' + ', ' + 'Bia';")
+            .unwrap();
+        assert_eq!(
+            "[Declaration(\"name\", Some(\"str\"), Some((('Ana' + ', ') + 'Bia')))]",
+            format!("{result:?}")
+        );
+
+        let result = parser.parse("let n = len(name) + 1;").unwrap();
+        assert_eq!(
+            "[Declaration(\"n\", None, Some((len(name) + 1)))]",
+            format!("{result:?}")
+        );
+    }
+
+    #[test]
+    fn test_struct() {
+        let parser = Parser::new();
+
+        let result = parser
+            .parse("struct Point { x, y: float, } p.x = p.y + 1;")
+            .unwrap();
+        assert_eq!(
+            "[Struct(\"Point\", [(\"x\", None), (\"y\", Some(\"float\"))]), Assignment(\"p.x\", (p.y + 1))]",
+            format!("{result:?}")
+        );
+        assert!(parser.parse("struct Point { x y }").is_err());
+        assert!(parser.parse("p. = 2;").is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        let parser = Parser::new();
+
+        let result = parser
+            .parse("// Start.\nlet a = 1; // One.\nwrite '// not a comment';\n// End.")
+            .unwrap();
+        assert_eq!(
+            "[Declaration(\"a\", None, Some(1)), WriteLiteral(\"'// not a comment'\")]",
+            format!("{result:?}")
+        );
+        assert!(parser.parse("let a = // 1;").is_err());
+    }
+
+    #[test]
+    fn test_tokens() {
+        let found = tokens("let a1 = letter.x::y // c\n2.5 'l' \"m.vit\" >= -").unwrap();
+        let found: Vec<(&str, &str)> = found.iter().map(|t| (t.kind, t.text)).collect();
+        assert_eq!(
+            found,
+            [
+                ("keyword", "let"),
+                ("identifier", "a1"),
+                ("symbol", "="),
+                ("identifier", "letter"),
+                ("symbol", "."),
+                ("identifier", "x"),
+                ("symbol", "::"),
+                ("identifier", "y"),
+                ("number", "2.5"),
+                ("string literal", "'l'"),
+                ("module path", "\"m.vit\""),
+                ("symbol", ">="),
+                ("symbol", "-"),
+            ]
+        );
+
+        let source = "let a = 1;\nlet b = a # 2;";
+        assert_eq!(
+            tokens(source).unwrap_err(),
+            Parser::new().parse_located(source).unwrap_err()
+        );
+    }
+
+    // Every fixed token of the grammar is one the lexer knows.
+    #[test]
+    fn test_terminals() {
+        let grammar = include_str!("vit_grammar.lalrpop");
+        for (terminal, _) in &TERMINALS[4..40] {
+            let literal = format!("\"{}\"", terminal.replace('\\', ""));
+            assert!(grammar.contains(&literal), "{literal}");
+        }
+    }
+
+    // Text made of the tokens of the language, in any order, or of the characters they use.
+    fn texts() -> impl Strategy<Value = String> {
+        let tokens: Vec<&str> = TERMINALS[4..40]
+            .iter()
+            .map(|(terminal, _)| terminal.trim_start_matches('\\'))
+            .chain([
+                "a",
+                "b",
+                "p.x",
+                "m::c",
+                "1",
+                "2.5",
+                "0",
+                "2147483648",
+                "'s'",
+                "\"m.vit\"",
+            ])
+            .chain(["max", "len", "int", "float", "random", "str", "//", "\n"])
+            .collect();
+        prop_oneof![
+            prop::collection::vec(prop::sample::select(tokens), 0..40).prop_map(|t| t.join(" ")),
+            "[a-z0-9 .,:;'\"(){}+*/^%=<>!#-]{0,60}",
+        ]
+    }
+
+    proptest! {
+        // Whatever the text, parsing it and compiling what parses fail with errors at
+        // worst. `cargo fuzz run parse` does the same with many more texts.
+        #[test]
+        fn arbitrary_text(source in texts()) {
+            if let Ok(program) = Parser::new().parse(&source) {
+                let _ = crate::vit::build(program);
+            }
+        }
+    }
 }
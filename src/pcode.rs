@@ -0,0 +1,430 @@
+use std::{collections::HashMap, fmt};
+
+mod optimize;
+
+pub use optimize::optimize;
+
+// The text of p-code, as written by `build`: one instruction or label per line. Tools can
+// inspect a program with `parse`, which also checks that the vm can run it. The vm reads
+// the same syntax with `read`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Procedure {
+    Abs,
+    Min,
+    Max,
+    Sqrt,
+    Floor,
+    Random,
+}
+
+// The operand of `ldc`. Strings hold their value, with escape sequences already replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i32),
+    Float(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Label(String),
+    Ldc(Constant),
+    Lda(usize),
+    Lod(usize),
+    Sto,
+    Rd,
+    Rdf,
+    Rds,
+    Wri,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Cat,
+    Len,
+    Equ,
+    Neq,
+    Grt,
+    Let,
+    Gte,
+    Lte,
+    And,
+    Or,
+    ToInt,
+    ToFloat,
+    Csp(Procedure),
+    Fjp(String),
+    Ujp(String),
+    Stp,
+}
+
+// An error in the line with this number, counting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Parses a whole program and checks that jumps go to labels that exist, that no label is
+// defined twice, that every path reaches each label with the same number of values on the
+// stack, that no instruction takes more values than there are, and that it ends with `stp`.
+pub fn parse(source: &str) -> Result<Vec<Instr>, Error> {
+    let lines = read(source)?;
+    validate(&lines)?;
+    Ok(lines.into_iter().map(|(_, instr)| instr).collect())
+}
+
+// Parses each line on its own, and returns the instructions with the number of their line.
+pub(crate) fn read(source: &str) -> Result<Vec<(usize, Instr)>, Error> {
+    let mut result = vec![];
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let instr = match line.strip_suffix(':') {
+            Some(label) => Instr::Label(label.to_string()),
+            None => parse_instr(line).map_err(|message| Error {
+                line: number + 1,
+                message,
+            })?,
+        };
+        result.push((number + 1, instr));
+    }
+    Ok(result)
+}
+
+fn parse_instr(line: &str) -> Result<Instr, String> {
+    let (operation, operand) = line.split_once(' ').unwrap_or((line, ""));
+
+    let address = || {
+        operand
+            .strip_prefix('#')
+            .and_then(|address| address.parse().ok())
+            .ok_or_else(|| format!("invalid address: {operand}."))
+    };
+
+    Ok(match operation {
+        "ldc" => Instr::Ldc(parse_constant(operand)?),
+        "lda" => Instr::Lda(address()?),
+        "lod" => Instr::Lod(address()?),
+        "fjp" if !operand.is_empty() => Instr::Fjp(operand.to_string()),
+        "ujp" if !operand.is_empty() => Instr::Ujp(operand.to_string()),
+        "to" if operand == "int" => Instr::ToInt,
+        "to" if operand == "float" => Instr::ToFloat,
+        "csp" => Instr::Csp(match operand {
+            "abs" => Procedure::Abs,
+            "min" => Procedure::Min,
+            "max" => Procedure::Max,
+            "sqt" => Procedure::Sqrt,
+            "flr" => Procedure::Floor,
+            "rnd" => Procedure::Random,
+            _ => return Err(format!("unknown procedure: {operand}.")),
+        }),
+        _ if !operand.is_empty() => return Err(format!("unexpected operand: {line}.")),
+        "sto" => Instr::Sto,
+        "rd" => Instr::Rd,
+        "rdf" => Instr::Rdf,
+        "rds" => Instr::Rds,
+        "wri" => Instr::Wri,
+        "add" => Instr::Add,
+        "sub" => Instr::Sub,
+        "mul" => Instr::Mul,
+        "div" => Instr::Div,
+        "pow" => Instr::Pow,
+        "cat" => Instr::Cat,
+        "len" => Instr::Len,
+        "equ" => Instr::Equ,
+        "neq" => Instr::Neq,
+        "grt" => Instr::Grt,
+        "let" => Instr::Let,
+        "gte" => Instr::Gte,
+        "lte" => Instr::Lte,
+        "and" => Instr::And,
+        "or" => Instr::Or,
+        "stp" => Instr::Stp,
+        _ => return Err(format!("unknown instruction: {line}.")),
+    })
+}
+
+fn parse_constant(operand: &str) -> Result<Constant, String> {
+    if let Some(string) = operand
+        .strip_prefix('"')
+        .and_then(|operand| operand.strip_suffix('"'))
+    {
+        return Ok(Constant::Str(unescape(string)));
+    }
+
+    if let Ok(n) = operand.parse() {
+        Ok(Constant::Int(n))
+    } else if let Ok(n) = operand.parse() {
+        Ok(Constant::Float(n))
+    } else {
+        Err(format!("invalid constant: {operand}."))
+    }
+}
+
+// String literals use the escape sequences of the source code, which `vit` replaces when
+// it evaluates them and writes back in p-code.
+pub(crate) fn unescape(string: &str) -> String {
+    let mut result = String::new();
+    let mut chars = string.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('\\') => result.push('\\'),
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn validate(lines: &[(usize, Instr)]) -> Result<(), Error> {
+    let mut labels = HashMap::new(); // Index of each label in `lines`.
+    for (index, (line, instr)) in lines.iter().enumerate() {
+        if let Instr::Label(name) = instr {
+            if labels.insert(name.as_str(), index).is_some() {
+                return Err(error(*line, format!("duplicate label: {name}.")));
+            }
+        }
+    }
+    for (line, instr) in lines {
+        if let Instr::Fjp(label) | Instr::Ujp(label) = instr {
+            if !labels.contains_key(label.as_str()) {
+                return Err(error(*line, format!("undefined label: {label}.")));
+            }
+        }
+    }
+    match lines.last() {
+        Some((_, Instr::Stp)) => (),
+        last => {
+            let line = last.map_or(1, |(line, _)| *line);
+            return Err(error(line, "the program must end with stp.".to_string()));
+        }
+    }
+
+    // Follows every path from the start, with the depth of the stack before each line.
+    let mut depths = vec![None; lines.len()];
+    let mut pending = vec![(0, 0)];
+
+    while let Some((index, depth)) = pending.pop() {
+        let (line, instr) = &lines[index];
+        match (depths[index], instr) {
+            (Some(known), _) if known == depth => continue,
+            (Some(known), Instr::Label(name)) => {
+                return Err(error(
+                    *line,
+                    format!("inconsistent stack depth at {name}: {known} and {depth}."),
+                ));
+            }
+            _ => depths[index] = Some(depth),
+        }
+
+        let (pops, pushes) = effect(instr);
+        if depth < pops {
+            return Err(error(
+                *line,
+                format!("{instr} takes {pops} values, but the stack has {depth}."),
+            ));
+        }
+        let depth = depth - pops + pushes;
+
+        match instr {
+            Instr::Fjp(label) => {
+                pending.push((labels[label.as_str()], depth));
+                pending.push((index + 1, depth));
+            }
+            Instr::Ujp(label) => pending.push((labels[label.as_str()], depth)),
+            Instr::Stp => (),
+            _ => pending.push((index + 1, depth)),
+        }
+    }
+    Ok(())
+}
+
+fn error(line: usize, message: String) -> Error {
+    Error { line, message }
+}
+
+// The number of values an instruction takes from the stack and the number it leaves.
+pub(crate) fn effect(instr: &Instr) -> (usize, usize) {
+    match instr {
+        Instr::Ldc(_) | Instr::Lda(_) | Instr::Lod(_) | Instr::Rd | Instr::Rdf | Instr::Rds => {
+            (0, 1)
+        }
+        Instr::Sto => (2, 0),
+        Instr::Wri | Instr::Fjp(_) => (1, 0),
+        Instr::Len | Instr::ToInt | Instr::ToFloat => (1, 1),
+        Instr::Csp(Procedure::Min | Procedure::Max) => (2, 1),
+        Instr::Csp(_) => (1, 1),
+        Instr::Label(_) | Instr::Ujp(_) | Instr::Stp => (0, 0),
+        _ => (2, 1),
+    }
+}
+
+// Formats the instruction as a line of p-code, so that parsing it gives it back.
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Label(name) => write!(f, "{name}:"),
+            Instr::Ldc(Constant::Int(n)) => write!(f, "ldc {n}"),
+            Instr::Ldc(Constant::Float(n)) => write!(f, "ldc {n:?}"),
+            Instr::Ldc(Constant::Str(s)) => {
+                let escaped = s
+                    .replace('\\', "\\\\")
+                    .replace('\n', "\\n")
+                    .replace('\t', "\\t");
+                write!(f, "ldc \"{escaped}\"")
+            }
+            Instr::Lda(address) => write!(f, "lda #{address}"),
+            Instr::Lod(address) => write!(f, "lod #{address}"),
+            Instr::Fjp(label) => write!(f, "fjp {label}"),
+            Instr::Ujp(label) => write!(f, "ujp {label}"),
+            Instr::ToInt => write!(f, "to int"),
+            Instr::ToFloat => write!(f, "to float"),
+            Instr::Csp(procedure) => write!(
+                f,
+                "csp {}",
+                match procedure {
+                    Procedure::Abs => "abs",
+                    Procedure::Min => "min",
+                    Procedure::Max => "max",
+                    Procedure::Sqrt => "sqt",
+                    Procedure::Floor => "flr",
+                    Procedure::Random => "rnd",
+                }
+            ),
+            instr => write!(f, "{}", format!("{instr:?}").to_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs, path::Path};
+
+    use crate::{modules, parser::Parser, vit};
+
+    #[test]
+    fn generated_code() {
+        let program = Parser::new()
+            .parse("let a: float; read a; if a > 1 { write 'big'; } else { write a; }")
+            .unwrap();
+        let source = vit::build(program).unwrap();
+        let instrs = parse(&source).unwrap();
+
+        assert_eq!(
+            instrs[..6],
+            [
+                Instr::Lda(0),
+                Instr::Rdf,
+                Instr::Sto,
+                Instr::Lod(0),
+                Instr::Ldc(Constant::Int(1)),
+                Instr::Grt,
+            ]
+        );
+        assert!(instrs.contains(&Instr::Fjp("F0".to_string())));
+        assert!(instrs.contains(&Instr::Label("E0".to_string())));
+
+        let text: String = instrs.iter().map(|instr| format!("{instr}\n")).collect();
+        assert_eq!(text, source);
+    }
+
+    #[test]
+    fn examples() {
+        let names = [
+            "collatz",
+            "even_or_odd",
+            "fib",
+            "fizzbuzz",
+            "greeting",
+            "points",
+            "square",
+        ];
+        for name in names {
+            let path = format!("examples/{name}.vit");
+            let modules = modules::load(Path::new(&path), |path| fs::read_to_string(path));
+            let source = vit::link(modules.unwrap()).unwrap();
+            assert_eq!(parse(&source).err(), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            parse("ldc 1\nwri\n\nlda 3\nstp\n").unwrap_err().to_string(),
+            "line 4: invalid address: 3."
+        );
+        assert_eq!(
+            parse("csp foo\n").unwrap_err().to_string(),
+            "line 1: unknown procedure: foo."
+        );
+        assert_eq!(
+            parse("ujp\n").unwrap_err().to_string(),
+            "line 1: unknown instruction: ujp."
+        );
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            parse("L0:\nujp L0\nL0:\nstp\n").unwrap_err(),
+            error(3, "duplicate label: L0.".to_string())
+        );
+        assert_eq!(
+            parse("ldc 1\nfjp E0\nstp\n").unwrap_err(),
+            error(2, "undefined label: E0.".to_string())
+        );
+    }
+
+    #[test]
+    fn stack_depth() {
+        // A loop that leaves a value on the stack each time around.
+        assert_eq!(
+            parse("L0:\nldc 1\nldc 2\nldc 3\nequ\nfjp L0\nstp\n").unwrap_err(),
+            error(1, "inconsistent stack depth at L0: 0 and 1.".to_string())
+        );
+        assert_eq!(
+            parse("ldc 1\nadd\nstp\n").unwrap_err(),
+            error(2, "add takes 2 values, but the stack has 1.".to_string())
+        );
+        // Code after an unconditional jump is only reached through its label.
+        assert!(parse("ldc 1\nujp E0\nwri\nE0:\nwri\nstp\n").is_ok());
+    }
+
+    #[test]
+    fn end() {
+        assert_eq!(
+            parse("ldc 1\nwri\n").unwrap_err(),
+            error(2, "the program must end with stp.".to_string())
+        );
+        assert_eq!(
+            parse("").unwrap_err(),
+            error(1, "the program must end with stp.".to_string())
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{Constant, Instr};
+
+// Rewrites a program into a shorter one that does the same, looking at a few instructions
+// at a time, until none of these rules applies:
+//
+// - `add`, `sub`, `mul` and `div` of two integer constants become their result, unless it
+//   overflows or divides by zero, which is left for the vm to report as it runs;
+// - `to int` and `to float` of a constant become the converted constant;
+// - a jump to a label followed by `ujp` goes straight where that `ujp` goes;
+// - `ujp` to the instruction right after it goes, and so does code that follows `ujp` or
+//   `stp` and isn't reached through a label;
+// - labels that no jump uses go.
+pub fn optimize(mut program: Vec<Instr>) -> Vec<Instr> {
+    loop {
+        let optimized = remove_dead(thread(fold(program.clone())));
+        if optimized == program {
+            return program;
+        }
+        program = optimized;
+    }
+}
+
+fn fold(program: Vec<Instr>) -> Vec<Instr> {
+    let mut result: Vec<Instr> = vec![];
+    for instr in program {
+        // The number of constants the instruction takes, and what they become.
+        let folded = match (&result[..], &instr) {
+            ([.., Instr::Ldc(Constant::Int(n))], Instr::ToInt) => Some((1, Constant::Int(*n))),
+            ([.., Instr::Ldc(Constant::Float(n))], Instr::ToInt) => {
+                Some((1, Constant::Int(*n as i32)))
+            }
+            ([.., Instr::Ldc(Constant::Int(n))], Instr::ToFloat) => {
+                Some((1, Constant::Float(*n as f64)))
+            }
+            ([.., Instr::Ldc(Constant::Float(n))], Instr::ToFloat) => {
+                Some((1, Constant::Float(*n)))
+            }
+            ([.., Instr::Ldc(Constant::Int(l)), Instr::Ldc(Constant::Int(r))], _) => {
+                arithmetic(&instr, *l, *r).map(|n| (2, Constant::Int(n)))
+            }
+            _ => None,
+        };
+        match folded {
+            Some((operands, constant)) => {
+                result.truncate(result.len() - operands);
+                result.push(Instr::Ldc(constant));
+            }
+            None => result.push(instr),
+        }
+    }
+    result
+}
+
+fn arithmetic(instr: &Instr, l: i32, r: i32) -> Option<i32> {
+    match instr {
+        Instr::Add => l.checked_add(r),
+        Instr::Sub => l.checked_sub(r),
+        Instr::Mul => l.checked_mul(r),
+        Instr::Div => l.checked_div(r),
+        _ => None,
+    }
+}
+
+fn thread(mut program: Vec<Instr>) -> Vec<Instr> {
+    let mut next = HashMap::new(); // Where each label followed by `ujp` leads.
+    for window in program.windows(2) {
+        if let [Instr::Label(label), Instr::Ujp(target)] = window {
+            next.insert(label.clone(), target.clone());
+        }
+    }
+
+    for instr in &mut program {
+        if let Instr::Fjp(label) | Instr::Ujp(label) = instr {
+            // A loop of jumps never ends, so it's followed only until it comes back.
+            let mut seen = HashSet::new();
+            while let Some(target) = next.get(label.as_str()) {
+                if !seen.insert(target.clone()) {
+                    break;
+                }
+                *label = target.clone();
+            }
+        }
+    }
+    program
+}
+
+fn remove_dead(program: Vec<Instr>) -> Vec<Instr> {
+    let used: HashSet<&str> = program
+        .iter()
+        .filter_map(|instr| match instr {
+            Instr::Fjp(label) | Instr::Ujp(label) => Some(label.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut result = vec![];
+    let mut reachable = true;
+    for (index, instr) in program.iter().enumerate() {
+        match instr {
+            Instr::Label(label) if !used.contains(label.as_str()) => continue,
+            Instr::Label(_) => reachable = true,
+            // The program still has to end with `stp`.
+            _ if !reachable && index + 1 < program.len() => continue,
+            Instr::Ujp(label)
+                if program[index + 1..]
+                    .iter()
+                    .take_while(|instr| matches!(instr, Instr::Label(_)))
+                    .any(|instr| *instr == Instr::Label(label.clone())) =>
+            {
+                continue
+            }
+            Instr::Ujp(_) | Instr::Stp => reachable = false,
+            _ => (),
+        }
+        result.push(instr.clone());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::{
+        modules,
+        pcode::parse,
+        vit,
+        vm::{Machine, Overflow},
+    };
+
+    fn optimized(source: &str) -> String {
+        let program = optimize(parse(source).unwrap());
+        program.iter().map(|instr| format!("{instr}\n")).collect()
+    }
+
+    #[test]
+    fn constants() {
+        // 7 % 2, as vit compiles it.
+        assert_eq!(
+            optimized("lda #0\nldc 7\nldc 7\nldc 2\ndiv\nto int\nldc 2\nmul\nsub\nsto\nstp\n"),
+            "lda #0\nldc 1\nsto\nstp\n"
+        );
+        assert_eq!(
+            optimized("ldc 2\nto float\nldc 2.5\nto int\nadd\nwri\nstp\n"),
+            "ldc 2.0\nldc 2\nadd\nwri\nstp\n"
+        );
+        // What would fail at run time still does.
+        assert_eq!(
+            optimized("ldc 1\nldc 0\ndiv\nldc 2147483647\nldc 1\nadd\nadd\nwri\nstp\n"),
+            "ldc 1\nldc 0\ndiv\nldc 2147483647\nldc 1\nadd\nadd\nwri\nstp\n"
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        // A loop whose body ends with `break`, then an if without anything after it.
+        let source =
+            "L0:\nlod #0\nwri\nujp E0\nujp L0\nE0:\nlod #0\nfjp F1\nujp E1\nF1:\nE1:\nstp\n";
+        assert_eq!(optimized(source), "lod #0\nwri\nlod #0\nfjp F1\nF1:\nstp\n");
+
+        // Jumps to jumps, including a loop of them.
+        let source = "ldc 1\nfjp A\nujp B\nA:\nujp B\nB:\nujp C\nC:\nujp B\nstp\n";
+        assert_eq!(optimized(source), "ldc 1\nfjp B\nB:\nujp B\nstp\n");
+    }
+
+    #[test]
+    fn examples() {
+        for name in ["collatz", "fib", "fizzbuzz", "points", "square"] {
+            let path = format!("examples/{name}.vit");
+            let modules = modules::load(Path::new(&path), |path| fs::read_to_string(path));
+            let source = vit::link(modules.unwrap()).unwrap();
+            let program = optimized(&source);
+            assert_eq!(parse(&program).err(), None, "{name}");
+            assert!(program.lines().count() <= source.lines().count(), "{name}");
+
+            let run = |code: &str| {
+                let mut output = vec![];
+                let machine = Machine::load(code).unwrap().with_overflow(Overflow::Trap);
+                machine
+                    .run(&mut "7\n1\n2\n3\n".as_bytes(), &mut output)
+                    .unwrap();
+                String::from_utf8(output).unwrap()
+            };
+            assert_eq!(run(&program), run(&source), "{name}");
+        }
+    }
+}
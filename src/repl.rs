@@ -0,0 +1,198 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    parser::Parser,
+    vit::Session,
+    vm::{Machine, Memory},
+};
+
+// Reads statements from `input` and runs each entry as soon as it is complete. Entries
+// go on for as many lines as it takes to close their braces. The symbols and the memory
+// outlive every entry, until `:reset`. Lines starting with `:` are meta-commands:
+//
+//   :ast <code>    shows the statements the code parses into, without running it
+//   :pcode <code>  shows the p-code generated for the code, without running it
+//   :vars          shows the symbols in scope, with the address of each variable
+//   :reset         forgets every symbol and value
+//
+// Programs that `read` take their input from the lines after the entry.
+pub fn run(seed: u64, input: &mut impl BufRead, output: &mut impl Write) -> Result<(), String> {
+    let parser = Parser::new();
+    let mut repl = Repl {
+        session: Session::new(),
+        memory: Memory::new(seed),
+        seed,
+    };
+
+    while let Some(entry) = read_entry(input, output)? {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        if let Err(message) = repl.evaluate(&parser, entry, input, output) {
+            writeln!(output, "error: {message}").map_err(|e| e.to_string())?;
+        }
+    }
+    output.flush().map_err(|e| e.to_string())
+}
+
+struct Repl {
+    session: Session,
+    memory: Memory,
+    seed: u64,
+}
+
+impl Repl {
+    fn evaluate(
+        &mut self,
+        parser: &Parser,
+        entry: &str,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), String> {
+        let (command, code) = match entry.strip_prefix(':') {
+            Some(meta) => meta.split_once(char::is_whitespace).unwrap_or((meta, "")),
+            None => ("", entry),
+        };
+
+        let text = match command {
+            "" => {
+                let code = self.session.compile(parser.parse(code)?)?;
+                let machine = Machine::load(&code)?;
+                return Ok(machine.run_in(&mut self.memory, input, output)?);
+            }
+            "ast" => parser
+                .parse(code)?
+                .iter()
+                .map(|statement| format!("{statement:#?}\n"))
+                .collect(),
+            // Compiled in a copy of the session, so declarations don't take effect.
+            "pcode" => {
+                let mut session = self.session.clone();
+                session.compile(parser.parse(code)?)?
+            }
+            "vars" => self
+                .session
+                .symbols()
+                .iter()
+                .map(|line| format!("{line}\n"))
+                .collect(),
+            "reset" => {
+                self.session = Session::new();
+                self.memory = Memory::new(self.seed);
+                String::new()
+            }
+            _ => return Err(format!("unknown command: :{command}.")),
+        };
+        write!(output, "{text}").map_err(|e| e.to_string())
+    }
+}
+
+// Reads the lines of the next entry, prompting for each. Returns `None` at the end of the
+// input.
+fn read_entry(input: &mut impl BufRead, output: &mut impl Write) -> Result<Option<String>, String> {
+    let mut entry = String::new();
+    loop {
+        let prompt = if entry.is_empty() { "> " } else { ". " };
+        write!(output, "{prompt}").map_err(|e| e.to_string())?;
+        output.flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok((!entry.is_empty()).then_some(entry));
+        }
+        entry.push_str(&line);
+
+        if depth(&entry) <= 0 {
+            return Ok(Some(entry));
+        }
+    }
+}
+
+// The number of braces left open in `code`. Braces inside strings, module paths and
+// comments don't count.
+fn depth(code: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '/') if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            (None, '{') => depth += 1,
+            (None, '}') => depth -= 1,
+            _ => (),
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input: &str) -> String {
+        let mut output = vec![];
+        run(0, &mut input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn persistent_state() {
+        assert_eq!(
+            session("let a = 2;\nlet b = a * 3;\nwrite b;\na = a + b;\nwrite a;\n"),
+            "> > > 6> > 8> "
+        );
+        assert_eq!(
+            session("let n;\nread n;\n41\nn = n + 1;\nwrite n;\n"),
+            "> > > > 42> "
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            session("let i = 0;\nloop {\n  i = i + 1;\n  if i == 3 { break; }\n}\nwrite i;\n"),
+            "> > . . . > 3> "
+        );
+        assert_eq!(session("write '{';\n"), "> {> ");
+        assert_eq!(depth("if a { write '}'; "), 1);
+        assert_eq!(depth("loop { // }\n"), 1);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            session("let a = 1;\nlet a = 2;\nwrite a;\n"),
+            "> > error: variable already declared: a.\n> 1> "
+        );
+        assert!(session("let b = ;\n").starts_with("> error: expected ["));
+        // A failed entry declares nothing.
+        assert_eq!(
+            session("let b = 1 + 'x';\nlet b = 2;\nwrite b;\n"),
+            session("let c = 1 + 'x';\nlet b = 2;\nwrite b;\n")
+        );
+        assert_eq!(session(":nope\n"), "> error: unknown command: :nope.\n> ");
+    }
+
+    #[test]
+    fn meta_commands() {
+        assert_eq!(
+            session("let a = 1;\n:pcode let b = a;\n:vars\n"),
+            "> > lda #1\nlod #0\nsto\nstp\n> a #0 int\n> "
+        );
+        assert_eq!(
+            session("const N = 3;\nstruct P { x, y }\nlet p: P;\np.y = 1.5;\n:vars\n"),
+            "> > > > > p.x #0 unknown\np.y #1 float\np: P\nN = 3\n> "
+        );
+        assert_eq!(session(":ast write x;\n"), "> WriteId(\n    \"x\",\n)\n> ");
+        assert_eq!(
+            session("let a = 5;\n:reset\n:vars\nlet a = 1;\nwrite a;\n"),
+            "> > > > > 1> "
+        );
+    }
+}
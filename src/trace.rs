@@ -0,0 +1,196 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{BufRead, Write},
+};
+
+use crate::{
+    vit::DebugInfo,
+    vm::{Machine, Process, RuntimeError},
+};
+
+// Runs the program, writing to `trace` each instruction it runs, with its index and the
+// stack after it, bottom first. The output of the program is flushed after every
+// instruction, so that it shows up in order when both go to the terminal.
+pub fn trace(
+    machine: &Machine,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    trace: &mut impl Write,
+) -> Result<(), RuntimeError> {
+    let mut process = machine.start();
+    while !process.finished() {
+        let pc = process.pc();
+        process.step(input, output)?;
+        output.flush().map_err(|e| e.to_string())?;
+
+        let stack: Vec<String> = process.stack().iter().map(|value| value.quoted()).collect();
+        writeln!(
+            trace,
+            "{pc:>5}  {:<16} [{}]",
+            process.instruction(pc),
+            stack.join(", ")
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(output.flush().map_err(|e| e.to_string())?)
+}
+
+// Runs the program and writes to `report` how many times each source line and each
+// instruction ran, the most run first. A line counts the instructions of its own
+// statements as `self`, and adds those of the statements nested in them as `total`, so
+// the loops that run the most come first. `source` is the text of the main module.
+//
+// The report is written even when the program fails, before returning the error.
+pub fn profile(
+    machine: &Machine,
+    info: &DebugInfo,
+    source: &str,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    report: &mut impl Write,
+) -> Result<(), RuntimeError> {
+    let mut process = machine.start();
+    let mut counts = vec![];
+    let result = count(&mut process, &mut counts, input, output);
+
+    let mut lines: BTreeMap<usize, (u64, u64)> = BTreeMap::new(); // Self and total, by line.
+    for (pc, &count) in counts.iter().enumerate() {
+        // Enclosing lines come first, so the last one is the innermost.
+        let numbers: Vec<usize> = info
+            .lines
+            .iter()
+            .filter(|line| line.code.contains(&pc))
+            .map(|line| line.span.line(source))
+            .collect();
+        if let Some(&innermost) = numbers.last() {
+            lines.entry(innermost).or_default().0 += count;
+        }
+        for number in numbers.into_iter().collect::<BTreeSet<_>>() {
+            lines.entry(number).or_default().1 += count;
+        }
+    }
+    let mut lines: Vec<_> = lines
+        .into_iter()
+        .filter(|(_, (_, total))| *total > 0)
+        .collect();
+    lines.sort_by_key(|&(number, (_, total))| (u64::MAX - total, number));
+
+    let mut instructions: Vec<(usize, u64)> = counts
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    instructions.sort_by_key(|&(pc, count)| (u64::MAX - count, pc));
+
+    let mut text = format!("{} instructions run.\n", counts.iter().sum::<u64>());
+    text.push_str("\n line      self     total  source\n");
+    for (number, (own, total)) in lines {
+        let code = source.lines().nth(number - 1).unwrap_or_default().trim();
+        text.push_str(&format!("{number:>5} {own:>9} {total:>9}  {code}\n"));
+    }
+    text.push_str("\nindex     count  instruction\n");
+    for (pc, count) in instructions {
+        text.push_str(&format!(
+            "{pc:>5} {count:>9}  {}\n",
+            process.instruction(pc)
+        ));
+    }
+    write!(report, "{text}").map_err(|e| e.to_string())?;
+
+    result
+}
+
+// Runs the program, counting how many times the instruction at each index runs.
+fn count(
+    process: &mut Process,
+    counts: &mut Vec<u64>,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<(), RuntimeError> {
+    while !process.finished() {
+        let pc = process.pc();
+        if counts.len() <= pc {
+            counts.resize(pc + 1, 0);
+        }
+        counts[pc] += 1;
+        process.step(input, output)?;
+    }
+    Ok(output.flush().map_err(|e| e.to_string())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{modules, vit};
+
+    fn machine(source: &str) -> (Machine, DebugInfo) {
+        let modules = modules::load(Path::new("main.vit"), |_| Ok(source.to_string()));
+        let (code, info) = vit::link_debug(modules.unwrap()).unwrap();
+        (Machine::load(&code).unwrap(), info)
+    }
+
+    #[test]
+    fn traced() {
+        let (machine, _) = machine("let a = 'x';\nlet b = 7 % 2;\nwrite b;\n");
+        let (mut output, mut trace) = (vec![], vec![]);
+        super::trace(&machine, &mut "".as_bytes(), &mut output, &mut trace).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "1");
+        assert_eq!(
+            String::from_utf8(trace).unwrap(),
+            "    0  lda #0           [#0]
+    1  ldc \"x\"          [#0, 'x']
+    2  sto              []
+    3  lda #1           [#1]
+    4  ldc 7            [#1, 7]
+    5  ldc 7            [#1, 7, 7]
+    6  ldc 2            [#1, 7, 7, 2]
+    7  div              [#1, 7, 3]
+    8  to int           [#1, 7, 3]
+    9  ldc 2            [#1, 7, 3, 2]
+   10  mul              [#1, 7, 6]
+   11  sub              [#1, 1]
+   12  sto              []
+   13  lod #1           [1]
+   14  wri              []
+"
+        );
+    }
+
+    #[test]
+    fn profiled() {
+        let source = "let i = 0;\nloop {\n    i = i + 1;\n    if i == 3 { break; }\n}\n";
+        let (machine, info) = machine(source);
+        let mut report = vec![];
+        profile(
+            &machine,
+            &info,
+            source,
+            &mut "".as_bytes(),
+            &mut vec![],
+            &mut report,
+        )
+        .unwrap();
+
+        let report = String::from_utf8(report).unwrap();
+        let (lines, instructions) = report.split_once("\n\nindex").unwrap();
+        assert_eq!(
+            lines,
+            "33 instructions run.
+
+ line      self     total  source
+    2         2        30  loop {
+    3        15        15  i = i + 1;
+    4        13        13  if i == 3 { break; }
+    1         3         3  let i = 0;"
+        );
+        assert!(instructions.starts_with(
+            "     count  instruction
+    3         3  lda #0
+"
+        ));
+    }
+}
@@ -1,31 +1,219 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::{
+    ast::{Expr, Identifier, Span, Spanned, Statement},
+    ir::{self, Block, Constant, Type},
+    modules::Module,
+    pcode,
+};
+
+mod builtins;
+mod constants;
+mod emit;
+mod expressions;
+mod imports;
+mod records;
+mod verify;
 
-use crate::ast::{Expr, Statement};
+use imports::Exports;
 
-mod expressions;
+pub fn build(program: Vec<Spanned<Statement>>) -> Result<String, String> {
+    let mut state = State::new();
+    let statements = state.run(program)?;
+    checked(emit::program(&state.close(statements)))
+}
+
+// Compiles modules returned by `modules::load` into a single program. Each module runs in
+// its own scope before the modules that import it; the last module is the main one.
+pub fn link(modules: Vec<Module>) -> Result<String, String> {
+    checked(emit::program(&lower(modules)?))
+}
+
+// Compiles a program one piece at a time, as the REPL does. Each piece sees the symbols
+// declared by the pieces before it, and its variables take the addresses after theirs.
+#[derive(Clone)]
+pub struct Session {
+    state: State,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            state: State::new(),
+        }
+    }
+
+    // Generates the p-code of `program`, which ends with `stp`. When it fails, the symbols
+    // are left as they were before.
+    pub fn compile(&mut self, program: Vec<Spanned<Statement>>) -> Result<String, String> {
+        let mut state = self.state.clone();
+        let statements = state.run(program)?;
+        let code = checked(emit::program(&Block {
+            variables: vec![],
+            statements,
+        }))?;
+        self.state = state;
+        Ok(code)
+    }
+
+    // Describes the symbols in scope, a line each: `name #address type` for variables
+    // and `name = value` for constants. Records list their fields as variables.
+    pub fn symbols(&self) -> Vec<String> {
+        let mut result = vec![];
+        for scope in &self.state.stack {
+            let mut symbols: Vec<_> = scope.iter().collect();
+            symbols.sort_by_key(|(name, symbol)| match symbol {
+                Symbol::Variable(variable) => (0, variable.address, name.as_str()),
+                Symbol::Record(_) => (1, 0, name.as_str()),
+                Symbol::Constant(_) => (2, 0, name.as_str()),
+            });
+            result.extend(
+                symbols
+                    .into_iter()
+                    .map(|(name, symbol)| symbol.describe(name)),
+            );
+        }
+        result
+    }
+}
+
+// In debug builds, makes sure the generated code keeps its stack balanced.
+fn checked(code: String) -> Result<String, String> {
+    if cfg!(debug_assertions) {
+        verify::verify(&code).map_err(|e| format!("invalid generated code: {e}"))?;
+    }
+    Ok(code)
+}
 
-pub fn build(program: Vec<Statement>) -> Result<String, String> {
-    let mut result = String::new();
+// Like `link`, but also maps the code back to the source of the main module.
+pub fn link_debug(modules: Vec<Module>) -> Result<(String, DebugInfo), String> {
+    let program = lower(modules)?;
+    Ok((
+        checked(emit::program(&program))?,
+        emit::debug_info(&program),
+    ))
+}
+
+// Where the code of a program comes from. Code is given as a range of instruction indices,
+// which don't count labels, like the jumps of the machine.
+#[derive(Default)]
+pub struct DebugInfo {
+    pub lines: Vec<Line>,
+    pub scopes: Vec<Scope>,
+}
+
+impl DebugInfo {
+    // The innermost statement whose code includes the instruction at `pc`.
+    pub fn line_at(&self, pc: usize) -> Option<&Line> {
+        self.lines.iter().rev().find(|line| line.code.contains(&pc))
+    }
+}
+
+// The code of the statement in `span`, including the statements nested in it.
+pub struct Line {
+    pub code: Range<usize>,
+    pub span: Span,
+}
+
+// The code that runs while `variables` are in scope.
+pub struct Scope {
+    pub code: Range<usize>,
+    pub variables: Vec<ir::Variable>,
+}
+
+// Like `link`, but returns the program in the form the other backends take.
+pub(crate) fn lower(modules: Vec<Module>) -> Result<Block, String> {
     let mut state = State::new();
+    let statements = state.lower(modules)?;
+    Ok(state.close(statements))
+}
 
-    for statement in program {
-        result.push_str(&state.parse_statement(statement)?);
+// Checks a program without generating its code, as editors do while it's written.
+pub fn analyze(modules: Vec<Module>) -> Analysis {
+    let mut state = State::new();
+    let error = state.lower(modules).err();
+    // Closing the scopes left open gives the variables in them their types.
+    while !state.stack.is_empty() {
+        state.pop_scope();
+    }
+    Analysis {
+        definitions: state.definitions,
+        error: error.map(|message| (state.failed, message)),
     }
-    result.push_str("stp\n");
-    Ok(result)
 }
 
+// What `analyze` finds: the names the main module declares, and the first error, if
+// any. An error found in the main module comes with the span of its statement.
+pub struct Analysis {
+    pub definitions: Vec<Definition>,
+    pub error: Option<(Option<Span>, String)>,
+}
+
+// A variable, constant or record declared by the statement in `span`. `symbol` describes
+// it like `Session::symbols` does, with the type the variable ends up with.
+#[derive(Clone)]
+pub struct Definition {
+    pub name: String,
+    pub span: Span,
+    pub symbol: String,
+    address: Option<u32>,
+}
+
+#[derive(Clone)]
 struct Variable {
     address: u32,
     initialized: bool,
+    ty: Type,
+}
+
+// A record doesn't hold a value itself. Each of its fields is a variable named
+// `record.field`, stored in consecutive addresses starting at the record's own.
+#[derive(Clone)]
+enum Symbol {
+    Variable(Variable),
+    Constant(Constant),
+    Record(Identifier),
+}
+
+impl Symbol {
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Symbol::Variable(variable) => format!("{name} #{} {}", variable.address, variable.ty),
+            Symbol::Record(record) => format!("{name}: {record}"),
+            Symbol::Constant(constant) => format!("{name} = {constant}"),
+        }
+    }
+}
+
+impl From<Variable> for Symbol {
+    fn from(variable: Variable) -> Self {
+        Symbol::Variable(variable)
+    }
 }
 
-// Generates p-code from the AST created by the parser.
+// Checks the AST created by the parser and lowers it into the IR, from which p-code is
+// generated.
+#[derive(Clone)]
 struct State {
-    stack: Vec<HashMap<String, Variable>>,
+    stack: Vec<HashMap<String, Symbol>>,
+    structs: HashMap<String, Vec<(Identifier, Type)>>, // Field layout of each struct, in order.
     current_address: u32, // When the next value is stored, it will go in this address.,
     label_count: u32,
     labels: Vec<u32>,
+    modules: HashMap<String, Exports>, // Modules already compiled, which can be imported.
+    located: bool, // Whether statements keep their span; only the main module does.
+    span: Span,    // The statement being compiled, if located.
+    failed: Option<Span>, // The innermost located statement that failed to compile.
+    definitions: Vec<Definition>, // The names declared by located statements.
 }
 
 impl State {
@@ -33,25 +221,103 @@ impl State {
         let stack = vec![HashMap::new()];
         State {
             stack,
+            structs: HashMap::new(),
             current_address: 0,
             label_count: 0,
             labels: vec![],
+            modules: HashMap::new(),
+            located: true,
+            span: Span::default(),
+            failed: None,
+            definitions: vec![],
         }
     }
 
-    pub fn run(&mut self, program: Vec<Statement>) -> Result<String, String> {
-        let mut result = String::new();
+    pub fn run(&mut self, program: Vec<Spanned<Statement>>) -> Result<Vec<ir::Statement>, String> {
+        let mut result = vec![];
 
         for statement in program {
-            result.push_str(&self.parse_statement(statement)?);
+            if self.located {
+                self.span = statement.span;
+            }
+            let code = self.parse_statement(statement.node).inspect_err(|_| {
+                if self.located {
+                    self.failed.get_or_insert(statement.span);
+                }
+            })?;
+            if self.located && !code.is_empty() {
+                result.push(ir::Statement::Located(statement.span, code));
+            } else {
+                result.extend(code);
+            }
         }
-
         Ok(result)
     }
 
-    fn parse_statement(&mut self, statement: Statement) -> Result<String, String> {
+    // Compiles the modules returned by `modules::load` in the outermost scope.
+    fn lower(&mut self, modules: Vec<Module>) -> Result<Vec<ir::Statement>, String> {
+        let mut statements = vec![];
+        let main = modules.len().saturating_sub(1);
+
+        for (index, module) in modules.into_iter().enumerate() {
+            self.located = index == main;
+            let code = if index == main {
+                self.run(module.program)
+            } else {
+                self.run_module(module.name, module.program)
+                    .map(|block| vec![ir::Statement::Block(block)])
+            };
+
+            statements.extend(code.map_err(|e| format!("{}: {e}", module.path.display()))?);
+        }
+        Ok(statements)
+    }
+
+    // Keeps track of a name declared by the current statement, if it's located.
+    fn define(&mut self, name: &str) {
+        if !self.located {
+            return;
+        }
+        let symbol = &self.stack.last().unwrap()[name];
+        self.definitions.push(Definition {
+            name: name.to_string(),
+            span: self.span,
+            symbol: symbol.describe(name),
+            address: match symbol {
+                Symbol::Variable(variable) => Some(variable.address),
+                _ => None,
+            },
+        });
+    }
+
+    // Turns the statements compiled in the outermost scope into the whole program.
+    fn close(mut self, statements: Vec<ir::Statement>) -> Block {
+        Block {
+            variables: self.pop_scope(),
+            statements,
+        }
+    }
+
+    // Compiles `program` in a scope of its own. Structs declared in it go out of scope with
+    // its variables.
+    fn block(&mut self, program: Vec<Spanned<Statement>>) -> Result<Block, String> {
+        let structs: HashSet<String> = self.structs.keys().cloned().collect();
+
+        self.push_scope();
+        let statements = self.run(program)?;
+        self.structs.retain(|id, _| structs.contains(id));
+        Ok(Block {
+            variables: self.pop_scope(),
+            statements,
+        })
+    }
+
+    fn parse_statement(&mut self, statement: Statement) -> Result<Vec<ir::Statement>, String> {
         match statement {
-            Statement::Declaration(id, expr) => self.declare(id, expr),
+            Statement::Declaration(id, ty, expr) => self.declare(id, ty, expr),
+            Statement::Constant(id, expr) => self.declare_constant(id, *expr),
+            Statement::Struct(id, fields) => self.declare_struct(id, fields),
+            Statement::Import(path) => self.import(path),
             Statement::Assignment(id, expr) => self.assign(id, *expr),
             Statement::Read(id) => self.read(id),
             Statement::WriteId(id) => self.write(id),
@@ -68,117 +334,132 @@ impl State {
     fn if_statement(
         &mut self,
         predicate: Expr,
-        if_block: Vec<Statement>,
-        else_block: Option<Vec<Statement>>,
-    ) -> Result<String, String> {
-        let mut result = String::new();
-
+        if_block: Vec<Spanned<Statement>>,
+        else_block: Option<Vec<Spanned<Statement>>>,
+    ) -> Result<Vec<ir::Statement>, String> {
         let label = self.label_count;
         self.label_count += 1;
 
-        Self::parse_expression(&mut self.stack, predicate, &mut result)?;
-        result.push_str(&format!(
-            "fjp {}{}\n",
-            if else_block.is_some() { "F" } else { "E" },
-            label
-        )); // Jump to else if condition is false.
-
-        // IF-BLOCK
-        self.push_scope();
-        result.push_str(&self.run(if_block)?);
-        self.pop_scope();
-
-        // ELSE-BLOCK
-        if let Some(e_block) = else_block {
-            result.push_str(&format!("ujp E{label}\n")); // Jump to the end of the else block.
-
-            self.push_scope();
-            result.push_str(&format!("F{label}:\n"));
-            result.push_str(&self.run(e_block)?);
-            self.pop_scope();
-        }
-        result.push_str(&format!("E{label}:\n"));
+        let condition = Self::parse_expression(&mut self.stack, predicate)?;
+        let if_block = self.block(if_block)?;
+        let else_block = match else_block {
+            Some(e_block) => Some(self.block(e_block)?),
+            None => None,
+        };
 
-        Ok(result)
+        Ok(vec![ir::Statement::If(
+            label, condition, if_block, else_block,
+        )])
     }
 
-    fn break_loop(&mut self) -> Result<String, String> {
-        if self.labels.is_empty() {
-            return Err("break not inside a loop.".to_string());
+    fn break_loop(&mut self) -> Result<Vec<ir::Statement>, String> {
+        match self.labels.last() {
+            Some(label) => Ok(vec![ir::Statement::Break(*label)]),
+            None => Err("break not inside a loop.".to_string()),
         }
-        Ok(format!("ujp E{}\n", self.labels.last().unwrap()))
     }
 
-    fn do_until(&mut self, expr: Expr, block: Vec<Statement>) -> Result<String, String> {
-        let mut result = String::new();
+    fn do_until(
+        &mut self,
+        expr: Expr,
+        block: Vec<Spanned<Statement>>,
+    ) -> Result<Vec<ir::Statement>, String> {
         let label = self.label_count;
         self.label_count += 1;
         self.labels.push(label);
         self.push_scope();
 
-        result.push_str(&format!("L{label}:\n"));
-
-        result.push_str(&self.run(block)?);
+        let statements = self.run(block)?;
+        let condition = Self::parse_expression(&mut self.stack, expr)?;
 
-        Self::parse_expression(&mut self.stack, expr, &mut result)?;
-        result.push_str(&format!("fjp L{label}\nE{label}:\n"));
-        self.pop_scope();
+        let block = Block {
+            variables: self.pop_scope(),
+            statements,
+        };
         self.labels.pop();
-        Ok(result)
+        Ok(vec![ir::Statement::Until(label, block, condition)])
     }
 
-    fn u_loop(&mut self, block: Vec<Statement>) -> Result<String, String> {
-        let mut result = String::new();
-
+    fn u_loop(&mut self, block: Vec<Spanned<Statement>>) -> Result<Vec<ir::Statement>, String> {
         let label = self.label_count;
         self.labels.push(label);
         self.label_count += 1;
-        self.push_scope();
 
-        result.push_str(&format!("L{label}:\n"));
+        let block = self.block(block)?;
 
-        for statement in block {
-            result.push_str(&self.parse_statement(statement)?);
-        }
-
-        result.push_str(&format!("ujp L{label}\nE{label}:\n")); // This label allows the program to break from the loop.
         self.labels.pop();
-        self.pop_scope();
-        Ok(result)
+        Ok(vec![ir::Statement::Loop(label, block)])
     }
 
-    fn read(&mut self, id: String) -> Result<String, String> {
-        let variable = Self::get_address(&mut self.stack, &id)?;
+    fn read(&mut self, id: String) -> Result<Vec<ir::Statement>, String> {
+        let variable = match Self::get_symbol_mut(&mut self.stack, &id)? {
+            Symbol::Constant(_) => return Err(format!("cannot read into constant: {id}.")),
+            Symbol::Record(name) => return Err(format!("record used as a value: {id} ({name}).")),
+            Symbol::Variable(variable) => variable,
+        };
+        if variable.ty == Type::Unknown {
+            variable.ty = Type::Int;
+        }
         variable.initialized = true;
 
-        Ok(format!("lda #{}\nrd\nsto\n", variable.address))
+        Ok(vec![ir::Statement::Read(variable.address, variable.ty)])
     }
 
-    fn write(&mut self, id: String) -> Result<String, String> {
-        let variable = Self::get_address(&mut self.stack, &id)?;
+    fn write(&mut self, id: String) -> Result<Vec<ir::Statement>, String> {
+        let variable = match Self::get_symbol_mut(&mut self.stack, &id)? {
+            Symbol::Constant(constant) => {
+                let value = ir::Expr::constant(constant.clone());
+                return Ok(vec![ir::Statement::Write(value)]);
+            }
+            Symbol::Record(name) => return Err(format!("record used as a value: {id} ({name}).")),
+            Symbol::Variable(variable) => variable,
+        };
 
         if !variable.initialized {
             return Err("unitialized variable.".to_string());
         }
 
-        Ok(format!("lod #{}\nwri\n", variable.address))
+        Ok(vec![ir::Statement::Write(ir::Expr {
+            ty: variable.ty,
+            kind: ir::ExprKind::Load(variable.address),
+        })])
     }
 
-    fn write_string(&mut self, string: String) -> Result<String, String> {
-        Ok(format!("ldc \"{}\"\nwri\n", string.replace("'", "")))
+    fn write_string(&mut self, string: String) -> Result<Vec<ir::Statement>, String> {
+        let value = Constant::Str(pcode::unescape(&string.replace("'", "")));
+        Ok(vec![ir::Statement::Write(ir::Expr::constant(value))])
     }
 
-    fn assign(&mut self, id: String, expr: Expr) -> Result<String, String> {
-        let address = Self::get_address(&mut self.stack, &id)?.address;
+    fn assign(&mut self, id: String, expr: Expr) -> Result<Vec<ir::Statement>, String> {
+        let address = match Self::get_symbol_mut(&mut self.stack, &id)? {
+            Symbol::Constant(_) => return Err(format!("cannot assign to constant: {id}.")),
+            Symbol::Record(name) => return Err(format!("record used as a value: {id} ({name}).")),
+            Symbol::Variable(variable) => variable.address,
+        };
 
-        let mut result = format!("lda #{address}\n");
-        Self::parse_expression(&mut self.stack, expr, &mut result)?;
+        let value = Self::parse_expression(&mut self.stack, expr)?;
+        let value = Self::store(Self::get_address(&mut self.stack, &id)?, value)?;
+        Ok(vec![ir::Statement::Store(address, value)])
+    }
 
-        result.push_str("sto\n");
-        Ok(result)
+    // Prepares `value` to be stored in `variable`, converting between numeric types if
+    // needed. An untyped variable takes the type of the first value stored in it.
+    fn store(variable: &mut Variable, value: ir::Expr) -> Result<ir::Expr, String> {
+        if variable.ty == Type::Unknown {
+            variable.ty = value.ty;
+        }
+
+        let value = Self::convert(value, variable.ty)?;
+        variable.initialized = true;
+        Ok(value)
     }
 
-    fn declare(&mut self, id: String, e: Option<Box<Expr>>) -> Result<String, String> {
+    fn declare(
+        &mut self,
+        id: String,
+        annotation: Option<Identifier>,
+        e: Option<Box<Expr>>,
+    ) -> Result<Vec<ir::Statement>, String> {
         if self.stack.is_empty() {
             self.stack.push(HashMap::new());
         }
@@ -187,35 +468,99 @@ impl State {
             return Err(format!("variable already declared: {}.", id));
         }
 
+        if let Some(name) = annotation
+            .as_ref()
+            .filter(|name| self.structs.contains_key(*name))
+        {
+            if e.is_some() {
+                return Err(format!("record can't have an initializer: {id}."));
+            }
+            return self.declare_record(id, name);
+        }
+
         let mut variable = Variable {
             address: self.current_address,
-            initialized: e.is_some(),
+            initialized: false,
+            ty: match annotation {
+                Some(name) => Type::from_name(&name)?,
+                None => Type::Unknown,
+            },
         };
 
-        let mut result = String::new();
+        let mut result = vec![];
 
         if let Some(expr) = e {
-            result.push_str(&format!("lda #{}\n", self.current_address));
-            Self::parse_expression(&mut self.stack, *expr, &mut result)?;
-            result.push_str("sto\n");
-            variable.initialized = true;
+            let value = Self::parse_expression(&mut self.stack, *expr)?;
+            let value = Self::store(&mut variable, value)?;
+            result.push(ir::Statement::Store(self.current_address, value));
         }
 
-        self.stack.last_mut().unwrap().insert(id, variable);
+        self.stack
+            .last_mut()
+            .unwrap()
+            .insert(id.clone(), variable.into());
+        self.define(&id);
         self.current_address += 1;
         Ok(result)
     }
 
+    // Constants are evaluated at compile time and don't take a memory cell.
+    fn declare_constant(&mut self, id: String, expr: Expr) -> Result<Vec<ir::Statement>, String> {
+        if self.stack.is_empty() {
+            self.stack.push(HashMap::new());
+        }
+
+        if self.stack.last().unwrap().contains_key(&id) {
+            return Err(format!("constant already declared: {}.", id));
+        }
+
+        let constant = Self::evaluate(&self.stack, &expr)?;
+        self.stack
+            .last_mut()
+            .unwrap()
+            .insert(id.clone(), Symbol::Constant(constant));
+        self.define(&id);
+        Ok(vec![])
+    }
+
     fn push_scope(&mut self) {
         self.stack.push(HashMap::new());
     }
 
-    fn pop_scope(&mut self) {
-        if let Some(scope) = self.stack.pop() {
-            self.current_address -= scope.len() as u32;
-        } else {
-            panic!("the stack is empty.");
+    // Returns the variables of the scope. One that was never given a value is an int.
+    fn pop_scope(&mut self) -> Vec<ir::Variable> {
+        let scope = self.stack.pop().expect("the stack is empty.");
+
+        // Variables declared later with the same address belong to scopes already gone.
+        for (name, symbol) in &scope {
+            if let Symbol::Variable(variable) = symbol {
+                let definition = self.definitions.iter_mut().rev().find(|definition| {
+                    definition.address == Some(variable.address) && &definition.name == name
+                });
+                if let Some(definition) = definition {
+                    definition.symbol = symbol.describe(name);
+                }
+            }
         }
+
+        let mut variables: Vec<ir::Variable> = scope
+            .into_iter()
+            .filter_map(|(name, symbol)| match symbol {
+                Symbol::Variable(variable) => Some(ir::Variable {
+                    name,
+                    address: variable.address,
+                    ty: match variable.ty {
+                        Type::Unknown => Type::Int,
+                        ty => ty,
+                    },
+                }),
+                _ => None,
+            })
+            .collect();
+        variables.sort_by_key(|variable| variable.address);
+
+        self.current_address -= variables.len() as u32;
+        variables
     }
 }
 
@@ -227,15 +572,19 @@ mod tests {
 
     lalrpop_mod!(pub vit_grammar);
 
+    fn pcode(result: Result<Vec<ir::Statement>, String>) -> Result<String, String> {
+        result.map(|statements| emit::statements(&statements))
+    }
+
     #[test]
     fn valid_declaration() {
         let mut state = State::new();
-        let statement = Statement::Declaration("a".to_string(), None);
+        let statement = Statement::Declaration("a".to_string(), None, None);
 
-        let result = state.parse_statement(statement);
+        let result = pcode(state.parse_statement(statement));
         assert!(result.unwrap().is_empty());
         assert_eq!(state.current_address, 1);
-        assert!(state.stack.get(0).unwrap().contains_key("a"));
+        assert!(state.stack.first().unwrap().contains_key("a"));
     }
 
     #[test]
@@ -244,38 +593,44 @@ mod tests {
 
         _ = state.parse_statement(Statement::Declaration(
             "a".to_string(),
+            None,
             Some(vit_grammar::ExprParser::new().parse("24").unwrap()),
         ));
 
         let statement = Statement::Declaration(
             "b".to_string(),
+            None,
             Some(vit_grammar::ExprParser::new().parse("a * 2 + 1").unwrap()),
         );
 
-        let result = state.parse_statement(statement);
+        let result = pcode(state.parse_statement(statement));
         assert_eq!(
             result.unwrap(),
             "lda #1\nlod #0\nldc 2\nmul\nldc 1\nadd\nsto\n"
         );
         assert_eq!(state.current_address, 2);
-        assert!(state.stack.get(0).unwrap().contains_key("b"));
+        assert!(state.stack.first().unwrap().contains_key("b"));
     }
 
     #[test]
     fn declaration_with_shadowing() {
         let mut state = State::new();
 
-        state.parse_statement(Statement::Declaration(
-            "a".to_string(),
-            Some(vit_grammar::ExprParser::new().parse("24").unwrap()),
-        ));
+        state
+            .parse_statement(Statement::Declaration(
+                "a".to_string(),
+                None,
+                Some(vit_grammar::ExprParser::new().parse("24").unwrap()),
+            ))
+            .unwrap();
 
         let statement = Statement::Declaration(
             "a".to_string(),
+            None,
             Some(vit_grammar::ExprParser::new().parse("4").unwrap()),
         );
 
-        let result = state.parse_statement(statement);
+        let result = pcode(state.parse_statement(statement));
         assert!(result.is_err());
     }
 
@@ -283,19 +638,23 @@ mod tests {
     fn declaration_inside_inner_scope() {
         let mut state = State::new();
 
-        state.parse_statement(Statement::Declaration(
-            "a".to_string(),
-            Some(vit_grammar::ExprParser::new().parse("24").unwrap()),
-        ));
+        state
+            .parse_statement(Statement::Declaration(
+                "a".to_string(),
+                None,
+                Some(vit_grammar::ExprParser::new().parse("24").unwrap()),
+            ))
+            .unwrap();
 
         state.stack.push(HashMap::new());
 
         let statement = Statement::Declaration(
             "b".to_string(),
+            None,
             Some(vit_grammar::ExprParser::new().parse("a * 2").unwrap()),
         );
 
-        let result = state.parse_statement(statement);
+        let result = pcode(state.parse_statement(statement));
         assert_eq!(result.unwrap(), "lda #1\nlod #0\nldc 2\nmul\nsto\n");
         assert_eq!(state.current_address, 2);
         assert!(state.stack.last().unwrap().contains_key("b"));
@@ -309,19 +668,21 @@ mod tests {
 
         let statement = Statement::Declaration(
             "b".to_string(),
+            None,
             Some(vit_grammar::ExprParser::new().parse("2").unwrap()),
         );
 
-        state.parse_statement(statement);
+        state.parse_statement(statement).unwrap();
 
         state.stack.pop();
 
         let statement = Statement::Declaration(
             "a".to_string(),
+            None,
             Some(vit_grammar::ExprParser::new().parse("b + 2").unwrap()),
         );
 
-        let result = state.parse_statement(statement);
+        let result = pcode(state.parse_statement(statement));
 
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("undeclared variable"));
@@ -343,15 +704,16 @@ mod tests {
     fn assign_to_variable() {
         let mut state = State::new();
 
-        state.parse_statement(Statement::Declaration("age".to_string(), None));
-
-        let result = state
-            .parse_statement(Statement::Assignment(
-                "age".to_string(),
-                vit_grammar::ExprParser::new().parse("24").unwrap(),
-            ))
+        state
+            .parse_statement(Statement::Declaration("age".to_string(), None, None))
             .unwrap();
 
+        let result = pcode(state.parse_statement(Statement::Assignment(
+            "age".to_string(),
+            vit_grammar::ExprParser::new().parse("24").unwrap(),
+        )))
+        .unwrap();
+
         assert_eq!(result, "lda #0\nldc 24\nsto\n");
     }
 
@@ -360,25 +722,32 @@ mod tests {
         let mut state = State::new();
         let parser = vit_grammar::ExprParser::new();
 
-        state.parse_statement(Statement::Declaration("average".to_string(), None));
-
-        state.parse_statement(Statement::Declaration(
-            "n1".to_string(),
-            Some(parser.parse("7.8").unwrap()),
-        ));
+        state
+            .parse_statement(Statement::Declaration("average".to_string(), None, None))
+            .unwrap();
 
-        state.parse_statement(Statement::Declaration(
-            "n2".to_string(),
-            Some(parser.parse("9.0").unwrap()),
-        ));
+        state
+            .parse_statement(Statement::Declaration(
+                "n1".to_string(),
+                None,
+                Some(parser.parse("7.8").unwrap()),
+            ))
+            .unwrap();
 
-        let result = state
-            .parse_statement(Statement::Assignment(
-                "average".to_string(),
-                parser.parse("(n1 + n2) / 2").unwrap(),
+        state
+            .parse_statement(Statement::Declaration(
+                "n2".to_string(),
+                None,
+                Some(parser.parse("9.0").unwrap()),
             ))
             .unwrap();
 
+        let result = pcode(state.parse_statement(Statement::Assignment(
+            "average".to_string(),
+            parser.parse("(n1 + n2) / 2").unwrap(),
+        )))
+        .unwrap();
+
         assert_eq!(result, "lda #0\nlod #1\nlod #2\nadd\nldc 2\ndiv\nsto\n");
         assert_eq!(state.current_address, 3);
     }
@@ -386,9 +755,11 @@ mod tests {
     #[test]
     fn read_to_variable() {
         let mut state = State::new();
-        state.parse_statement(Statement::Declaration("age".to_string(), None));
+        state
+            .parse_statement(Statement::Declaration("age".to_string(), None, None))
+            .unwrap();
 
-        let result = state.parse_statement(Statement::Read("age".to_string()));
+        let result = pcode(state.parse_statement(Statement::Read("age".to_string())));
 
         assert_eq!(result.unwrap(), "lda #0\nrd\nsto\n");
     }
@@ -397,7 +768,7 @@ mod tests {
     fn read_to_undeclared_variable() {
         let mut state = State::new();
 
-        let result = state.parse_statement(Statement::Read("age".to_string()));
+        let result = pcode(state.parse_statement(Statement::Read("age".to_string())));
 
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("undeclared variable"));
@@ -407,9 +778,11 @@ mod tests {
     fn write_unitialized_variable() {
         let mut state = State::new();
 
-        state.parse_statement(Statement::Declaration("a".to_string(), None));
+        state
+            .parse_statement(Statement::Declaration("a".to_string(), None, None))
+            .unwrap();
 
-        let result = state.write("a".to_string());
+        let result = pcode(state.write("a".to_string()));
 
         println!("{result:?}");
         assert!(result.is_err());
@@ -420,12 +793,15 @@ mod tests {
     fn write_valid_variable() {
         let mut state = State::new();
 
-        state.parse_statement(Statement::Declaration(
-            "a".to_string(),
-            Some(vit_grammar::ExprParser::new().parse("-50").unwrap()),
-        ));
+        state
+            .parse_statement(Statement::Declaration(
+                "a".to_string(),
+                None,
+                Some(vit_grammar::ExprParser::new().parse("-50").unwrap()),
+            ))
+            .unwrap();
 
-        let result = state.write("a".to_string());
+        let result = pcode(state.write("a".to_string()));
 
         println!("{result:?}");
         assert_eq!(result.unwrap(), "lod #0\nwri\n");
@@ -435,7 +811,7 @@ mod tests {
     fn write_string() {
         let mut state = State::new();
 
-        let result = state.write_string("hello, world!\\n".to_string());
+        let result = pcode(state.write_string("hello, world!\\n".to_string()));
 
         assert_eq!(result.unwrap(), "ldc \"hello, world!\\n\"\nwri\n");
     }
@@ -455,7 +831,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
         // println!("{}", result);
         // assert!(false);
 
@@ -486,7 +862,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert_eq!(result.unwrap(), "L0:\nldc \"Loop 0.\\n\"\nwri\nL1:\nldc \"Loop 1.\\n\"\nwri\nL2:\nldc \"Loop 2.\\n\"\nwri\nujp E2\nujp L2\nE2:\nujp E1\nujp L1\nE1:\nujp E0\nujp L0\nE0:\n");
     }
@@ -505,7 +881,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("undeclared"));
@@ -526,7 +902,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert_eq!(
             result.unwrap(),
@@ -550,7 +926,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert_eq!(result.unwrap(), "lda #0\nrd\nsto\nlod #0\nldc 2\nequ\nfjp F0\nldc \"a is 2.\\n\"\nwri\nujp E0\nF0:\nldc \"a is not 2.\\n\"\nwri\nE0:\n");
     }
@@ -571,7 +947,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert!(result.is_ok());
         assert_eq!(state.current_address, 2);
@@ -591,7 +967,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("undeclared"));
@@ -618,7 +994,7 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert_eq!(result.unwrap(), "L0:\nlda #0\nrd\nsto\nlod #0\nldc 0\nneq\nfjp F1\nlod #0\nwri\nldc \"\\n\"\nwri\nujp E1\nF1:\nujp E0\nE1:\nujp L0\nE0:\nldc \"END\"\nwri\n");
     }
@@ -637,11 +1013,491 @@ mod tests {
 
         let mut state = State::new();
 
-        let result = state.run(program);
+        let result = pcode(state.run(program));
 
         assert_eq!(
             result.unwrap(),
             "L0:\nldc \"Type a number: \"\nwri\nlda #0\nrd\nsto\nlod #0\nldc 0\nlte\nfjp L0\nE0:\n"
         );
     }
+
+    #[test]
+    fn string_concatenation() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "let name = 'Ana';
+            let greeting = 'Hello, ' + name;
+            write greeting;",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        let result = pcode(state.run(program));
+
+        assert_eq!(
+            result.unwrap(),
+            "lda #0\nldc \"Ana\"\nsto\nlda #1\nldc \"Hello, \"\nlod #0\ncat\nsto\nlod #1\nwri\n"
+        );
+    }
+
+    #[test]
+    fn read_string() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "let name: str;
+            read name;
+            let size = len(name);
+            if name == 'Ana' {
+                write size;
+            }",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        assert_eq!(
+            pcode(state.run(program)).unwrap(),
+            "lda #0\nrds\nsto\nlda #1\nlod #0\nlen\nsto\nlod #0\nldc \"Ana\"\nequ\nfjp E0\nlod #1\nwri\nE0:\n"
+        );
+    }
+
+    #[test]
+    fn string_type_mismatch() {
+        let parser = vit_grammar::ProgramParser::new();
+
+        let result = State::new().run(parser.parse("let a = 'one' + 1;").unwrap());
+        assert!(result.unwrap_err().contains("type mismatch"));
+
+        let result = State::new().run(parser.parse("let a = 1; a = 'one';").unwrap());
+        assert!(result.unwrap_err().contains("type mismatch"));
+
+        let result = State::new().run(parser.parse("let a: str = len(2);").unwrap());
+        assert!(result.unwrap_err().contains("type mismatch"));
+
+        let result = State::new().run(parser.parse("let a: text;").unwrap());
+        assert!(result.unwrap_err().contains("unknown type"));
+    }
+
+    #[test]
+    fn numeric_conversion() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "let a: int = 2.5;
+            let b: float;
+            read b;
+            b = a;",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        assert_eq!(
+            pcode(state.run(program)).unwrap(),
+            "lda #0\nldc 2.5\nto int\nsto\nlda #1\nrdf\nsto\nlda #1\nlod #0\nto float\nsto\n"
+        );
+    }
+
+    #[test]
+    fn constant_declaration() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "const N = 100;
+            const HALF = N / 2 + 0.5;
+            const NAME = 'vit' + '!';
+            let a = N * 2;
+            write HALF;
+            write NAME;",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        let result = pcode(state.run(program));
+
+        assert_eq!(
+            result.unwrap(),
+            "lda #0\nldc 100\nldc 2\nmul\nsto\nldc 50.5\nwri\nldc \"vit!\"\nwri\n"
+        );
+        assert_eq!(state.current_address, 1);
+    }
+
+    #[test]
+    fn assign_to_constant() {
+        let parser = vit_grammar::ProgramParser::new();
+
+        let result = State::new().run(parser.parse("const N = 3; N = 4;").unwrap());
+        assert_eq!(result.unwrap_err(), "cannot assign to constant: N.");
+
+        let result = State::new().run(parser.parse("const N = 3; read N;").unwrap());
+        assert!(result.unwrap_err().contains("constant"));
+    }
+
+    #[test]
+    fn invalid_constant_expression() {
+        let parser = vit_grammar::ProgramParser::new();
+
+        let result = State::new().run(parser.parse("let a = 2; const N = a + 1;").unwrap());
+        assert!(result.unwrap_err().contains("not a constant expression"));
+
+        let result = State::new().run(parser.parse("const N = 1 / 0;").unwrap());
+        assert!(result.unwrap_err().contains("division by zero"));
+
+        for source in [
+            "const N = 2147483647 + 1;",
+            "const N = 0 - 2147483647 - 2;",
+            "const N = 65536 * 65536;",
+            "const N = 2 ^ 31;",
+            "const M = 0 - 2147483647 - 1; const N = M / -1;",
+            "const N = abs(0 - 2147483647 - 1);",
+        ] {
+            let result = State::new().run(parser.parse(source).unwrap());
+            assert_eq!(
+                result.unwrap_err(),
+                "overflow in constant expression.",
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn constant_scope() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "let a = 1;
+            if a == 1 {
+                const LIMIT = 10;
+                let b = LIMIT;
+            }
+            let c = LIMIT;",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        let result = pcode(state.run(program));
+
+        assert!(result.unwrap_err().contains("undeclared"));
+        assert_eq!(state.current_address, 1);
+    }
+
+    #[test]
+    fn record_fields() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "struct Point { x, y }
+            let a = 1;
+            let p: Point;
+            p.x = 3;
+            read p.y;
+            let sum = p.x + p.y;
+            write p.x;",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        let result = pcode(state.run(program));
+
+        assert_eq!(
+            result.unwrap(),
+            "lda #0\nldc 1\nsto\nlda #1\nldc 3\nsto\nlda #2\nrd\nsto\nlda #3\nlod #1\nlod #2\nadd\nsto\nlod #1\nwri\n"
+        );
+        assert_eq!(state.current_address, 4);
+    }
+
+    #[test]
+    fn nested_record() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "struct Point { x: float, y: float }
+            struct Segment { start: Point, end: Point, name: str }
+            if 1 == 1 {
+                let s: Segment;
+                s.end.y = 2;
+                s.name = 'diagonal';
+            }
+            let b = 0;",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        let result = pcode(state.run(program));
+
+        assert_eq!(
+            result.unwrap(),
+            "ldc 1\nldc 1\nequ\nfjp E0\nlda #3\nldc 2\nto float\nsto\nlda #4\nldc \"diagonal\"\nsto\nE0:\nlda #0\nldc 0\nsto\n"
+        );
+        assert_eq!(state.current_address, 1);
+    }
+
+    #[test]
+    fn struct_scope() {
+        let parser = vit_grammar::ProgramParser::new();
+
+        let result = State::new().run(
+            parser
+                .parse("if 1 == 1 { struct P { x } let p: P; p.x = 1; } let q: P;")
+                .unwrap(),
+        );
+        assert_eq!(result.unwrap_err(), "unknown type: P.");
+
+        // Once out of scope, the name can be declared again.
+        let result = State::new().run(
+            parser
+                .parse("loop { struct P { x } break; } struct P { y } let p: P; p.y = 1;")
+                .unwrap(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn invalid_record_use() {
+        let parser = vit_grammar::ProgramParser::new();
+
+        let result = State::new().run(parser.parse("struct P { x } let p: P; p.z = 1;").unwrap());
+        assert_eq!(result.unwrap_err(), "unknown field: p.z.");
+
+        let result = State::new().run(parser.parse("struct P { x } let p: P; p = 1;").unwrap());
+        assert!(result.unwrap_err().contains("record used as a value"));
+
+        let result = State::new().run(parser.parse("struct P { x } let p: P; write p.x;").unwrap());
+        assert!(result.unwrap_err().contains("unitialized"));
+
+        let result = State::new().run(parser.parse("struct P { x, x }").unwrap());
+        assert!(result.unwrap_err().contains("field already declared"));
+
+        let result = State::new().run(parser.parse("struct P { x: Q }").unwrap());
+        assert!(result.unwrap_err().contains("unknown type"));
+    }
+
+    fn module(name: &str, source: &str) -> Module {
+        Module {
+            name: name.to_string(),
+            path: format!("{name}.vit").into(),
+            program: vit_grammar::ProgramParser::new().parse(source).unwrap(),
+        }
+    }
+
+    #[test]
+    fn link_modules() {
+        let modules = vec![
+            module(
+                "geometry",
+                "const SIDES = 4; struct Point { x, y } let unused = 0;",
+            ),
+            module(
+                "main",
+                "import \"geometry.vit\";
+                const SIDES = 3;
+                let p: geometry::Point;
+                p.x = geometry::SIDES + SIDES;",
+            ),
+        ];
+
+        let result = link(modules);
+
+        assert_eq!(
+            result.unwrap(),
+            "lda #0\nldc 0\nsto\nlda #0\nldc 4\nldc 3\nadd\nsto\nstp\n"
+        );
+    }
+
+    #[test]
+    fn module_namespace() {
+        let modules = vec![
+            module("util", "const MAX = 10; let hidden = 1;"),
+            module("main", "import \"util.vit\"; let a = MAX;"),
+        ];
+        assert_eq!(
+            link(modules).unwrap_err(),
+            "main.vit: undeclared variable: MAX."
+        );
+
+        let modules = vec![
+            module("util", "const MAX = 10; let hidden = 1;"),
+            module("main", "import \"util.vit\"; let a = util::hidden;"),
+        ];
+        assert!(link(modules).unwrap_err().contains("undeclared"));
+    }
+
+    #[test]
+    fn transitive_import() {
+        // main imports b, which imports c: b sees c's names, main doesn't.
+        let modules = || {
+            vec![
+                module("c", "const Y = 2; struct P { x }"),
+                module("b", "import \"c.vit\"; const Z = c::Y + 1; let p: c::P;"),
+            ]
+        };
+        let link_main = |source| {
+            let mut modules = modules();
+            modules.push(module("main", source));
+            link(modules)
+        };
+
+        assert!(link_main("import \"b.vit\"; let a = b::Z; write a;").is_ok());
+        assert_eq!(
+            link_main("import \"b.vit\"; let a = c::Y;").unwrap_err(),
+            "main.vit: undeclared variable: c::Y."
+        );
+        assert_eq!(
+            link_main("import \"b.vit\"; let p: c::P;").unwrap_err(),
+            "main.vit: unknown type: c::P."
+        );
+        assert!(link_main("import \"b.vit\"; import \"c.vit\"; let a = c::Y;").is_ok());
+    }
+
+    #[test]
+    fn unresolved_import() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse("import \"util.vit\"; let a = 2;")
+            .unwrap();
+
+        assert_eq!(build(program).unwrap_err(), "unresolved import: util.vit.");
+    }
+
+    #[test]
+    fn builtin_functions() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse(
+                "let a = 2;
+            let b = max(a, 2.5) + random(6);
+            let c = int(sqrt(b));",
+            )
+            .unwrap();
+
+        let mut state = State::new();
+
+        assert_eq!(
+            pcode(state.run(program)).unwrap(),
+            "lda #0\nldc 2\nsto\nlda #1\nlod #0\nto float\nldc 2.5\ncsp max\nldc 6\ncsp rnd\nadd\nsto\nlda #2\nlod #1\ncsp sqt\nto int\nsto\n"
+        );
+    }
+
+    #[test]
+    fn invalid_builtin_call() {
+        let parser = vit_grammar::ProgramParser::new();
+
+        let result = State::new().run(parser.parse("let a = min(1);").unwrap());
+        assert_eq!(result.unwrap_err(), "min expects 2 arguments, found 1.");
+
+        let result = State::new().run(parser.parse("let a = random(2.5);").unwrap());
+        assert_eq!(
+            result.unwrap_err(),
+            "type mismatch: random expects int, found float."
+        );
+
+        let result = State::new().run(parser.parse("let a = sqrt('four');").unwrap());
+        assert!(result.unwrap_err().contains("type mismatch"));
+
+        let result = State::new().run(parser.parse("let a = cbrt(8);").unwrap());
+        assert_eq!(result.unwrap_err(), "unknown function: cbrt.");
+    }
+
+    #[test]
+    fn constant_builtin_call() {
+        let program = vit_grammar::ProgramParser::new()
+            .parse("const A = abs(-4) + floor(2.5); const B = sqrt(A); write B;")
+            .unwrap();
+
+        assert_eq!(
+            pcode(State::new().run(program)).unwrap(),
+            "ldc 2.449489742783178\nwri\n"
+        );
+
+        let program = vit_grammar::ProgramParser::new()
+            .parse("const A = random(4);")
+            .unwrap();
+
+        assert!(State::new()
+            .run(program)
+            .unwrap_err()
+            .contains("not a constant expression"));
+    }
+
+    #[test]
+    fn debug_info() {
+        let main = "import \"lib.vit\";\nlet a = 1;\nloop {\n    a = a + 1;\n    if a > 3 { break; }\n}\nwrite a;\n";
+        let read = |path: &std::path::Path| {
+            Ok(match path.to_str() {
+                Some("main.vit") => main.to_string(),
+                _ => "let hidden = 2;\nwrite hidden;\n".to_string(),
+            })
+        };
+        let modules = crate::modules::load(std::path::Path::new("main.vit"), read).unwrap();
+        let (_, info) = link_debug(modules).unwrap();
+
+        let lines: Vec<_> = info
+            .lines
+            .iter()
+            .map(|line| (line.code.clone(), line.span.line(main)))
+            .collect();
+        // The imported module has no lines, and the code of `a = a + 1;` starts at 8.
+        assert_eq!(
+            lines,
+            vec![
+                (5..8, 2),
+                (8..19, 3),
+                (8..13, 4),
+                (13..18, 5),
+                (17..18, 5),
+                (19..21, 7)
+            ]
+        );
+
+        let scopes: Vec<_> = info
+            .scopes
+            .iter()
+            .map(|scope| {
+                let names: Vec<_> = scope.variables.iter().map(|v| v.name.as_str()).collect();
+                (scope.code.clone(), names.join(" "))
+            })
+            .collect();
+        assert_eq!(
+            scopes,
+            vec![
+                (0..21, "a".to_string()),
+                (0..5, "hidden".to_string()),
+                (8..18, String::new()),
+                (17..18, String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn analysis() {
+        let main = "import \"lib.vit\";\nconst N = 2;\nstruct P { x }\nlet p: P;\nlet a;\nloop {\n    let b = 1.5;\n    break;\n}\na = 'x';\nif a == 1 { write b; }\n";
+        let read = |path: &std::path::Path| {
+            Ok(match path.to_str() {
+                Some("main.vit") => main.to_string(),
+                _ => "let hidden = 2;\n".to_string(),
+            })
+        };
+        let modules = crate::modules::load(std::path::Path::new("main.vit"), read).unwrap();
+        let analysis = analyze(modules);
+
+        let definitions: Vec<_> = analysis
+            .definitions
+            .iter()
+            .map(|definition| (definition.span.line(main), definition.symbol.as_str()))
+            .collect();
+        // Variables have the type they end up with, up to the error.
+        assert_eq!(
+            definitions,
+            vec![
+                (2, "N = 2"),
+                (4, "p: P"),
+                (5, "a #1 str"),
+                (7, "b #2 float")
+            ]
+        );
+        let (span, message) = analysis.error.unwrap();
+        assert_eq!(span.map(|span| span.line(main)), Some(11));
+        assert_eq!(
+            message,
+            "main.vit: type mismatch: cannot apply == to str and int."
+        );
+    }
 }
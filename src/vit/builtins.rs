@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::Expr,
+    ir::{self, ExprKind},
+};
+
+use super::{constants::overflow, Constant, State, Symbol, Type};
+
+// The type a built-in function accepts for one of its arguments.
+#[derive(Clone, Copy, PartialEq)]
+enum Param {
+    Number,
+    Int,
+    Str,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Returns {
+    Int,
+    Float,
+    Promoted, // Float if any argument is a float, int otherwise.
+}
+
+struct Builtin {
+    name: &'static str,
+    params: &'static [Param],
+    returns: Returns,
+    code: &'static str, // Instructions run once all arguments are on the stack.
+}
+
+// Functions that don't fit in a single instruction call a standard procedure of the
+// machine with `csp`.
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "abs",
+        params: &[Param::Number],
+        returns: Returns::Promoted,
+        code: "csp abs\n",
+    },
+    Builtin {
+        name: "min",
+        params: &[Param::Number, Param::Number],
+        returns: Returns::Promoted,
+        code: "csp min\n",
+    },
+    Builtin {
+        name: "max",
+        params: &[Param::Number, Param::Number],
+        returns: Returns::Promoted,
+        code: "csp max\n",
+    },
+    Builtin {
+        name: "sqrt",
+        params: &[Param::Number],
+        returns: Returns::Float,
+        code: "csp sqt\n",
+    },
+    Builtin {
+        name: "floor",
+        params: &[Param::Number],
+        returns: Returns::Int,
+        code: "csp flr\n",
+    },
+    Builtin {
+        name: "int",
+        params: &[Param::Number],
+        returns: Returns::Int,
+        code: "to int\n",
+    },
+    Builtin {
+        name: "float",
+        params: &[Param::Number],
+        returns: Returns::Float,
+        code: "to float\n",
+    },
+    Builtin {
+        name: "random",
+        params: &[Param::Int],
+        returns: Returns::Int,
+        code: "csp rnd\n",
+    },
+    Builtin {
+        name: "len",
+        params: &[Param::Str],
+        returns: Returns::Int,
+        code: "len\n",
+    },
+];
+
+// The instructions of a built-in function, once all of its arguments are on the stack.
+pub(super) fn code(name: &str) -> &'static str {
+    BUILTINS
+        .iter()
+        .find(|builtin| builtin.name == name)
+        .map_or("", |builtin| builtin.code)
+}
+
+impl Builtin {
+    fn find(id: &str) -> Result<&'static Builtin, String> {
+        BUILTINS
+            .iter()
+            .find(|builtin| builtin.name == id)
+            .ok_or_else(|| format!("unknown function: {id}."))
+    }
+
+    // Checks the arguments and computes the type of the result.
+    fn check(&self, args: &[Type]) -> Result<Type, String> {
+        if args.len() != self.params.len() {
+            return Err(format!(
+                "{} expects {} argument{}, found {}.",
+                self.name,
+                self.params.len(),
+                if self.params.len() == 1 { "" } else { "s" },
+                args.len()
+            ));
+        }
+
+        for (param, ty) in self.params.iter().zip(args) {
+            let valid = match param {
+                Param::Number => ty.is_numeric(),
+                Param::Int => *ty == Type::Int,
+                Param::Str => *ty == Type::Str,
+            };
+            if !valid {
+                let expected = match param {
+                    Param::Number => "a number",
+                    Param::Int => "int",
+                    Param::Str => "str",
+                };
+                return Err(format!(
+                    "type mismatch: {} expects {expected}, found {ty}.",
+                    self.name
+                ));
+            }
+        }
+
+        Ok(match self.returns {
+            Returns::Int => Type::Int,
+            Returns::Float => Type::Float,
+            Returns::Promoted if args.contains(&Type::Float) => Type::Float,
+            Returns::Promoted => Type::Int,
+        })
+    }
+}
+
+impl State {
+    pub(super) fn call(
+        stack: &mut [HashMap<String, Symbol>],
+        id: String,
+        args: Vec<Expr>,
+    ) -> Result<ir::Expr, String> {
+        let builtin = Builtin::find(&id)?;
+
+        let args = args
+            .into_iter()
+            .map(|arg| Self::parse_expression(stack, arg))
+            .collect::<Result<Vec<ir::Expr>, String>>()?;
+        let ty = builtin.check(&args.iter().map(|arg| arg.ty).collect::<Vec<Type>>())?;
+
+        // Both arguments of min and max must have the type of the result.
+        let args = if builtin.returns == Returns::Promoted {
+            args.into_iter()
+                .map(|arg| Self::convert(arg, ty))
+                .collect::<Result<Vec<ir::Expr>, String>>()?
+        } else {
+            args
+        };
+
+        Ok(ir::Expr {
+            ty,
+            kind: ExprKind::Call(builtin.name, args),
+        })
+    }
+
+    // Evaluates a call at compile time. `random` is never constant.
+    pub(super) fn evaluate_call(id: &str, args: Vec<Constant>) -> Result<Constant, String> {
+        let builtin = Builtin::find(id)?;
+        let ty = builtin.check(&args.iter().map(Constant::ty).collect::<Vec<Type>>())?;
+
+        let value = match (id, args.as_slice()) {
+            ("len", [Constant::Str(s)]) => return Ok(Constant::Int(s.chars().count() as i32)),
+            ("abs", [Constant::Int(n)]) => {
+                return n.checked_abs().map(Constant::Int).ok_or_else(overflow)
+            }
+            ("min", [Constant::Int(a), Constant::Int(b)]) => return Ok(Constant::Int(*a.min(b))),
+            ("max", [Constant::Int(a), Constant::Int(b)]) => return Ok(Constant::Int(*a.max(b))),
+            ("abs", [n]) => n.as_float().abs(),
+            ("min", [a, b]) => a.as_float().min(b.as_float()),
+            ("max", [a, b]) => a.as_float().max(b.as_float()),
+            ("sqrt", [n]) => n.as_float().sqrt(),
+            ("floor", [n]) => n.as_float().floor(),
+            ("int", [n]) => n.as_float().trunc(),
+            ("float", [n]) => n.as_float(),
+            _ => return Err(format!("not a constant expression: {id}.")),
+        };
+
+        Ok(match (ty, &args[0]) {
+            (Type::Int, Constant::Int(n)) if id == "int" || id == "floor" => Constant::Int(*n),
+            (Type::Int, _) => Constant::Int(value as i32),
+            _ => Constant::Float(value),
+        })
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Expr, Opcode},
+    pcode,
+};
+
+use super::{Constant, State, Symbol, Type};
+
+impl State {
+    // Evaluates a constant expression at compile time. Constants don't take a memory cell:
+    // every use is replaced by their value.
+    pub(super) fn evaluate(
+        stack: &[HashMap<String, Symbol>],
+        expr: &Expr,
+    ) -> Result<Constant, String> {
+        match expr {
+            Expr::Number(sign, num) => match Self::evaluate(stack, num)? {
+                Constant::Int(n) if *sign => {
+                    n.checked_neg().map(Constant::Int).ok_or_else(overflow)
+                }
+                Constant::Float(n) if *sign => Ok(Constant::Float(-n)),
+                constant => Ok(constant),
+            },
+            Expr::Integer(n) => Ok(Constant::Int(*n)),
+            Expr::Float(n) => Ok(Constant::Float(*n)),
+            Expr::Str(s) => Ok(Constant::Str(pcode::unescape(s))),
+            Expr::Id(id) => match Self::get_symbol(stack, id)? {
+                Symbol::Constant(constant) => Ok(constant.clone()),
+                _ => Err(format!("not a constant expression: {id}.")),
+            },
+            Expr::Call(id, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| Self::evaluate(stack, arg))
+                    .collect::<Result<Vec<Constant>, String>>()?;
+                Self::evaluate_call(id, args)
+            }
+            Expr::Op(l, op, r) | Expr::Predicate(l, op, r) => {
+                let left = Self::evaluate(stack, l)?;
+                let right = Self::evaluate(stack, r)?;
+                Self::fold(op, left, right)
+            }
+        }
+    }
+
+    fn fold(op: &Opcode, left: Constant, right: Constant) -> Result<Constant, String> {
+        let ty = Self::operation_type(op, left.ty(), right.ty())?;
+
+        match (left, right) {
+            (Constant::Str(l), Constant::Str(r)) if ty == Type::Str => Ok(Constant::Str(l + &r)),
+            (Constant::Int(l), Constant::Int(r)) if ty == Type::Int => match op {
+                Opcode::Add => l.checked_add(r).map(Constant::Int).ok_or_else(overflow),
+                Opcode::Sub => l.checked_sub(r).map(Constant::Int).ok_or_else(overflow),
+                Opcode::Mul => l.checked_mul(r).map(Constant::Int).ok_or_else(overflow),
+                Opcode::Div | Opcode::Mod if r == 0 => {
+                    Err("division by zero in constant expression.".to_string())
+                }
+                Opcode::Div => l.checked_div(r).map(Constant::Int).ok_or_else(overflow),
+                // The remainder is in range even where the quotient isn't.
+                Opcode::Mod => Ok(Constant::Int(l.wrapping_rem(r))),
+                Opcode::Exp if r >= 0 => l
+                    .checked_pow(r as u32)
+                    .map(Constant::Int)
+                    .ok_or_else(overflow),
+                Opcode::Exp if l == 0 => {
+                    Err("division by zero in constant expression.".to_string())
+                }
+                Opcode::Exp => Ok(Constant::Int(match l {
+                    1 => 1,
+                    -1 if r % 2 == 0 => 1,
+                    -1 => -1,
+                    _ => 0,
+                })),
+                _ => Err(format!("not a constant expression: {op}.")),
+            },
+            (l, r) if ty == Type::Float => {
+                let (l, r) = (l.as_float(), r.as_float());
+                match op {
+                    Opcode::Add => Ok(Constant::Float(l + r)),
+                    Opcode::Sub => Ok(Constant::Float(l - r)),
+                    Opcode::Mul => Ok(Constant::Float(l * r)),
+                    Opcode::Div => Ok(Constant::Float(l / r)),
+                    Opcode::Mod => Ok(Constant::Float(l - (l / r).trunc() * r)),
+                    Opcode::Exp => Ok(Constant::Float(l.powf(r))),
+                    _ => Err(format!("not a constant expression: {op}.")),
+                }
+            }
+            _ => Err(format!("not a constant expression: {op}.")),
+        }
+    }
+}
+
+// The error for an integer result out of the range of an i32. Where the program would wrap
+// such a result as it runs, a constant can't have a value other than the one written.
+pub(super) fn overflow() -> String {
+    "overflow in constant expression.".to_string()
+}
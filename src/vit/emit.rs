@@ -0,0 +1,200 @@
+use std::slice;
+
+use crate::{
+    ast::Opcode,
+    ir::{Block, Expr, ExprKind, Statement, Type},
+};
+
+use super::{builtins, DebugInfo, Line, Scope};
+
+// Generates the p-code of a whole program, which ends with `stp`.
+pub(super) fn program(program: &Block) -> String {
+    let mut result = statements(&program.statements);
+    result.push_str("stp\n");
+    result
+}
+
+// Maps the code generated by `program` back to the statements and scopes it came from.
+// Lines and scopes that enclose others come first.
+pub(super) fn debug_info(program: &Block) -> DebugInfo {
+    let mut info = DebugInfo {
+        lines: vec![],
+        scopes: vec![],
+    };
+    locate_scope(program, 0, &mut info);
+    info.lines
+        .sort_by_key(|line| (line.code.start, usize::MAX - line.code.end));
+    info.scopes
+        .sort_by_key(|scope| (scope.code.start, usize::MAX - scope.code.end));
+    info
+}
+
+// Records the lines and scopes of the statements in `code`, whose instructions start at
+// `index`, and returns the index of the instruction after them.
+fn locate(code: &[Statement], mut index: usize, info: &mut DebugInfo) -> usize {
+    for statement in code {
+        index = match statement {
+            Statement::If(_, condition, if_block, else_block) => {
+                let start = index + expression_length(condition) + 1; // After the `fjp`.
+                let end = locate_scope(if_block, start, info);
+                match else_block {
+                    Some(else_block) => locate_scope(else_block, end + 1, info),
+                    None => end,
+                }
+            }
+            Statement::Loop(_, block) => locate_scope(block, index, info) + 1,
+            // The condition is part of the scope of the block.
+            Statement::Until(_, block, condition) => {
+                let end = locate(&block.statements, index, info);
+                let end = end + expression_length(condition) + 1;
+                info.scopes.push(Scope {
+                    code: index..end,
+                    variables: block.variables.clone(),
+                });
+                end
+            }
+            Statement::Block(block) => locate_scope(block, index, info),
+            Statement::Located(span, code) => {
+                let end = locate(code, index, info);
+                info.lines.push(Line {
+                    code: index..end,
+                    span: *span,
+                });
+                end
+            }
+            statement => index + length(&statements(slice::from_ref(statement))),
+        };
+    }
+    index
+}
+
+fn locate_scope(block: &Block, index: usize, info: &mut DebugInfo) -> usize {
+    let end = locate(&block.statements, index, info);
+    info.scopes.push(Scope {
+        code: index..end,
+        variables: block.variables.clone(),
+    });
+    end
+}
+
+fn expression_length(expr: &Expr) -> usize {
+    let mut code = String::new();
+    push_expression(expr, &mut code);
+    length(&code)
+}
+
+// The number of instructions in `code`, which doesn't count labels.
+fn length(code: &str) -> usize {
+    code.lines().filter(|line| !line.ends_with(':')).count()
+}
+
+pub(super) fn statements(statements: &[Statement]) -> String {
+    let mut result = String::new();
+
+    for statement in statements {
+        push_statement(statement, &mut result);
+    }
+    result
+}
+
+fn push_statement(statement: &Statement, result: &mut String) {
+    match statement {
+        Statement::Store(address, value) => {
+            result.push_str(&format!("lda #{address}\n"));
+            push_expression(value, result);
+            result.push_str("sto\n");
+        }
+        Statement::Read(address, ty) => {
+            let instruction = match ty {
+                Type::Float => "rdf",
+                Type::Str => "rds",
+                _ => "rd",
+            };
+            result.push_str(&format!("lda #{address}\n{instruction}\nsto\n"));
+        }
+        Statement::Write(value) => {
+            push_expression(value, result);
+            result.push_str("wri\n");
+        }
+        Statement::If(label, condition, if_block, else_block) => {
+            push_expression(condition, result);
+            result.push_str(&format!(
+                "fjp {}{}\n",
+                if else_block.is_some() { "F" } else { "E" },
+                label
+            )); // Jump to else if condition is false.
+
+            result.push_str(&statements(&if_block.statements));
+
+            if let Some(e_block) = else_block {
+                result.push_str(&format!("ujp E{label}\n")); // Jump to the end of the else block.
+                result.push_str(&format!("F{label}:\n"));
+                result.push_str(&statements(&e_block.statements));
+            }
+            result.push_str(&format!("E{label}:\n"));
+        }
+        Statement::Loop(label, block) => {
+            result.push_str(&format!("L{label}:\n"));
+            result.push_str(&statements(&block.statements));
+            result.push_str(&format!("ujp L{label}\nE{label}:\n")); // This label allows the program to break from the loop.
+        }
+        Statement::Until(label, block, condition) => {
+            result.push_str(&format!("L{label}:\n"));
+            result.push_str(&statements(&block.statements));
+            push_expression(condition, result);
+            result.push_str(&format!("fjp L{label}\nE{label}:\n"));
+        }
+        Statement::Break(label) => result.push_str(&format!("ujp E{label}\n")),
+        Statement::Block(block) => result.push_str(&statements(&block.statements)),
+        Statement::Located(_, code) => result.push_str(&statements(code)),
+    }
+}
+
+pub(super) fn push_expression(expr: &Expr, result: &mut String) {
+    match &expr.kind {
+        ExprKind::Constant(constant) => result.push_str(&format!("ldc {constant}\n")),
+        ExprKind::Load(address) => result.push_str(&format!("lod #{address}\n")),
+        ExprKind::Binary(left, op, right) => {
+            push_expression(left, result);
+            push_expression(right, result);
+
+            if *op == Opcode::Add && expr.ty == Type::Str {
+                result.push_str("cat\n");
+            } else {
+                result.push_str(operation(op));
+            }
+        }
+        ExprKind::Convert(value) => {
+            push_expression(value, result);
+            result.push_str(match expr.ty {
+                Type::Float => "to float\n",
+                _ => "to int\n",
+            });
+        }
+        ExprKind::Call(name, args) => {
+            for arg in args {
+                push_expression(arg, result);
+            }
+            result.push_str(builtins::code(name));
+        }
+    }
+}
+
+fn operation(op: &Opcode) -> &'static str {
+    match op {
+        Opcode::Add => "add\n",
+        Opcode::Sub => "sub\n",
+        Opcode::Mul => "mul\n",
+        Opcode::Div => "div\n",
+        Opcode::Exp => "pow\n",
+        Opcode::Eq => "equ\n",
+        Opcode::Neq => "neq\n",
+        Opcode::Grt => "grt\n",
+        Opcode::Geq => "gte\n",
+        Opcode::Let => "let\n",
+        Opcode::Leq => "lte\n",
+        Opcode::And => "and\n",
+        Opcode::Or => "or\n",
+        Opcode::Mod | Opcode::Not => "",
+    }
+}
@@ -1,82 +1,139 @@
 use std::collections::HashMap;
 
-use crate::ast::{Expr, Opcode};
+use crate::{
+    ast::{Expr, Opcode},
+    ir::{self, ExprKind},
+};
 
-use super::{State, Variable};
+use super::{State, Symbol, Type, Variable};
 
 impl State {
-    pub(super) fn get_address<'a>(
-        stack: &'a mut Vec<HashMap<String, Variable>>,
+    pub(super) fn get_symbol<'a>(
+        stack: &'a [HashMap<String, Symbol>],
         id: &String,
-    ) -> Result<&'a mut Variable, String> {
+    ) -> Result<&'a Symbol, String> {
+        for scope in stack.iter().rev() {
+            if let Some(symbol) = scope.get(id) {
+                return Ok(symbol);
+            }
+        }
+        Self::undeclared(id)
+    }
+
+    pub(super) fn get_symbol_mut<'a>(
+        stack: &'a mut [HashMap<String, Symbol>],
+        id: &String,
+    ) -> Result<&'a mut Symbol, String> {
         for scope in stack.iter_mut().rev() {
-            if let Some(var) = scope.get_mut(id) {
-                return Ok(var);
+            if let Some(symbol) = scope.get_mut(id) {
+                return Ok(symbol);
             }
         }
-        Err(format!("undeclared variable: {}.", id))
+        Self::undeclared(id)
+    }
+
+    fn undeclared<T>(id: &str) -> Result<T, String> {
+        if id.contains('.') {
+            Err(format!("unknown field: {id}."))
+        } else {
+            Err(format!("undeclared variable: {id}."))
+        }
+    }
+
+    pub(super) fn get_address<'a>(
+        stack: &'a mut [HashMap<String, Symbol>],
+        id: &String,
+    ) -> Result<&'a mut Variable, String> {
+        match Self::get_symbol_mut(stack, id)? {
+            Symbol::Variable(variable) => Ok(variable),
+            Symbol::Constant(_) => Err(format!("not a variable: {id}.")),
+            Symbol::Record(name) => Err(format!("record used as a value: {id} ({name}).")),
+        }
     }
 
     pub(super) fn parse_expression(
-        stack: &mut Vec<HashMap<String, Variable>>,
+        stack: &mut [HashMap<String, Symbol>],
         expr: Expr,
-        result: &mut String,
-    ) -> Result<(), String> {
+    ) -> Result<ir::Expr, String> {
         match expr {
-            Expr::Number(sign, num) => {
-                result.push_str(&format!("ldc {}{}\n", if sign { "-" } else { "" }, num));
+            Expr::Number(..) | Expr::Integer(_) | Expr::Float(_) | Expr::Str(_) => {
+                Ok(ir::Expr::constant(Self::evaluate(stack, &expr)?))
+            }
+            Expr::Op(l, Opcode::Mod, r) => {
+                let left = Self::parse_expression(stack, *l)?;
+                let right = Self::parse_expression(stack, *r)?;
+                Self::operation_type(&Opcode::Mod, left.ty, right.ty)?;
+
+                // `a % b` is `a - int(a / b) * b`, which evaluates both operands twice.
+                let quotient = Self::operation(left.clone(), Opcode::Div, right.clone())?;
+                let quotient = ir::Expr {
+                    ty: Type::Int,
+                    kind: ExprKind::Convert(Box::new(quotient)),
+                };
+                let product = Self::operation(quotient, Opcode::Mul, right)?;
+                Self::operation(left, Opcode::Sub, product)
             }
             Expr::Op(l, op, r) => {
-                if op == Opcode::Mod {
-                    let mut left_expression = String::new();
-                    let mut right_expression = String::new();
-
-                    Self::parse_expression(stack, *l, &mut left_expression)?;
-                    Self::parse_expression(stack, *r, &mut right_expression)?;
-
-                    result.push_str(&left_expression);
-                    result.push_str(&left_expression);
-                    result.push_str(&right_expression);
-                    result.push_str("div\nto int\n");
-                    result.push_str(&right_expression);
-                    result.push_str("mul\nsub\n");
-                } else {
-                    Self::parse_expression(stack, *l, result)?;
-                    Self::parse_expression(stack, *r, result)?;
-                    result.push_str(Self::parse_op(op));
-                }
+                let left = Self::parse_expression(stack, *l)?;
+                let right = Self::parse_expression(stack, *r)?;
+                Self::operation(left, op, right)
             }
-            Expr::Id(id) => {
-                let var = Self::get_address(stack, &id)?;
-                if !var.initialized {
-                    return Err(format!("uninitialized variable: {id}."));
+            Expr::Predicate(l, op, r) => Self::parse_expression(stack, Expr::Op(l, op, r)),
+            Expr::Id(id) => match Self::get_symbol(stack, &id)? {
+                Symbol::Constant(constant) => Ok(ir::Expr::constant(constant.clone())),
+                Symbol::Record(name) => Err(format!("record used as a value: {id} ({name}).")),
+                Symbol::Variable(var) => {
+                    if !var.initialized {
+                        return Err(format!("uninitialized variable: {id}."));
+                    }
+                    Ok(ir::Expr {
+                        ty: var.ty,
+                        kind: ExprKind::Load(var.address),
+                    })
                 }
-                result.push_str(&format!(
-                    "lod #{}\n",
-                    var.address,
-                ));
             },
-            _ => (),
+            Expr::Call(id, args) => Self::call(stack, id, args),
         }
+    }
 
-        Ok(())
+    fn operation(left: ir::Expr, op: Opcode, right: ir::Expr) -> Result<ir::Expr, String> {
+        Ok(ir::Expr {
+            ty: Self::operation_type(&op, left.ty, right.ty)?,
+            kind: ExprKind::Binary(Box::new(left), op, Box::new(right)),
+        })
     }
 
-    pub(super) fn parse_op(op: Opcode) -> &'static str {
+    // Type of the result of `left op right`, or an error if the operands don't support `op`.
+    pub(super) fn operation_type(op: &Opcode, left: Type, right: Type) -> Result<Type, String> {
         match op {
-            Opcode::Add => "add\n",
-            Opcode::Sub => "sub\n",
-            Opcode::Mul => "mul\n",
-            Opcode::Div => "div\n",
-            Opcode::Eq => "equ\n",
-            Opcode::Neq => "neq\n",
-            Opcode::Grt => "grt\n",
-            Opcode::Geq => "gte\n",
-            Opcode::Let => "let\n",
-            Opcode::Leq => "lte\n",
-            Opcode::And => "and\n",
-            Opcode::Or => "or\n",
-            _ => "",
+            Opcode::And | Opcode::Or if left == Type::Bool && right == Type::Bool => Ok(Type::Bool),
+            Opcode::Eq | Opcode::Neq if left == Type::Str && right == Type::Str => Ok(Type::Bool),
+            Opcode::Add if left == Type::Str && right == Type::Str => Ok(Type::Str),
+            Opcode::And | Opcode::Or => Err(format!(
+                "type mismatch: cannot apply {op} to {left} and {right}."
+            )),
+            Opcode::Eq | Opcode::Neq | Opcode::Grt | Opcode::Let | Opcode::Geq | Opcode::Leq
+                if left.is_numeric() && right.is_numeric() =>
+            {
+                Ok(Type::Bool)
+            }
+            _ if left == Type::Int && right == Type::Int => Ok(Type::Int),
+            _ if left.is_numeric() && right.is_numeric() => Ok(Type::Float),
+            _ => Err(format!(
+                "type mismatch: cannot apply {op} to {left} and {right}."
+            )),
+        }
+    }
+
+    // Converts `value` to `to`, which is only possible between numeric types.
+    pub(super) fn convert(value: ir::Expr, to: Type) -> Result<ir::Expr, String> {
+        match (value.ty, to) {
+            (from, to) if from == to => Ok(value),
+            (Type::Float, Type::Int) | (Type::Int, Type::Float) => Ok(ir::Expr {
+                ty: to,
+                kind: ExprKind::Convert(Box::new(value)),
+            }),
+            (from, to) => Err(format!("type mismatch: expected {to}, found {from}.")),
         }
     }
 }
@@ -85,6 +142,8 @@ impl State {
 mod tests {
     use super::*;
 
+    use crate::vit::emit;
+
     use lalrpop_util::lalrpop_mod;
 
     lalrpop_mod!(pub vit_grammar);
@@ -93,7 +152,7 @@ mod tests {
     fn mod_operator() {
         let expr = vit_grammar::ExprParser::new().parse("a % 2").unwrap();
         let mut result = String::new();
-        let mut stack: Vec<HashMap<String, Variable>> = vec![];
+        let mut stack: Vec<HashMap<String, Symbol>> = vec![];
 
         let mut scope = HashMap::new();
         scope.insert(
@@ -101,11 +160,14 @@ mod tests {
             Variable {
                 address: 0,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
         stack.push(scope);
 
-        let _ = State::parse_expression(&mut stack, *expr, &mut result);
+        let expr = State::parse_expression(&mut stack, *expr).unwrap();
+        emit::push_expression(&expr, &mut result);
         assert_eq!(
             result,
             "lod #0\nlod #0\nldc 2\ndiv\nto int\nldc 2\nmul\nsub\n"
@@ -116,8 +178,9 @@ mod tests {
     fn valid_expression() {
         if let Ok(expr) = vit_grammar::ExprParser::new().parse("2 + 3 * 4 - 3") {
             let mut result = String::new();
-            let mut stack: Vec<HashMap<String, Variable>> = vec![];
-            let _ = State::parse_expression(&mut stack, *expr, &mut result);
+            let mut stack: Vec<HashMap<String, Symbol>> = vec![];
+            let expr = State::parse_expression(&mut stack, *expr).unwrap();
+            emit::push_expression(&expr, &mut result);
             assert_eq!(result, "ldc 2\nldc 3\nldc 4\nmul\nadd\nldc 3\nsub\n");
         }
     }
@@ -128,12 +191,10 @@ mod tests {
             .parse("2 + 3 * a - 3")
             .unwrap();
 
-        let mut result = String::new();
-
-        let mut stack: Vec<HashMap<String, Variable>> = vec![];
+        let mut stack: Vec<HashMap<String, Symbol>> = vec![];
         stack.push(HashMap::new());
 
-        assert!(State::parse_expression(&mut stack, *expr, &mut result).is_err());
+        assert!(State::parse_expression(&mut stack, *expr).is_err());
     }
 
     #[test]
@@ -144,27 +205,32 @@ mod tests {
 
         let mut result = String::new();
 
-        let mut stack: Vec<HashMap<String, Variable>> = vec![];
-        let mut table: HashMap<String, Variable> = HashMap::new();
+        let mut stack: Vec<HashMap<String, Symbol>> = vec![];
+        let mut table: HashMap<String, Symbol> = HashMap::new();
 
         table.insert(
             "a".to_string(),
             Variable {
                 address: 0,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
         table.insert(
             "start".to_string(),
             Variable {
                 address: 1,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
 
         stack.push(table);
 
-        let _ = State::parse_expression(&mut stack, *expr, &mut result);
+        let expr = State::parse_expression(&mut stack, *expr).unwrap();
+        emit::push_expression(&expr, &mut result);
         assert_eq!(
             result,
             "ldc 7\nlod #1\nldc 2\nadd\nmul\nldc 2\nsub\nldc 2\nlod #0\ndiv\nadd\n"
@@ -179,39 +245,46 @@ mod tests {
 
         let mut result = String::new();
 
-        let mut stack: Vec<HashMap<String, Variable>> = vec![];
+        let mut stack: Vec<HashMap<String, Symbol>> = vec![];
 
-        let mut table: HashMap<String, Variable> = HashMap::new();
+        let mut table: HashMap<String, Symbol> = HashMap::new();
 
         table.insert(
             "a".to_string(),
             Variable {
                 address: 0,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
         table.insert(
             "b".to_string(),
             Variable {
                 address: 1,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
 
         stack.push(table);
 
-        let mut table: HashMap<String, Variable> = HashMap::new();
+        let mut table: HashMap<String, Symbol> = HashMap::new();
         table.insert(
             "b".to_string(),
             Variable {
                 address: 2,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
 
         stack.push(table);
 
-        let _ = State::parse_expression(&mut stack, *expr, &mut result);
+        let expr = State::parse_expression(&mut stack, *expr).unwrap();
+        emit::push_expression(&expr, &mut result);
         assert_eq!(result, "ldc 2\nlod #0\nadd\nldc 3\nlod #2\nmul\nsub\n");
     }
 
@@ -223,26 +296,31 @@ mod tests {
 
         let mut result = String::new();
 
-        let mut table: HashMap<String, Variable> = HashMap::new();
+        let mut table: HashMap<String, Symbol> = HashMap::new();
         table.insert(
             "a".to_string(),
             Variable {
                 address: 0,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
         table.insert(
             "b".to_string(),
             Variable {
                 address: 1,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
 
-        let mut stack: Vec<HashMap<String, Variable>> = vec![];
+        let mut stack: Vec<HashMap<String, Symbol>> = vec![];
         stack.push(table);
 
-        assert!(State::parse_expression(&mut stack, *expr, &mut result).is_ok());
+        let expr = State::parse_expression(&mut stack, *expr).unwrap();
+        emit::push_expression(&expr, &mut result);
         assert_eq!(
             result,
             "ldc 2\nldc 3\nlod #0\nmul\nadd\nlod #1\nldc 2\ndiv\ngrt\n"
@@ -257,33 +335,40 @@ mod tests {
         println!("{expr:?}");
         let mut result = String::new();
 
-        let mut table: HashMap<String, Variable> = HashMap::new();
+        let mut table: HashMap<String, Symbol> = HashMap::new();
         table.insert(
             "a".to_string(),
             Variable {
                 address: 0,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
         table.insert(
             "b".to_string(),
             Variable {
                 address: 1,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
         table.insert(
             "x".to_string(),
             Variable {
                 address: 2,
                 initialized: true,
-            },
+                ty: Type::Int,
+            }
+            .into(),
         );
 
-        let mut stack: Vec<HashMap<String, Variable>> = vec![];
+        let mut stack: Vec<HashMap<String, Symbol>> = vec![];
         stack.push(table);
 
-        assert!(State::parse_expression(&mut stack, *expr, &mut result).is_ok());
+        let expr = State::parse_expression(&mut stack, *expr).unwrap();
+        emit::push_expression(&expr, &mut result);
         assert_eq!(
             result,
             "\
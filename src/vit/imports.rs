@@ -0,0 +1,79 @@
+use std::{collections::HashSet, mem, path::Path};
+
+use crate::{
+    ast::{Identifier, Spanned, Statement},
+    ir::{self, Block},
+    modules,
+};
+
+use super::{Constant, State, Symbol, Type};
+
+// The constants and structs a module declares, which a module that imports it sees as
+// `name::item`.
+#[derive(Clone)]
+pub(super) struct Exports {
+    constants: Vec<(Identifier, Constant)>,
+    structs: Vec<(Identifier, Vec<(Identifier, Type)>)>,
+}
+
+impl State {
+    // Compiles an imported module in a scope of its own. Afterwards its variables, and
+    // what it imported, go out of scope; its constants and structs are kept for the
+    // modules that import it.
+    pub(super) fn run_module(
+        &mut self,
+        name: String,
+        program: Vec<Spanned<Statement>>,
+    ) -> Result<Block, String> {
+        let structs: HashSet<String> = self.structs.keys().cloned().collect();
+
+        self.push_scope();
+        let statements = self.run(program)?;
+
+        // Qualified names are those it imported.
+        let own = |id: &String| !id.contains("::");
+        let constants = self
+            .stack
+            .last()
+            .unwrap()
+            .iter()
+            .filter_map(|(id, symbol)| match symbol {
+                Symbol::Constant(constant) if own(id) => Some((id.clone(), constant.clone())),
+                _ => None,
+            })
+            .collect();
+        let variables = self.pop_scope();
+
+        let declared = mem::take(&mut self.structs);
+        let (kept, declared) = declared
+            .into_iter()
+            .partition(|(id, _)| structs.contains(id));
+        self.structs = kept;
+        let structs = declared.into_iter().filter(|(id, _)| own(id)).collect();
+
+        self.modules.insert(name, Exports { constants, structs });
+        Ok(Block {
+            variables,
+            statements,
+        })
+    }
+
+    // The imported module has already been compiled by `link`. Importing it makes what it
+    // exports visible in the current scope, and only there: a module that imports another
+    // doesn't pass on what that one imports.
+    pub(super) fn import(&mut self, path: String) -> Result<Vec<ir::Statement>, String> {
+        let name = modules::module_name(Path::new(&path))?;
+        let Some(exports) = self.modules.get(&name) else {
+            return Err(format!("unresolved import: {path}."));
+        };
+
+        let scope = self.stack.last_mut().unwrap();
+        for (id, constant) in &exports.constants {
+            scope.insert(format!("{name}::{id}"), Symbol::Constant(constant.clone()));
+        }
+        for (id, layout) in &exports.structs {
+            self.structs.insert(format!("{name}::{id}"), layout.clone());
+        }
+        Ok(vec![])
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use crate::{ast::Identifier, ir};
+
+use super::{State, Symbol, Type, Variable};
+
+impl State {
+    // Computes the layout of a struct. A field whose type is another struct is flattened
+    // into that struct's fields, so `line.start.x` is a field of its own.
+    pub(super) fn declare_struct(
+        &mut self,
+        id: String,
+        fields: Vec<(Identifier, Option<Identifier>)>,
+    ) -> Result<Vec<ir::Statement>, String> {
+        if self.structs.contains_key(&id) {
+            return Err(format!("struct already declared: {id}."));
+        }
+
+        let mut layout: Vec<(Identifier, Type)> = vec![];
+
+        for (field, annotation) in fields {
+            if layout
+                .iter()
+                .any(|(name, _)| name.split('.').next() == Some(&field))
+            {
+                return Err(format!("field already declared: {id}.{field}."));
+            }
+
+            match annotation {
+                None => layout.push((field, Type::Unknown)),
+                Some(name) => match self.structs.get(&name) {
+                    Some(inner) => layout.extend(
+                        inner
+                            .iter()
+                            .map(|(name, ty)| (format!("{field}.{name}"), *ty)),
+                    ),
+                    None => layout.push((field, Type::from_name(&name)?)),
+                },
+            }
+        }
+
+        self.structs.insert(id, layout);
+        Ok(vec![])
+    }
+
+    // Reserves one address for each field of the record, starting at the current address.
+    pub(super) fn declare_record(
+        &mut self,
+        id: String,
+        name: &str,
+    ) -> Result<Vec<ir::Statement>, String> {
+        let layout = &self.structs[name];
+        let scope: &mut HashMap<String, Symbol> = self.stack.last_mut().unwrap();
+
+        for (offset, (field, ty)) in layout.iter().enumerate() {
+            let variable = Variable {
+                address: self.current_address + offset as u32,
+                initialized: false,
+                ty: *ty,
+            };
+            scope.insert(format!("{id}.{field}"), variable.into());
+        }
+
+        scope.insert(id.clone(), Symbol::Record(name.to_string()));
+        self.current_address += layout.len() as u32;
+        self.define(&id);
+        Ok(vec![])
+    }
+}
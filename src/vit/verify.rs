@@ -0,0 +1,119 @@
+use crate::pcode::{self, Instr};
+
+// What the verifier knows about a value on the stack.
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Address, // Pushed by `lda`, for `sto`.
+    Value,
+}
+
+// Checks the stack of generated p-code and returns its height before each instruction.
+// Statements start with an empty stack, and every statement ends with `sto`, `wri`, a
+// jump or a label, so the stack must be empty there too. Errors name the index of the
+// offending instruction, counting from 0 and skipping labels, as the machine does.
+pub(super) fn verify(code: &str) -> Result<Vec<usize>, String> {
+    let lines = pcode::read(code).map_err(|e| e.to_string())?;
+    let mut heights = vec![];
+    let mut stack = vec![];
+
+    for (_, instr) in &lines {
+        let index = heights.len();
+        let error = |message: String| format!("instruction {index} ({instr}): {message}");
+
+        if let Instr::Label(name) = instr {
+            if !stack.is_empty() {
+                return Err(format!(
+                    "instruction {index}: the stack has {} values at label {name}.",
+                    stack.len()
+                ));
+            }
+            continue;
+        }
+        heights.push(stack.len());
+
+        let (pops, pushes) = pcode::effect(instr);
+        if stack.len() < pops {
+            return Err(error(format!(
+                "takes {pops} values, but the stack has {}.",
+                stack.len()
+            )));
+        }
+        let operands = stack.split_off(stack.len() - pops);
+        match (instr, operands.as_slice()) {
+            (Instr::Sto, [Slot::Address, Slot::Value]) => (),
+            (Instr::Sto, _) => {
+                return Err(error("expects an address and a value beneath it.".into()));
+            }
+            (_, operands) if operands.contains(&Slot::Address) => {
+                return Err(error("takes an address as a value.".into()));
+            }
+            _ => (),
+        }
+        let slot = match instr {
+            Instr::Lda(_) => Slot::Address,
+            _ => Slot::Value,
+        };
+        stack.extend(std::iter::repeat_n(slot, pushes));
+
+        let ends_statement = matches!(
+            instr,
+            Instr::Sto | Instr::Wri | Instr::Fjp(_) | Instr::Ujp(_) | Instr::Stp
+        );
+        if ends_statement && !stack.is_empty() {
+            return Err(error(format!(
+                "leaves {} values on the stack at the end of a statement.",
+                stack.len()
+            )));
+        }
+    }
+    Ok(heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heights() {
+        assert_eq!(
+            verify("lda #0\nldc 1\nldc 2\nadd\nsto\nA:\nlod #0\nwri\nujp A\nstp\n"),
+            Ok(vec![0, 1, 2, 3, 2, 0, 1, 0, 0])
+        );
+    }
+
+    #[test]
+    fn unbalanced() {
+        // A binary operation without its instruction.
+        assert_eq!(
+            verify("ldc 2\nldc 3\nwri\nstp\n"),
+            Err(
+                "instruction 2 (wri): leaves 1 values on the stack at the end of a statement."
+                    .into()
+            )
+        );
+        assert_eq!(
+            verify("lda #0\nldc 2\nldc 3\nsto\nstp\n"),
+            Err("instruction 3 (sto): expects an address and a value beneath it.".into())
+        );
+        assert_eq!(
+            verify("ldc 1\nfjp E\nldc 2\nE:\nstp\n"),
+            Err("instruction 3: the stack has 1 values at label E.".into())
+        );
+        assert_eq!(
+            verify("add\nstp\n"),
+            Err("instruction 0 (add): takes 2 values, but the stack has 0.".into())
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            verify("ldc 1\nlda #0\nsto\nstp\n"),
+            Err("instruction 2 (sto): expects an address and a value beneath it.".into())
+        );
+        assert_eq!(
+            verify("lda #0\nwri\nstp\n"),
+            Err("instruction 1 (wri): takes an address as a value.".into())
+        );
+    }
+}
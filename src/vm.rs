@@ -0,0 +1,1001 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{BufRead, Write},
+};
+
+use crate::pcode::{self, Constant, Instr, Procedure};
+
+pub mod bytecode;
+
+// Runs the p-code generated by `vit::build`, or its binary form from `bytecode`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Address(usize),
+}
+
+impl Value {
+    // Like the format of `wri`, but with strings quoted to tell them apart from numbers.
+    pub fn quoted(&self) -> String {
+        match self {
+            Value::Str(s) => format!("'{s}'"),
+            value => value.to_string(),
+        }
+    }
+}
+
+// The format used by `wri`. Floats are written with up to six decimal places and always
+// keep their decimal point.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Float(n) => write!(f, "{}", format_float(*n)),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Str(s) => write!(f, "{s}"),
+            Value::Address(address) => write!(f, "#{address}"),
+        }
+    }
+}
+
+pub fn format_float(n: f64) -> String {
+    if n.is_nan() {
+        return "nan".to_string();
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+
+    // From 2^53 on every float is an integer, written with all its digits.
+    let sign = if n < 0.0 { "-" } else { "" };
+    if n.abs() >= 9007199254740992.0 {
+        return format!("{sign}{:.0}.0", n.abs());
+    }
+
+    let mut units = n.abs().trunc() as u64;
+    let mut millionths = ((n.abs() - n.abs().trunc()) * 1e6 + 0.5) as u64;
+    if millionths == 1_000_000 {
+        units += 1;
+        millionths = 0;
+    }
+    let fraction = format!("{millionths:06}");
+    let fraction = fraction.trim_end_matches('0');
+
+    format!(
+        "{}{units}.{}",
+        if units != 0 || millionths != 0 {
+            sign
+        } else {
+            ""
+        },
+        if fraction.is_empty() { "0" } else { fraction }
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Instruction {
+    Ldc(Value),
+    Lda(usize),
+    Lod(usize),
+    Sto,
+    Rd,
+    Rdf,
+    Rds,
+    Wri,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Cat,
+    Len,
+    Equ,
+    Neq,
+    Grt,
+    Let,
+    Gte,
+    Lte,
+    And,
+    Or,
+    ToInt,
+    ToFloat,
+    Csp(Procedure),
+    Fjp(usize),
+    Ujp(usize),
+    Stp,
+}
+
+pub struct Machine {
+    code: Vec<Instruction>,
+    seed: u64,
+    overflow: Overflow,
+}
+
+// What integer arithmetic does with results out of the range of an i32. Wrapping is what
+// the other backends do too.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Overflow {
+    #[default]
+    Wrap,
+    Trap,     // Stops the program with an error.
+    Saturate, // Gives the closest value in range.
+}
+
+impl Overflow {
+    // Applies the mode to the result of `operation`, given as its wrapped result, whether
+    // it overflowed and its saturated result.
+    fn apply(
+        self,
+        (wrapped, overflowed): (i32, bool),
+        saturated: i32,
+        operation: &str,
+    ) -> Result<i32, RuntimeError> {
+        match self {
+            _ if !overflowed => Ok(wrapped),
+            Overflow::Wrap => Ok(wrapped),
+            Overflow::Trap => Err(RuntimeError::new(
+                ErrorKind::Overflow,
+                format!("integer overflow in {operation}."),
+            )),
+            Overflow::Saturate => Ok(saturated),
+        }
+    }
+}
+
+// An error that stops a running program, with the index of the instruction that failed.
+// `line` is the number and text of the source line of that instruction, when known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub pc: usize,
+    pub line: Option<(usize, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    DivisionByZero,
+    Overflow,
+    Input, // Input that isn't a number where one is read, or no input left.
+    Other,
+}
+
+impl ErrorKind {
+    // The exit code of `vit run` when the program stops with this error.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 2,
+            ErrorKind::DivisionByZero => 3,
+            ErrorKind::Overflow => 4,
+            ErrorKind::Input => 5,
+        }
+    }
+}
+
+impl RuntimeError {
+    fn new(kind: ErrorKind, message: impl Into<String>) -> RuntimeError {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            pc: 0,
+            line: None,
+        }
+    }
+}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        RuntimeError::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<RuntimeError> for String {
+    fn from(error: RuntimeError) -> Self {
+        error.to_string()
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.line {
+            Some((number, text)) => write!(f, "line {number}: {}\n    {text}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl Machine {
+    pub fn load(source: &str) -> Result<Machine, String> {
+        Ok(Machine {
+            code: parse(source)?,
+            seed: 0,
+            overflow: Overflow::Wrap,
+        })
+    }
+
+    // Loads bytecode, or p-code text when `code` doesn't start like bytecode.
+    pub fn load_bytes(code: &[u8]) -> Result<Machine, String> {
+        let code = if bytecode::is_bytecode(code) {
+            bytecode::decode(code)?
+        } else {
+            let source = std::str::from_utf8(code).map_err(|_| "p-code must be UTF-8.")?;
+            parse(source)?
+        };
+        Ok(Machine {
+            code,
+            seed: 0,
+            overflow: Overflow::Wrap,
+        })
+    }
+
+    // Sets the seed of `random`. Runs with the same seed and input give the same output.
+    pub fn with_seed(mut self, seed: u64) -> Machine {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Machine {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn run(
+        &self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        self.run_in(&mut Memory::new(self.seed), input, output)
+    }
+
+    pub fn start(&self) -> Process<'_> {
+        Process {
+            code: &self.code,
+            execution: Execution {
+                pc: 0,
+                stack: vec![],
+                memory: vec![],
+                random: self.seed,
+                overflow: self.overflow,
+            },
+        }
+    }
+
+    // Runs the code on `memory`, which keeps the values stored by earlier runs. The seed
+    // of the machine is ignored: `random` goes on from the state in `memory`.
+    pub fn run_in(
+        &self,
+        memory: &mut Memory,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        let mut execution = Execution {
+            pc: 0,
+            stack: vec![],
+            memory: std::mem::take(&mut memory.cells),
+            random: memory.random,
+            overflow: self.overflow,
+        };
+        let result = self.execute(&mut execution, input, output);
+
+        memory.cells = execution.memory;
+        memory.random = execution.random;
+        result?;
+        Ok(output.flush().map_err(|e| e.to_string())?)
+    }
+
+    fn execute(
+        &self,
+        execution: &mut Execution,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        while execution.pc < self.code.len() {
+            let instruction = &self.code[execution.pc];
+            execution.pc += 1;
+
+            if *instruction == Instruction::Stp {
+                break;
+            }
+            execution.step(instruction, input, output)?;
+        }
+        Ok(())
+    }
+}
+
+// A run of the machine that goes one instruction at a time, as a debugger does.
+pub struct Process<'a> {
+    code: &'a [Instruction],
+    execution: Execution,
+}
+
+impl Process<'_> {
+    // The index of the next instruction to run.
+    pub fn pc(&self) -> usize {
+        self.execution.pc
+    }
+
+    pub fn finished(&self) -> bool {
+        self.code
+            .get(self.execution.pc)
+            .is_none_or(|instruction| *instruction == Instruction::Stp)
+    }
+
+    // Runs the next instruction, which must not be past the end of the program.
+    pub fn step(
+        &mut self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        let instruction = &self.code[self.execution.pc];
+        self.execution.pc += 1;
+        self.execution.step(instruction, input, output)
+    }
+
+    // The text of the instruction at `index`, with jumps to the index of their target.
+    pub fn instruction(&self, index: usize) -> String {
+        self.code[index].to_string()
+    }
+
+    // The values on the stack, from the bottom up.
+    pub fn stack(&self) -> &[Value] {
+        &self.execution.stack
+    }
+
+    // The value in the memory cell at `address`. Cells never stored into hold 0.
+    pub fn load(&self, address: usize) -> Value {
+        self.execution
+            .memory
+            .get(address)
+            .cloned()
+            .unwrap_or(Value::Int(0))
+    }
+}
+
+// How many memory cells a program can use. P-code can come from anywhere, and an address
+// past this would make the machine run out of memory rather than fail with an error.
+const CELLS: usize = 1 << 20;
+
+// The memory cells of a machine and the state of `random`, which can outlive a run.
+pub struct Memory {
+    cells: Vec<Value>,
+    random: u64,
+}
+
+impl Memory {
+    pub fn new(seed: u64) -> Memory {
+        Memory {
+            cells: vec![],
+            random: seed,
+        }
+    }
+}
+
+struct Execution {
+    pc: usize,
+    stack: Vec<Value>,
+    memory: Vec<Value>,
+    random: u64,
+    overflow: Overflow,
+}
+
+impl Execution {
+    // Runs `instruction`, which is the one before `pc`.
+    fn step(
+        &mut self,
+        instruction: &Instruction,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        let pc = self.pc - 1;
+        self.run_instruction(instruction, input, output)
+            .map_err(|error| RuntimeError { pc, ..error })
+    }
+
+    fn run_instruction(
+        &mut self,
+        instruction: &Instruction,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> Result<(), RuntimeError> {
+        match instruction {
+            Instruction::Ldc(value) => self.stack.push(value.clone()),
+            Instruction::Lda(address) => self.stack.push(Value::Address(*address)),
+            Instruction::Lod(address) => self
+                .stack
+                .push(self.memory.get(*address).cloned().unwrap_or(Value::Int(0))),
+            Instruction::Sto => {
+                let value = self.pop()?;
+                let address = match self.pop()? {
+                    Value::Address(address) => address,
+                    value => return Err(format!("sto expects an address, found {value}.").into()),
+                };
+                if address >= CELLS {
+                    return Err(format!("address out of range: #{address}.").into());
+                }
+                if address >= self.memory.len() {
+                    self.memory.resize(address + 1, Value::Int(0));
+                }
+                self.memory[address] = value;
+            }
+            Instruction::Rd => {
+                let line = read_line(input)?;
+                let n = line.trim().parse().map_err(|_| {
+                    let message = format!("invalid input: expected an integer, found '{line}'.");
+                    RuntimeError::new(ErrorKind::Input, message)
+                })?;
+                self.stack.push(Value::Int(n));
+            }
+            Instruction::Rdf => {
+                let line = read_line(input)?;
+                let n = line.trim().parse().map_err(|_| {
+                    let message = format!("invalid input: expected a number, found '{line}'.");
+                    RuntimeError::new(ErrorKind::Input, message)
+                })?;
+                self.stack.push(Value::Float(n));
+            }
+            Instruction::Rds => {
+                let line = read_line(input)?;
+                self.stack.push(Value::Str(line));
+            }
+            Instruction::Wri => {
+                let value = self.pop()?;
+                write!(output, "{value}").map_err(|e| e.to_string())?;
+            }
+            Instruction::Len => match self.pop()? {
+                Value::Str(s) => self.stack.push(Value::Int(s.chars().count() as i32)),
+                value => return Err(format!("len expects a string, found {value}.").into()),
+            },
+            Instruction::ToInt => match self.pop()? {
+                Value::Int(n) => self.stack.push(Value::Int(n)),
+                Value::Float(n) => self.stack.push(Value::Int(n as i32)),
+                value => return Err(format!("to int expects a number, found {value}.").into()),
+            },
+            Instruction::ToFloat => {
+                let n = self.pop_float()?;
+                self.stack.push(Value::Float(n));
+            }
+            Instruction::Csp(procedure) => self.call(*procedure)?,
+            Instruction::Fjp(target) => match self.pop()? {
+                Value::Bool(false) => self.pc = *target,
+                Value::Bool(true) => (),
+                value => return Err(format!("fjp expects a boolean, found {value}.").into()),
+            },
+            Instruction::Ujp(target) => self.pc = *target,
+            Instruction::Stp => (),
+            operation => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                self.stack
+                    .push(binary(operation, left, right, self.overflow)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, procedure: Procedure) -> Result<(), RuntimeError> {
+        let result = match procedure {
+            Procedure::Abs => match self.pop()? {
+                Value::Int(n) => Value::Int(self.overflow.apply(
+                    n.overflowing_abs(),
+                    n.saturating_abs(),
+                    "abs",
+                )?),
+                value => Value::Float(float(&value)?.abs()),
+            },
+            Procedure::Min | Procedure::Max => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                let less = binary(
+                    &Instruction::Let,
+                    left.clone(),
+                    right.clone(),
+                    self.overflow,
+                )?;
+                match (procedure, less) {
+                    (Procedure::Min, Value::Bool(true)) | (Procedure::Max, Value::Bool(false)) => {
+                        left
+                    }
+                    _ => right,
+                }
+            }
+            Procedure::Sqrt => Value::Float(self.pop_float()?.sqrt()),
+            Procedure::Floor => Value::Int(self.pop_float()?.floor() as i32),
+            Procedure::Random => match self.pop()? {
+                Value::Int(bound) if bound > 0 => {
+                    // Knuth's MMIX linear congruential generator.
+                    self.random = self
+                        .random
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    Value::Int(((self.random >> 33) % bound as u64) as i32)
+                }
+                value => {
+                    return Err(format!("random expects a positive bound, found {value}.").into())
+                }
+            },
+        };
+
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        self.stack
+            .pop()
+            .ok_or_else(|| "the stack is empty.".to_string().into())
+    }
+
+    fn pop_float(&mut self) -> Result<f64, RuntimeError> {
+        float(&self.pop()?)
+    }
+}
+
+fn float(value: &Value) -> Result<f64, RuntimeError> {
+    match value {
+        Value::Int(n) => Ok(*n as f64),
+        Value::Float(n) => Ok(*n),
+        value => Err(format!("expected a number, found {value}.").into()),
+    }
+}
+
+fn binary(
+    operation: &Instruction,
+    left: Value,
+    right: Value,
+    overflow: Overflow,
+) -> Result<Value, RuntimeError> {
+    use Instruction::*;
+
+    match (operation, left, right) {
+        (Add, Value::Int(l), Value::Int(r)) => overflow
+            .apply(l.overflowing_add(r), l.saturating_add(r), "add")
+            .map(Value::Int),
+        (Sub, Value::Int(l), Value::Int(r)) => overflow
+            .apply(l.overflowing_sub(r), l.saturating_sub(r), "sub")
+            .map(Value::Int),
+        (Mul, Value::Int(l), Value::Int(r)) => overflow
+            .apply(l.overflowing_mul(r), l.saturating_mul(r), "mul")
+            .map(Value::Int),
+        (Div, Value::Int(_), Value::Int(0)) => Err(division_by_zero()),
+        (Div, Value::Int(l), Value::Int(r)) => overflow
+            .apply(l.overflowing_div(r), l.saturating_div(r), "div")
+            .map(Value::Int),
+        (Pow, Value::Int(l), Value::Int(r)) => power(l, r, overflow).map(Value::Int),
+        (Cat, Value::Str(l), Value::Str(r)) => Ok(Value::Str(l + &r)),
+        (Equ | Neq | Grt | Let | Gte | Lte, Value::Int(l), Value::Int(r)) => {
+            Ok(Value::Bool(compare(operation, l.cmp(&r))))
+        }
+        (Equ | Neq | Grt | Let | Gte | Lte, Value::Str(l), Value::Str(r)) => {
+            Ok(Value::Bool(compare(operation, l.cmp(&r))))
+        }
+        (Equ, Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l == r)),
+        (Neq, Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l != r)),
+        (And, Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l && r)),
+        (Or, Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l || r)),
+        (Add | Sub | Mul | Div | Pow | Equ | Neq | Grt | Let | Gte | Lte, left, right)
+            if is_number(&left) && is_number(&right) =>
+        {
+            let (l, r) = (float(&left)?, float(&right)?);
+            Ok(match operation {
+                Add => Value::Float(l + r),
+                Sub => Value::Float(l - r),
+                Mul => Value::Float(l * r),
+                Div => Value::Float(l / r),
+                Pow => Value::Float(l.powf(r)),
+                // Any comparison with NaN is false, except !=.
+                Equ => Value::Bool(l == r),
+                Neq => Value::Bool(l != r),
+                Grt => Value::Bool(l > r),
+                Let => Value::Bool(l < r),
+                Gte => Value::Bool(l >= r),
+                _ => Value::Bool(l <= r),
+            })
+        }
+        (operation, left, right) => {
+            Err(format!("invalid operands for {operation:?}: {left} and {right}.").into())
+        }
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Int(_) | Value::Float(_))
+}
+
+fn compare(operation: &Instruction, ordering: std::cmp::Ordering) -> bool {
+    match operation {
+        Instruction::Equ => ordering.is_eq(),
+        Instruction::Neq => ordering.is_ne(),
+        Instruction::Grt => ordering.is_gt(),
+        Instruction::Let => ordering.is_lt(),
+        Instruction::Gte => ordering.is_ge(),
+        _ => ordering.is_le(),
+    }
+}
+
+// Integer power. A negative exponent gives the truncated result of 1 / base^-exponent.
+fn power(base: i32, exponent: i32, overflow: Overflow) -> Result<i32, RuntimeError> {
+    match (base, exponent) {
+        (_, 0..) => overflow.apply(
+            base.overflowing_pow(exponent as u32),
+            base.saturating_pow(exponent as u32),
+            "pow",
+        ),
+        (0, _) => Err(division_by_zero()),
+        (1, _) => Ok(1),
+        (-1, _) => Ok(if exponent % 2 == 0 { 1 } else { -1 }),
+        _ => Ok(0),
+    }
+}
+
+fn division_by_zero() -> RuntimeError {
+    RuntimeError::new(ErrorKind::DivisionByZero, "division by zero.")
+}
+
+fn read_line(input: &mut impl BufRead) -> Result<String, RuntimeError> {
+    let mut line = String::new();
+
+    match input.read_line(&mut line) {
+        Ok(0) => Err(RuntimeError::new(
+            ErrorKind::Input,
+            "unexpected end of input.",
+        )),
+        Ok(_) => {
+            let end = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(end);
+            Ok(line)
+        }
+        Err(e) => Err(RuntimeError::new(ErrorKind::Input, e.to_string())),
+    }
+}
+
+// The p-code of an instruction. Jumps go to labels named after the index of their target.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Ldc(Value::Int(n)) => Instr::Ldc(Constant::Int(*n)).fmt(f),
+            Instruction::Ldc(Value::Float(n)) => Instr::Ldc(Constant::Float(*n)).fmt(f),
+            Instruction::Ldc(Value::Str(s)) => Instr::Ldc(Constant::Str(s.clone())).fmt(f),
+            Instruction::Ldc(value) => write!(f, "ldc {value}"),
+            Instruction::Lda(address) => Instr::Lda(*address).fmt(f),
+            Instruction::Lod(address) => Instr::Lod(*address).fmt(f),
+            Instruction::Fjp(target) => write!(f, "fjp L{target}"),
+            Instruction::Ujp(target) => write!(f, "ujp L{target}"),
+            Instruction::ToInt => Instr::ToInt.fmt(f),
+            Instruction::ToFloat => Instr::ToFloat.fmt(f),
+            Instruction::Csp(procedure) => Instr::Csp(*procedure).fmt(f),
+            instruction => write!(f, "{}", format!("{instruction:?}").to_lowercase()),
+        }
+    }
+}
+
+// Reads p-code text, with jumps resolved to the index of the instruction after their label.
+fn parse(source: &str) -> Result<Vec<Instruction>, String> {
+    let lines = pcode::read(source).map_err(|e| e.to_string())?;
+
+    let mut labels = HashMap::new();
+    let mut count = 0;
+    for (_, instr) in &lines {
+        match instr {
+            Instr::Label(label) => {
+                labels.insert(label.as_str(), count);
+            }
+            _ => count += 1,
+        }
+    }
+
+    let mut code = vec![];
+    for (number, instr) in &lines {
+        let label = |label: &String| {
+            labels
+                .get(label.as_str())
+                .copied()
+                .ok_or_else(|| format!("line {number}: undefined label: {label}."))
+        };
+
+        code.push(match instr {
+            Instr::Label(_) => continue,
+            Instr::Ldc(Constant::Int(n)) => Instruction::Ldc(Value::Int(*n)),
+            Instr::Ldc(Constant::Float(n)) => Instruction::Ldc(Value::Float(*n)),
+            Instr::Ldc(Constant::Str(s)) => Instruction::Ldc(Value::Str(s.clone())),
+            Instr::Lda(address) => Instruction::Lda(*address),
+            Instr::Lod(address) => Instruction::Lod(*address),
+            Instr::Sto => Instruction::Sto,
+            Instr::Rd => Instruction::Rd,
+            Instr::Rdf => Instruction::Rdf,
+            Instr::Rds => Instruction::Rds,
+            Instr::Wri => Instruction::Wri,
+            Instr::Add => Instruction::Add,
+            Instr::Sub => Instruction::Sub,
+            Instr::Mul => Instruction::Mul,
+            Instr::Div => Instruction::Div,
+            Instr::Pow => Instruction::Pow,
+            Instr::Cat => Instruction::Cat,
+            Instr::Len => Instruction::Len,
+            Instr::Equ => Instruction::Equ,
+            Instr::Neq => Instruction::Neq,
+            Instr::Grt => Instruction::Grt,
+            Instr::Let => Instruction::Let,
+            Instr::Gte => Instruction::Gte,
+            Instr::Lte => Instruction::Lte,
+            Instr::And => Instruction::And,
+            Instr::Or => Instruction::Or,
+            Instr::ToInt => Instruction::ToInt,
+            Instr::ToFloat => Instruction::ToFloat,
+            Instr::Csp(procedure) => Instruction::Csp(*procedure),
+            Instr::Fjp(target) => Instruction::Fjp(label(target)?),
+            Instr::Ujp(target) => Instruction::Ujp(label(target)?),
+            Instr::Stp => Instruction::Stp,
+        });
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{parser::Parser, vit};
+
+    fn run(source: &str, input: &str) -> Result<String, String> {
+        let program = Parser::new().parse(source)?;
+        let code = vit::build(program)?;
+        let mut output = vec![];
+
+        Machine::load(&code)?.run(&mut input.as_bytes(), &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(
+            run(
+                "let a = 7; let b = a / 2; write b; write ' '; let c = a % 3; write c;",
+                ""
+            ),
+            Ok("3 1".to_string())
+        );
+        assert_eq!(
+            run(
+                "let a = 7.5 % 2; write a; let b = 2 ^ 10; write ' '; write b;",
+                ""
+            ),
+            Ok("1.5 1024".to_string())
+        );
+        assert_eq!(
+            run(
+                "let a = 1 / 3.0; write a; let b: float = 3; write ' '; write b;",
+                ""
+            ),
+            Ok("0.333333 3.0".to_string())
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+            let n;
+            read n;
+            do {
+                write n;
+                write ' ';
+                if n % 2 == 0 {
+                    n = n / 2;
+                } else {
+                    n = n * 3 + 1;
+                }
+            } until n == 1;
+            write n;";
+
+        assert_eq!(run(source, "6\n"), Ok("6 3 10 5 16 8 4 2 1".to_string()));
+    }
+
+    #[test]
+    fn strings() {
+        let source = "
+            let name: str;
+            read name;
+            let greeting = 'Hello, ' + name + '!\\n';
+            write greeting;
+            let size = len(name);
+            write size;";
+
+        assert_eq!(run(source, "Ana\n"), Ok("Hello, Ana!\n3".to_string()));
+    }
+
+    #[test]
+    fn builtins() {
+        let source = "
+            let a = abs(-3) + min(2, 5) * max(2, 5);
+            write a;
+            write ' ';
+            let b = sqrt(2);
+            write b;
+            write ' ';
+            let c = floor(-2.5) + int(2.7);
+            write c;
+            write ' ';
+            let d = max(1, 2.5);
+            write d;";
+
+        assert_eq!(run(source, ""), Ok("13 1.414214 -1 2.5".to_string()));
+    }
+
+    #[test]
+    fn constants_match_run_time() {
+        // Folded at compile time, then computed as the program runs.
+        for expr in [
+            "16777217 * 1.0",
+            "0.1 + 0.2",
+            "sqrt(2) * 1000000",
+            "2.0 ^ 0.5",
+        ] {
+            let folded = run(&format!("const A = {expr}; write A;"), "");
+            let computed = run(&format!("let a = {expr}; write a;"), "");
+            assert_eq!(folded, computed, "{expr}");
+        }
+        assert_eq!(
+            run("const A = 16777217 * 1.0; write A;", ""),
+            Ok("16777217.0".to_string())
+        );
+    }
+
+    #[test]
+    fn deterministic_random() {
+        let source = "
+            let i = 0;
+            loop {
+                let n = random(100);
+                write n;
+                write ' ';
+                i = i + 1;
+                if i == 5 {
+                    break;
+                }
+            }";
+
+        let first = run(source, "").unwrap();
+        assert_eq!(first, run(source, "").unwrap());
+
+        let code = vit::build(Parser::new().parse(source).unwrap()).unwrap();
+        let mut output = vec![];
+        Machine::load(&code)
+            .unwrap()
+            .with_seed(42)
+            .run(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_ne!(first, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert!(run("let a = 0; let b = 1 / a;", "").is_err());
+        assert!(run("let a; read a;", "abc\n").is_err());
+        assert!(run("let a; read a;", "").is_err());
+        assert!(run("let a = random(0);", "").is_err());
+    }
+
+    fn error(source: &str, input: &str, overflow: Overflow) -> RuntimeError {
+        let code = vit::build(Parser::new().parse(source).unwrap()).unwrap();
+        let machine = Machine::load(&code).unwrap().with_overflow(overflow);
+        machine.run(&mut input.as_bytes(), &mut vec![]).unwrap_err()
+    }
+
+    #[test]
+    fn error_kinds() {
+        let division = error("let a = 0; let b = 1; b = b / a;", "", Overflow::Wrap);
+        assert_eq!(
+            (division.kind, division.message.as_str(), division.pc),
+            (ErrorKind::DivisionByZero, "division by zero.", 9)
+        );
+        let modulo = error("let a = 0; let b = 5 % a;", "", Overflow::Wrap);
+        assert_eq!(modulo.kind, ErrorKind::DivisionByZero);
+
+        assert_eq!(
+            error("let a; read a;", "abc\n", Overflow::Wrap).kind,
+            ErrorKind::Input
+        );
+        assert_eq!(
+            error("let a; read a;", "", Overflow::Wrap).kind,
+            ErrorKind::Input
+        );
+        assert_eq!(
+            error("let a = random(0);", "", Overflow::Wrap).kind,
+            ErrorKind::Other
+        );
+        assert_ne!(
+            ErrorKind::Input.exit_code(),
+            ErrorKind::Overflow.exit_code()
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let run_with = |source: &str, overflow| {
+            let code = vit::build(Parser::new().parse(source).unwrap()).unwrap();
+            let mut output = vec![];
+            let machine = Machine::load(&code).unwrap().with_overflow(overflow);
+            machine
+                .run(&mut "".as_bytes(), &mut output)
+                .map(|_| String::from_utf8(output).unwrap())
+                .map_err(|error| (error.kind, error.message))
+        };
+        let max = "let a = 2147483647; let b = a + 1; write b;";
+        let min = "let a = -2147483647 - 1; let b = a * 2; write b; b = a / -1; write b;";
+        let power = "let a = 3; let b = a ^ 40; write b;";
+        let abs = "let a = -2147483647 - 1; let b = abs(a); write b;";
+
+        assert_eq!(run_with(max, Overflow::Wrap), Ok("-2147483648".into()));
+        assert_eq!(run_with(max, Overflow::Saturate), Ok("2147483647".into()));
+        assert_eq!(
+            run_with(max, Overflow::Trap),
+            Err((ErrorKind::Overflow, "integer overflow in add.".into()))
+        );
+        assert_eq!(run_with(min, Overflow::Wrap), Ok("0-2147483648".into()));
+        assert_eq!(
+            run_with(min, Overflow::Saturate),
+            Ok("-21474836482147483647".into())
+        );
+        assert_eq!(
+            run_with(min, Overflow::Trap),
+            Err((ErrorKind::Overflow, "integer overflow in mul.".into()))
+        );
+        assert_eq!(run_with(power, Overflow::Saturate), Ok("2147483647".into()));
+        assert!(run_with(power, Overflow::Trap).is_err());
+        assert_eq!(run_with(abs, Overflow::Saturate), Ok("2147483647".into()));
+        assert!(run_with(abs, Overflow::Trap).is_err());
+    }
+
+    #[test]
+    fn address_out_of_range() {
+        for address in [usize::MAX, 100_000_000_000, CELLS] {
+            let code = format!("lda #{address}\nldc 1\nsto\nstp\n");
+            let error = Machine::load(&code)
+                .unwrap()
+                .run(&mut "".as_bytes(), &mut vec![])
+                .unwrap_err();
+            assert_eq!(error.message, format!("address out of range: #{address}."));
+        }
+        assert!(run("let a = 1; write a;", "").is_ok());
+    }
+
+    #[test]
+    fn invalid_code() {
+        assert!(Machine::load("ujp L0\n").is_err());
+        assert!(Machine::load("foo\n").is_err());
+        assert!(Machine::load("lda 3\n").is_err());
+    }
+
+    #[test]
+    fn float_format() {
+        assert_eq!(format_float(2.0), "2.0");
+        assert_eq!(format_float(-0.1), "-0.1");
+        assert_eq!(format_float(1e-9), "0.0");
+        assert_eq!(format_float(2.0000004), "2.0");
+        assert_eq!(format_float(1.9999996), "2.0");
+        assert_eq!(format_float(-0.0000004), "0.0");
+
+        // Too big to count in millionths with a u64.
+        assert_eq!(format_float(12345678901234.5), "12345678901234.5");
+        assert_eq!(format_float(-9007199254740991.0), "-9007199254740991.0");
+        assert_eq!(format_float(9007199254740994.0), "9007199254740994.0");
+        assert_eq!(format_float(-1e20), "-100000000000000000000.0");
+        assert_eq!(
+            format_float(f64::MAX),
+            "1797693134862315708145274237317043567980705675258449965989174768031572607800285\
+             38760589558632766878171540458953514382464234321326889464182768467546703537516986\
+             04991057655128207624549009038932894407586850845513394230458323690322294816580855\
+             9332123348274797826204144723168738177180919299881250404026184124858368.0"
+        );
+        assert_eq!(
+            run("let a = 10.0 ^ 20; write a;", ""),
+            Ok("100000000000000000000.0".to_string())
+        );
+    }
+}
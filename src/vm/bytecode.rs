@@ -0,0 +1,467 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::{parse, Instruction, Procedure, Value};
+use crate::pcode::{self, Instr};
+
+// The binary form of p-code. All numbers are little endian. It starts with a header:
+//
+//   magic "VITB", version: u16
+//
+// followed by the constant pool, which holds the floats and strings loaded by `ldc`:
+//
+//   count: u32, then for each constant a tag: u8 and its value:
+//     0: a float, f64
+//     1: a string, length: u32 and its UTF-8 bytes
+//
+// and the code, as a count: u32 and the instructions. Each one is an opcode: u8 and its
+// operand, if any: an i32 for an integer `ldc`, the u32 index of a constant for other
+// `ldc`s, a u32 address for `lda` and `lod`, the u32 index of the target instruction for
+// jumps, and a u8 for the procedure of `csp`.
+//
+// Since version 2 the code is followed by the labels of the text, which the vm doesn't
+// need but `disassemble` writes back:
+//
+//   count: u32, then for each label the u32 index of its instruction and its name, as a
+//   length: u32 and its UTF-8 bytes
+//
+// Version 1 is still read, as code without labels.
+
+const MAGIC: &[u8] = b"VITB";
+const VERSION: u16 = 2;
+
+const LDC_INT: u8 = 0;
+const LDC: u8 = 1;
+const LDA: u8 = 2;
+const LOD: u8 = 3;
+const FJP: u8 = 4;
+const UJP: u8 = 5;
+const CSP: u8 = 6;
+
+// Instructions without operands take the opcodes after CSP, in this order.
+const SIMPLE: &[Instruction] = &[
+    Instruction::Sto,
+    Instruction::Rd,
+    Instruction::Rdf,
+    Instruction::Rds,
+    Instruction::Wri,
+    Instruction::Add,
+    Instruction::Sub,
+    Instruction::Mul,
+    Instruction::Div,
+    Instruction::Pow,
+    Instruction::Cat,
+    Instruction::Len,
+    Instruction::Equ,
+    Instruction::Neq,
+    Instruction::Grt,
+    Instruction::Let,
+    Instruction::Gte,
+    Instruction::Lte,
+    Instruction::And,
+    Instruction::Or,
+    Instruction::ToInt,
+    Instruction::ToFloat,
+    Instruction::Stp,
+];
+
+const PROCEDURES: &[Procedure] = &[
+    Procedure::Abs,
+    Procedure::Min,
+    Procedure::Max,
+    Procedure::Sqrt,
+    Procedure::Floor,
+    Procedure::Random,
+];
+
+// Translates p-code text into bytecode.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut labels = vec![];
+    let mut count = 0;
+    for (_, instr) in pcode::read(source).map_err(|e| e.to_string())? {
+        match instr {
+            Instr::Label(name) => labels.push((count, name)),
+            _ => count += 1,
+        }
+    }
+    Ok(encode(&parse(source)?, &labels))
+}
+
+// Translates bytecode into p-code text, with the labels it was assembled with. Text as
+// `build` writes it, one instruction or label per line, comes back as it was; blank lines
+// and indentation don't. Jumps to where bytecode has no label, as in version 1, go to
+// labels named after the index of the instruction they point to. Either way assembling
+// the text gives back the same code.
+pub fn disassemble(code: &[u8]) -> Result<String, String> {
+    let (instructions, mut labels) = read(code)?;
+    let targets: BTreeSet<usize> = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Fjp(target) | Instruction::Ujp(target) => Some(*target),
+            _ => None,
+        })
+        .collect();
+    for target in targets {
+        if labels.iter().all(|(index, _)| *index != target) {
+            labels.push((target, format!("L{target}")));
+        }
+    }
+    // Stable, so that labels of the same instruction keep their order.
+    labels.sort_by_key(|(index, _)| *index);
+
+    // Jumps go to the first label of their target.
+    let mut names = HashMap::new();
+    for (index, name) in &labels {
+        names.entry(*index).or_insert(name.clone());
+    }
+
+    let mut result = String::new();
+    let mut labels = labels.into_iter().peekable();
+    for index in 0..=instructions.len() {
+        while let Some((_, name)) = labels.next_if(|(target, _)| *target == index) {
+            result.push_str(&format!("{name}:\n"));
+        }
+        let line = match instructions.get(index) {
+            Some(Instruction::Fjp(target)) => format!("fjp {}", names[target]),
+            Some(Instruction::Ujp(target)) => format!("ujp {}", names[target]),
+            Some(instruction) => instruction.to_string(),
+            None => break,
+        };
+        result.push_str(&line);
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+pub fn is_bytecode(code: &[u8]) -> bool {
+    code.starts_with(MAGIC)
+}
+
+fn encode(instructions: &[Instruction], labels: &Labels) -> Vec<u8> {
+    let mut constants = vec![];
+    let mut indices = HashMap::new(); // Index of each constant, by its encoding.
+    let mut code = vec![];
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::Ldc(Value::Int(n)) => {
+                code.push(LDC_INT);
+                code.extend(n.to_le_bytes());
+            }
+            Instruction::Ldc(value) => {
+                let mut constant = vec![];
+                match value {
+                    Value::Float(n) => {
+                        constant.push(0);
+                        constant.extend(n.to_le_bytes());
+                    }
+                    Value::Str(s) => {
+                        constant.push(1);
+                        constant.extend((s.len() as u32).to_le_bytes());
+                        constant.extend(s.as_bytes());
+                    }
+                    // The text of p-code has no other constants.
+                    _ => unreachable!("ldc of {value}"),
+                }
+                let index = *indices.entry(constant.clone()).or_insert_with(|| {
+                    constants.push(constant);
+                    constants.len() as u32 - 1
+                });
+                code.push(LDC);
+                code.extend(index.to_le_bytes());
+            }
+            Instruction::Lda(address) | Instruction::Lod(address) => {
+                code.push(if matches!(instruction, Instruction::Lda(_)) {
+                    LDA
+                } else {
+                    LOD
+                });
+                code.extend((*address as u32).to_le_bytes());
+            }
+            Instruction::Fjp(target) | Instruction::Ujp(target) => {
+                code.push(if matches!(instruction, Instruction::Fjp(_)) {
+                    FJP
+                } else {
+                    UJP
+                });
+                code.extend((*target as u32).to_le_bytes());
+            }
+            Instruction::Csp(procedure) => {
+                code.push(CSP);
+                code.push(PROCEDURES.iter().position(|p| p == procedure).unwrap() as u8);
+            }
+            instruction => {
+                let position = SIMPLE.iter().position(|s| s == instruction).unwrap();
+                code.push(CSP + 1 + position as u8);
+            }
+        }
+    }
+
+    let mut result = MAGIC.to_vec();
+    result.extend(VERSION.to_le_bytes());
+    result.extend((constants.len() as u32).to_le_bytes());
+    result.extend(constants.concat());
+    result.extend((instructions.len() as u32).to_le_bytes());
+    result.extend(code);
+    result.extend((labels.len() as u32).to_le_bytes());
+    for (index, name) in labels {
+        result.extend((*index as u32).to_le_bytes());
+        result.extend((name.len() as u32).to_le_bytes());
+        result.extend(name.as_bytes());
+    }
+    result
+}
+
+// The labels of the text, with the index of the instruction of each, in the order written.
+type Labels = Vec<(usize, String)>;
+
+pub(super) fn decode(code: &[u8]) -> Result<Vec<Instruction>, String> {
+    Ok(read(code)?.0)
+}
+
+// Decodes the instructions and the labels, with the index of the instruction of each.
+fn read(code: &[u8]) -> Result<(Vec<Instruction>, Labels), String> {
+    let mut reader = Reader { code, position: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("not bytecode.".to_string());
+    }
+    let version = u16::from_le_bytes(reader.array()?);
+    if version == 0 || version > VERSION {
+        return Err(format!("unsupported bytecode version: {version}."));
+    }
+
+    let mut constants = vec![];
+    for _ in 0..reader.u32()? {
+        constants.push(match reader.byte()? {
+            0 => Value::Float(f64::from_le_bytes(reader.array()?)),
+            1 => {
+                let length = reader.u32()? as usize;
+                let bytes = reader.take(length)?.to_vec();
+                Value::Str(String::from_utf8(bytes).map_err(|_| "invalid string constant.")?)
+            }
+            tag => return Err(format!("unknown constant tag: {tag}.")),
+        });
+    }
+
+    let count = reader.u32()? as usize;
+    let mut instructions = Vec::with_capacity(count.min(code.len()));
+    for _ in 0..count {
+        instructions.push(match reader.byte()? {
+            LDC_INT => Instruction::Ldc(Value::Int(i32::from_le_bytes(reader.array()?))),
+            LDC => {
+                let index = reader.u32()? as usize;
+                let constant = constants
+                    .get(index)
+                    .ok_or_else(|| format!("invalid constant index: {index}."))?;
+                Instruction::Ldc(constant.clone())
+            }
+            LDA => Instruction::Lda(reader.u32()? as usize),
+            LOD => Instruction::Lod(reader.u32()? as usize),
+            opcode @ (FJP | UJP) => {
+                let target = reader.u32()? as usize;
+                if target > count {
+                    return Err(format!("invalid jump target: {target}."));
+                }
+                if opcode == FJP {
+                    Instruction::Fjp(target)
+                } else {
+                    Instruction::Ujp(target)
+                }
+            }
+            CSP => {
+                let procedure = reader.byte()?;
+                let procedure = PROCEDURES
+                    .get(procedure as usize)
+                    .ok_or_else(|| format!("unknown procedure: {procedure}."))?;
+                Instruction::Csp(*procedure)
+            }
+            opcode => SIMPLE
+                .get((opcode - CSP - 1) as usize)
+                .cloned()
+                .ok_or_else(|| format!("unknown opcode: {opcode}."))?,
+        });
+    }
+
+    let mut labels = vec![];
+    if version >= 2 {
+        for _ in 0..reader.u32()? {
+            let index = reader.u32()? as usize;
+            if index > count {
+                return Err(format!("invalid label index: {index}."));
+            }
+            let length = reader.u32()? as usize;
+            let bytes = reader.take(length)?.to_vec();
+            let name = String::from_utf8(bytes)
+                .ok()
+                .filter(|name| !name.is_empty() && !name.contains('\n') && name.trim() == name)
+                .ok_or("invalid label name.")?;
+            labels.push((index, name));
+        }
+    }
+
+    if reader.position != code.len() {
+        return Err("unexpected data after the code.".to_string());
+    }
+    Ok((instructions, labels))
+}
+
+struct Reader<'a> {
+    code: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position.saturating_add(length);
+        let bytes = self
+            .code
+            .get(self.position..end)
+            .ok_or("truncated bytecode.")?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::{modules, vit, vm::Machine};
+
+    fn example(name: &str) -> String {
+        let path = format!("examples/{name}.vit");
+        let modules = modules::load(Path::new(&path), |path| fs::read_to_string(path));
+        vit::link(modules.unwrap()).unwrap()
+    }
+
+    fn run(machine: Machine, input: &str) -> String {
+        let mut output = vec![];
+        machine.run(&mut input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        for (name, input) in [
+            ("fib", "20\n"),
+            ("greeting", "Ana\n"),
+            ("points", "1\n2\n4\n6\n"),
+        ] {
+            let text = example(name);
+            let code = assemble(&text).unwrap();
+            assert!(code.len() < text.len(), "{name}");
+
+            let disassembled = disassemble(&code).unwrap();
+            assert_eq!(disassembled, text, "{name}");
+            assert_eq!(assemble(&disassembled).unwrap(), code, "{name}");
+
+            assert_eq!(
+                run(Machine::load_bytes(&code).unwrap(), input),
+                run(Machine::load(&text).unwrap(), input),
+                "{name}"
+            );
+            assert_eq!(
+                run(Machine::load_bytes(text.as_bytes()).unwrap(), input),
+                run(Machine::load(&text).unwrap(), input),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn disassembled_text() {
+        let code = assemble(
+            "ldc \"a\\\\b\\n\"\nwri\nA:\nldc 2.5\nldc -1\nB:\ncsp abs\nfjp A\nujp B\nto int\nstp\n",
+        )
+        .unwrap();
+        assert_eq!(
+            disassemble(&code),
+            Ok(
+                "ldc \"a\\\\b\\n\"\nwri\nA:\nldc 2.5\nldc -1\nB:\ncsp abs\nfjp A\nujp B\nto int\nstp\n"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let text = "start:\nldc 1\nfjp end\nloop:\nagain:\nujp loop\nunused:\nstp\nend:\n";
+        let code = assemble(text).unwrap();
+        assert_eq!(disassemble(&code), Ok(text.to_string()));
+        assert_eq!(
+            disassemble(&assemble("  ldc 1\n\nstp\n").unwrap()),
+            Ok("ldc 1\nstp\n".into())
+        );
+
+        // Version 1 has no labels, so jumps go to labels named after their target.
+        let mut old = assemble("ldc 1\nfjp E\nujp E\nE:\nstp\n").unwrap();
+        old.truncate(old.len() - 13); // The labels: a count, an index and a name of one byte.
+        old[4] = 1;
+        assert_eq!(
+            disassemble(&old),
+            Ok("ldc 1\nfjp L3\nujp L3\nL3:\nstp\n".to_string())
+        );
+    }
+
+    #[test]
+    fn shared_constants() {
+        let once = assemble("ldc \"hello\"\nldc 0.5\n").unwrap();
+        let twice = assemble("ldc \"hello\"\nldc 0.5\nldc \"hello\"\nldc 0.5\n").unwrap();
+        assert_eq!(twice.len() - once.len(), 10); // Two more instructions of five bytes.
+    }
+
+    #[test]
+    fn invalid_bytecode() {
+        let code = assemble("ldc \"a\"\nujp E\nE:\nstp\n").unwrap();
+
+        assert_eq!(
+            decode(&code[..code.len() - 1]),
+            Err("truncated bytecode.".into())
+        );
+        assert_eq!(
+            decode(&[code.as_slice(), &[0]].concat()),
+            Err("unexpected data after the code.".into())
+        );
+
+        let mut version = code.clone();
+        version[4] = 9;
+        assert_eq!(
+            decode(&version),
+            Err("unsupported bytecode version: 9.".into())
+        );
+
+        let labels = 13; // A count, an index and a name of one byte.
+        let mut jump = code.clone();
+        let target = jump.len() - labels - 5;
+        jump[target] = 7;
+        assert_eq!(decode(&jump), Err("invalid jump target: 7.".into()));
+
+        let mut opcode = code.clone();
+        let last = opcode.len() - labels - 1;
+        opcode[last] = 200;
+        assert_eq!(decode(&opcode), Err("unknown opcode: 200.".into()));
+
+        let mut label = code.clone();
+        let index = label.len() - 9;
+        label[index] = 7;
+        assert_eq!(decode(&label), Err("invalid label index: 7.".into()));
+
+        let mut name = code.clone();
+        let last = name.len() - 1;
+        name[last] = b'\n';
+        assert_eq!(decode(&name), Err("invalid label name.".into()));
+    }
+}
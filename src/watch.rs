@@ -0,0 +1,158 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+// How often the files are checked for changes.
+const POLL: Duration = Duration::from_millis(100);
+
+// How long the files must go without changing after one does before rebuilding, so that an
+// editor saving in several writes causes a single rebuild.
+const QUIET: Duration = Duration::from_millis(200);
+
+// What watching needs from the file system, so tests can give it a fake one.
+pub trait Watcher {
+    // When the file last changed, or None when it can't be read.
+    fn modified(&self, path: &Path) -> Option<SystemTime>;
+
+    // Waits for `duration`, and returns whether to keep watching.
+    fn wait(&mut self, duration: Duration) -> bool;
+}
+
+// Watches the real file system by checking the modification time of each file.
+pub struct Polling;
+
+impl Watcher for Polling {
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn wait(&mut self, duration: Duration) -> bool {
+        thread::sleep(duration);
+        true
+    }
+}
+
+// Clears the terminal and calls `rebuild`, which writes what it has to show and returns the
+// files it read, and does it again each time one of them changes, until the watcher stops.
+pub fn watch(
+    watcher: &mut impl Watcher,
+    output: &mut impl Write,
+    mut rebuild: impl FnMut(&mut dyn Write) -> io::Result<Vec<PathBuf>>,
+) -> io::Result<()> {
+    loop {
+        write!(output, "\x1b[2J\x1b[H")?;
+        let mut paths = rebuild(output)?;
+        paths.sort();
+        paths.dedup();
+        let names: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        writeln!(output, "\nWatching {} for changes.", names.join(", "))?;
+        output.flush()?;
+
+        let times = |watcher: &dyn Watcher| -> Vec<Option<SystemTime>> {
+            paths.iter().map(|path| watcher.modified(path)).collect()
+        };
+        let mut last = times(watcher);
+        loop {
+            if !watcher.wait(POLL) {
+                return Ok(());
+            }
+            if times(watcher) != last {
+                break;
+            }
+        }
+        loop {
+            last = times(watcher);
+            if !watcher.wait(QUIET) {
+                return Ok(());
+            }
+            if times(watcher) == last {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc, time::UNIX_EPOCH};
+
+    use super::*;
+
+    // A file system where each file is written at the given times, in milliseconds, and
+    // whose clock stops at `end`.
+    struct Fake {
+        now: Rc<Cell<Duration>>,
+        end: Duration,
+        writes: Vec<(u64, &'static str)>,
+    }
+
+    impl Watcher for Fake {
+        fn modified(&self, path: &Path) -> Option<SystemTime> {
+            self.writes
+                .iter()
+                .map(|&(time, file)| (Duration::from_millis(time), file))
+                .filter(|&(time, file)| time <= self.now.get() && Path::new(file) == path)
+                .map(|(time, _)| UNIX_EPOCH + time)
+                .next_back()
+        }
+
+        fn wait(&mut self, duration: Duration) -> bool {
+            self.now.set(self.now.get() + duration);
+            self.now.get() < self.end
+        }
+    }
+
+    // Watches the fake file system, with main.vit importing lib.vit once it has changed
+    // twice, and returns the times of each rebuild.
+    fn rebuilds(writes: Vec<(u64, &'static str)>) -> Vec<u128> {
+        let now = Rc::new(Cell::new(Duration::ZERO));
+        let mut watcher = Fake {
+            now: now.clone(),
+            end: Duration::from_secs(10),
+            writes,
+        };
+        let (mut times, mut output) = (vec![], vec![]);
+
+        watch(&mut watcher, &mut output, |_| {
+            times.push(now.get().as_millis());
+            Ok(match times.len() {
+                1 | 2 => vec![PathBuf::from("main.vit")],
+                _ => vec![PathBuf::from("main.vit"), PathBuf::from("lib.vit")],
+            })
+        })
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("\x1b[2J").count(), times.len());
+        times
+    }
+
+    #[test]
+    fn rebuilds_on_change() {
+        let times = rebuilds(vec![(0, "main.vit"), (1000, "main.vit"), (3000, "lib.vit")]);
+        assert_eq!(times, [0, 1200]);
+
+        let times = rebuilds(vec![
+            (0, "main.vit"),
+            (1000, "main.vit"),
+            (2000, "main.vit"),
+            (5000, "lib.vit"),
+        ]);
+        assert_eq!(times, [0, 1200, 2200, 5200]);
+    }
+
+    #[test]
+    fn debounces() {
+        // A burst of writes, each sooner after the last than the quiet time.
+        let times = rebuilds(vec![
+            (1000, "main.vit"),
+            (1150, "main.vit"),
+            (1300, "main.vit"),
+        ]);
+        assert_eq!(times, [0, 1600]);
+    }
+}
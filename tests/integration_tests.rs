@@ -21,3 +21,492 @@ fn even_or_odd() {
     let result = vit::vit::build(program).unwrap();
     assert_eq!(result, "ldc \"Input a number: \"\nwri\nlda #0\nrd\nsto\nldc \"The number is \"\nwri\nlod #0\nlod #0\nldc 2\ndiv\nto int\nldc 2\nmul\nsub\nldc 0\nequ\nfjp F0\nldc \"even.\\n\"\nwri\nujp E0\nF0:\nldc \"odd.\\n\"\nwri\nE0:\nstp\n")
 }
+
+// Build writes each stage given with --emit next to the source file, or where it's told to,
+// and nothing else.
+#[test]
+fn emit_stages() {
+    let dir = std::env::temp_dir().join(format!("vit-emit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.vit");
+    std::fs::write(&file, "let a = 7 % 2;\nif a == 1 { write a; }\n").unwrap();
+    let ast = dir.join("tree.txt");
+
+    let args = [
+        "vit".to_string(),
+        "build".to_string(),
+        file.display().to_string(),
+        "--emit=tokens,pcode".to_string(),
+        "--emit".to_string(),
+        format!("opt-pcode,ast={}", ast.display()),
+    ];
+    vit::run(vit::Config::build(args.into_iter()).unwrap()).unwrap();
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert!(read("main.tokens")
+        .starts_with("1:1      keyword         let\n1:5      identifier      a\n"));
+    assert!(read("main.pcode").starts_with("lda #0\nldc 7\nldc 7\nldc 2\ndiv\n"));
+    assert_eq!(
+        read("main.opt.pcode"),
+        "lda #0\nldc 1\nsto\nlod #0\nldc 1\nequ\nfjp E0\nlod #0\nwri\nE0:\nstp\n"
+    );
+    assert!(read("tree.txt").starts_with("[\n    Declaration(\n        \"a\",\n"));
+    assert!(!dir.join("main").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Runs the vit binary with `args`, feeding it `stdin`, and returns its exit code, stdout
+// and stderr.
+fn vit(args: &[&str], stdin: &str) -> (i32, String, String) {
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vit"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn command_line() {
+    assert_eq!(
+        vit(&["run", "-"], "let a = 6 * 7;\nwrite a;\n"),
+        (0, "42".to_string(), String::new())
+    );
+    assert_eq!(
+        vit(&["build", "-o", "-", "--", "-"], "write 'a';"),
+        (0, "ldc \"a\"\nwri\nstp\n".to_string(), String::new())
+    );
+    assert_eq!(
+        vit(&["fmt", "-"], "let  a=1;"),
+        (0, "let a = 1;\n".to_string(), String::new())
+    );
+    let (code, stdout, _) = vit(&["--version"], "");
+    assert_eq!(
+        (code, stdout),
+        (0, format!("vit {}\n", env!("CARGO_PKG_VERSION")))
+    );
+    assert!(vit(&["build", "--help"], "").1.starts_with("Usage: vit"));
+
+    // Usage, syntax, semantic and I/O errors, and those of running the program.
+    let errors = [
+        (
+            vec!["build", "--bogus", "a.vit"],
+            "",
+            64,
+            "unknown option: --bogus.",
+        ),
+        (
+            vec!["build", "a.vit", "b.vit"],
+            "",
+            64,
+            "unexpected argument: b.vit.",
+        ),
+        (vec!["run", "--seed"], "", 64, "--seed expects a number."),
+        (
+            vec!["fmt", "--watch", "a.vit"],
+            "",
+            64,
+            "--watch only applies to build and run.",
+        ),
+        (vec!["build", "-"], "let a = ;", 65, "stdin: expected ["),
+        (
+            vec!["build", "-"],
+            "write b;",
+            66,
+            "stdin: undeclared variable: b.",
+        ),
+        (
+            vec!["build", "tests/missing.vit"],
+            "",
+            74,
+            "tests/missing.vit: ",
+        ),
+        (
+            vec!["run", "-"],
+            "let a = 0; let b = 1 / a;",
+            3,
+            "runtime error: ",
+        ),
+        // debug stops the same way, whatever the commands that ran the program.
+        (
+            vec!["debug", "tests/programs/division_by_zero.vit"],
+            "c\n",
+            3,
+            "runtime error: ",
+        ),
+        (
+            vec!["debug", "tests/programs/division_by_zero.vit"],
+            "n\nn\nn\n",
+            3,
+            "runtime error: ",
+        ),
+        (
+            vec!["debug", "examples/undeclared.vit"],
+            "",
+            66,
+            "examples/undeclared.vit: ",
+        ),
+    ];
+    for (args, stdin, code, message) in errors {
+        let (status, _, stderr) = vit(&args, stdin);
+        assert_eq!(status, code, "{args:?}");
+        assert!(stderr.starts_with(message), "{args:?}: {stderr}");
+    }
+}
+
+// Each program in tests/programs and examples is built, and run with the `.stdin` next to
+// it as input, if it has one. What comes out must match the files next to it: `.pcode` for
+// the code that build writes, `.stdout` for what the program writes and `.stderr` for the
+// errors of either. A file that's missing means nothing is expected there. Files the
+// programs import go in subdirectories, which aren't programs themselves.
+//
+// Running the tests with BLESS=1 writes the files with what comes out instead.
+#[test]
+fn programs() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let mut failures = vec![];
+    let mut count = 0;
+
+    let mut paths: Vec<_> = ["tests/programs", "examples"]
+        .into_iter()
+        .flat_map(|dir| std::fs::read_dir(dir).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "vit"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let file = path.display().to_string();
+        let stdin = std::fs::read_to_string(path.with_extension("stdin")).unwrap_or_default();
+
+        let (code, pcode, build_errors) = vit(&["build", &file, "-o", "-"], "");
+        let (stdout, stderr) = if code == 0 {
+            let (_, stdout, stderr) = vit(&["run", &file], &stdin);
+            (stdout, stderr)
+        } else {
+            (String::new(), build_errors)
+        };
+
+        for (extension, actual) in [("pcode", pcode), ("stdout", stdout), ("stderr", stderr)] {
+            let expected_path = path.with_extension(extension);
+            let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+            if bless && actual.is_empty() {
+                let _ = std::fs::remove_file(&expected_path);
+            } else if bless {
+                std::fs::write(&expected_path, actual).unwrap();
+            } else if actual != expected {
+                failures.push(format!(
+                    "{}:\n--- expected\n{expected}\n--- found\n{actual}",
+                    expected_path.display()
+                ));
+            }
+        }
+        count += 1;
+    }
+
+    assert!(count > 0);
+    assert!(
+        failures.is_empty(),
+        "{}\n\nRun with BLESS=1 to accept what was found.",
+        failures.join("\n\n")
+    );
+}
+
+// Outputs go next to the input, whatever the names of the directories it's in.
+#[test]
+fn run_built_code() {
+    let dir = std::env::temp_dir().join(format!("vit-built-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.vit");
+    std::fs::write(&file, "let a: int; read a; let b = 10 / a; write b;\n").unwrap();
+    let path = |name: &str| dir.join(name).display().to_string();
+
+    // build writes p-code to `main`, and asm turns it into bytecode in `main.vbc`.
+    assert_eq!(vit(&["build", &path("main.vit")], "").0, 0);
+    assert_eq!(
+        vit(&["asm", &path("main"), "-o", &path("main.vbc")], "").0,
+        0
+    );
+    std::fs::copy(path("main"), path("main.pcode")).unwrap();
+
+    for built in ["main", "main.pcode", "main.vbc"] {
+        assert_eq!(
+            vit(&["run", &path(built)], "4\n"),
+            (0, "2".to_string(), String::new()),
+            "{built}"
+        );
+        let (code, _, stderr) = vit(&["run", &path(built)], "0\n");
+        assert_eq!(
+            (code, stderr.as_str()),
+            (3, "runtime error: division by zero.\n")
+        );
+    }
+
+    // Bytecode that is cut short is reported, rather than compiled as vit.
+    let bytecode = std::fs::read(path("main.vbc")).unwrap();
+    std::fs::write(path("broken.vbc"), &bytecode[..bytecode.len() / 2]).unwrap();
+    let (code, _, stderr) = vit(&["run", &path("broken.vbc")], "");
+    assert_eq!(code, 65, "{stderr}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn output_paths() {
+    let config = |args: &[&str]| {
+        let args = ["vit"].iter().chain(args).map(|arg| arg.to_string());
+        vit::Config::build(args)
+    };
+    let target = |args: &[&str]| config(args).map(|config| config.target_name);
+
+    assert_eq!(target(&["my.vit.dir/a.vit"]).unwrap(), "my.vit.dir/a");
+    assert_eq!(target(&["--target", "c", "a.b/a.vit"]).unwrap(), "a.b/a.c");
+    assert_eq!(target(&["asm", "a.pcode"]).unwrap(), "a.vbc");
+    assert_eq!(target(&["disasm", "a.vbc"]).unwrap(), "a.pcode");
+    assert_eq!(target(&["build", "a.vit", "-o", "out"]).unwrap(), "out");
+    assert_eq!(target(&["build", "-"]).unwrap(), "-");
+    assert_eq!(
+        target(&["build", "program"]).unwrap_err(),
+        "program would be overwritten; name the output with -o."
+    );
+
+    let emit = config(&["build", "my.vit.dir/a.vit", "--emit=opt-pcode,ast=-"]).unwrap();
+    let paths: Vec<&str> = emit.emit.iter().map(|(_, path)| path.as_str()).collect();
+    assert_eq!(paths, ["my.vit.dir/a.opt.pcode", "-"]);
+}
+
+// Each program in tests/fixtures/ast parses into the statements in the JSON file next to
+// it. To update one, run `vit parse` on the program.
+#[test]
+#[cfg(feature = "serde")]
+fn ast_fixtures() {
+    use vit::ast::{Spanned, Statement};
+
+    let mut count = 0;
+    for entry in std::fs::read_dir("tests/fixtures/ast").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|extension| extension != "vit") {
+            continue;
+        }
+        let source = std::fs::read_to_string(&path).unwrap();
+        let json = std::fs::read_to_string(path.with_extension("json")).unwrap();
+        let expected: Vec<Spanned<Statement>> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(
+            Parser::new().parse(&source).unwrap(),
+            expected,
+            "{}",
+            path.display()
+        );
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+// A client that sends its messages to the language server all at once, and then reads
+// what the server sent back.
+#[cfg(feature = "serde")]
+mod lsp {
+    use serde_json::{json, Value};
+
+    #[derive(Default)]
+    pub struct Client {
+        input: Vec<u8>,
+        next_id: u64,
+    }
+
+    impl Client {
+        pub fn request(&mut self, method: &str, params: Value) -> u64 {
+            self.next_id += 1;
+            self.send(
+                json!({ "jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params }),
+            );
+            self.next_id
+        }
+
+        pub fn notify(&mut self, method: &str, params: Value) {
+            self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+        }
+
+        fn send(&mut self, message: Value) {
+            self.send_text(&message.to_string());
+        }
+
+        pub fn send_text(&mut self, body: &str) {
+            self.input
+                .extend(format!("Content-Length: {}\r\n\r\n{body}", body.len()).bytes());
+        }
+
+        pub fn run(self) -> Vec<Value> {
+            let mut output = vec![];
+            vit::lsp::serve(&mut self.input.as_slice(), &mut output).unwrap();
+
+            let mut messages = vec![];
+            let mut rest = output.as_slice();
+            while !rest.is_empty() {
+                let text = std::str::from_utf8(rest).unwrap();
+                let (header, body) = text.split_once("\r\n\r\n").unwrap();
+                let length: usize = header["Content-Length: ".len()..].parse().unwrap();
+                messages.push(serde_json::from_str(&body[..length]).unwrap());
+                rest = &body.as_bytes()[length..];
+            }
+            messages
+        }
+    }
+
+    pub fn response(messages: &[Value], id: u64) -> &Value {
+        messages.iter().find(|message| message["id"] == id).unwrap()
+    }
+
+    pub fn diagnostics(messages: &[Value]) -> Vec<&Value> {
+        messages
+            .iter()
+            .filter(|message| message["method"] == "textDocument/publishDiagnostics")
+            .map(|message| &message["params"]["diagnostics"])
+            .collect()
+    }
+
+    pub fn at(line: u64, character: u64) -> Value {
+        json!({
+            "textDocument": { "uri": "file:///tmp/main.vit" },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": true },
+        })
+    }
+
+    pub fn range(line: u64, start: u64, end: u64) -> Value {
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        })
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn language_server() {
+    use lsp::{at, range};
+    use serde_json::json;
+
+    let uri = "file:///tmp/main.vit";
+    let source = "let total = 0;
+let i = 1;
+loop {
+    let i = 'x';
+    total = total + 1;
+    if total > 3 { break; }
+}
+write totl;
+";
+    let mut client = lsp::Client::default();
+    let initialize = client.request("initialize", json!({ "capabilities": {} }));
+    client.notify("initialized", json!({}));
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": { "uri": uri, "languageId": "vit", "version": 1, "text": source } }),
+    );
+    let definition = client.request("textDocument/definition", at(4, 14));
+    let references = client.request("textDocument/references", at(0, 6));
+    let inner = client.request("textDocument/hover", at(3, 8));
+    let outer = client.request("textDocument/hover", at(1, 4));
+    let nothing = client.request("textDocument/hover", at(3, 2));
+    let completion = client.request("textDocument/completion", at(7, 0));
+    let unknown = client.request("textDocument/rename", at(0, 6));
+    for text in ["let a = ;", &source.replace("totl", "total")] {
+        client.notify(
+            "textDocument/didChange",
+            json!({ "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": text }] }),
+        );
+    }
+    let shutdown = client.request("shutdown", json!(null));
+    client.notify("exit", json!(null));
+    let messages = client.run();
+
+    let response = |id| &lsp::response(&messages, id)["result"];
+    assert_eq!(response(initialize)["capabilities"]["hoverProvider"], true);
+    let diagnostics = lsp::diagnostics(&messages);
+    assert_eq!(
+        diagnostics[0],
+        &json!([{
+            "range": range(7, 0, 11),
+            "severity": 1,
+            "source": "vit",
+            "message": "undeclared variable: totl.",
+        }])
+    );
+    assert_eq!(diagnostics[1][0]["range"], range(0, 8, 9));
+    assert!(diagnostics[1][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("expected ["));
+    assert_eq!(diagnostics[2..], [&json!([])]);
+
+    assert_eq!(
+        response(definition),
+        &json!({ "uri": uri, "range": range(0, 4, 9) })
+    );
+    let lines: Vec<_> = response(references)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| {
+            (
+                &location["range"]["start"]["line"],
+                &location["range"]["start"]["character"],
+            )
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (&json!(0), &json!(4)),
+            (&json!(4), &json!(4)),
+            (&json!(4), &json!(12)),
+            (&json!(5), &json!(7))
+        ]
+    );
+    assert_eq!(response(inner)["contents"]["value"], "i #2 str");
+    assert_eq!(response(inner)["range"], range(3, 8, 9));
+    assert_eq!(response(outer)["contents"]["value"], "i #1 int");
+    assert_eq!(response(nothing), &json!(null));
+    assert!(response(completion)
+        .as_array()
+        .unwrap()
+        .contains(&json!({ "label": "loop", "kind": 14 })));
+    assert_eq!(lsp::response(&messages, unknown)["error"]["code"], -32601);
+    assert_eq!(response(shutdown), &json!(null));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn language_server_invalid_message() {
+    use serde_json::json;
+
+    let mut client = lsp::Client::default();
+    client.send_text("{\"jsonrpc\": \"2.0\", \"id\": ");
+    let shutdown = client.request("shutdown", json!(null));
+    client.notify("exit", json!(null));
+    let messages = client.run();
+
+    assert_eq!(messages[0]["id"], json!(null));
+    assert_eq!(messages[0]["error"]["code"], -32700);
+    assert_eq!(lsp::response(&messages, shutdown)["result"], json!(null));
+}